@@ -1,30 +1,544 @@
-use super::StreamConfig;
+use crate::config_migration::migrate_config;
+use crate::stream::StreamConfig;
+use crate::streams_config::StreamsConfig;
+use schemars::gen::SchemaGenerator;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::{File, OpenOptions};
-use std::io::BufReader;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::{uuid, Uuid};
 
-pub fn load_config(config_file_path:String) -> Result<StreamConfig, Box<dyn Error>>{
-    let file = File::open(config_file_path)?;
-    let reader = BufReader::new(file);
-    let config:StreamConfig = serde_json::from_reader(reader)?;
-    Ok(config)
+/// The key of the files included by a configuration file.
+const INCLUDE_KEY: &str = "include";
+
+/// The namespace of the UUIDs derived from stream IDs.
+const STREAM_ID_NAMESPACE: Uuid = uuid!("6c0b8a57-5c2e-4f0e-9d1b-3f6f2a4b9e17");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The `ConfigFormat` enum represents the file formats a `StreamsConfig` can be loaded from and saved to.
+/// The format of a file is chosen by its extension: `.json`, `.toml`, `.yaml` or `.yml`.
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(config_file_path: &str) -> Result<ConfigFormat, String> {
+        let extension = Path::new(config_file_path).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => Ok(ConfigFormat::Json),
+            "toml" => Ok(ConfigFormat::Toml),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            _ => Err(format!("{config_file_path}: unsupported configuration file extension, expected .json, .toml, .yaml or .yml")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The `LoadedConfig` struct is a configuration loaded with `load_config` or `parse_config`.
+///
+/// - `config`: The configuration.
+/// - `files`: The files read, the configuration file first, then the files it includes, e.g. to reload the
+///   configuration when any of them changes.
+/// - `warnings`: A warning for each change made to migrate the files of earlier versions, prefixed with the file.
+pub struct LoadedConfig {
+    pub config: StreamsConfig,
+    pub files: Vec<PathBuf>,
+    pub warnings: Vec<String>,
+}
+
+/// Parses a configuration. Streams may be referred to by their `id` instead of their UUID, and streams with an `id`
+/// but no `uuid` are given a UUID derived from their `id`.
+///
+/// Environment variables are substituted and included files are merged in as described for `load_config`, included
+/// files being found relative to the current directory.
+pub fn parse_config(text: &str, format: ConfigFormat) -> Result<LoadedConfig, Box<dyn Error>>{
+    let mut origins: Vec<String> = Vec::new();
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut warnings: Vec<String> = Vec::new();
+    let value = parse_config_value(text, format, None, &mut Vec::new(), &mut origins, &mut files, &mut warnings)?;
+    Ok(LoadedConfig { config: finish_config(value, &origins)?, files, warnings })
 }
 
+/// Parses the text of a configuration file into a value, substitutes environment variables in its strings, and merges
+/// in the streams of the files it includes. The location each stream was defined at is appended to `origins`, in the order
+/// of the streams.
+///
+/// # Arguments
+/// * `file` - The file the text was read from, if any. Errors are reported against it and included files are found
+///   relative to it.
+/// * `including_files` - The files including this one, to detect files that include themselves.
+/// * `files` - The included files read are appended to it.
+/// * `warnings` - The migration warnings of this file and of the files it includes are appended to it.
+fn parse_config_value(text: &str, format: ConfigFormat, file: Option<&Path>, including_files: &mut Vec<PathBuf>, origins: &mut Vec<String>, files: &mut Vec<PathBuf>, warnings: &mut Vec<String>) -> Result<Value, String> {
+    let source = file.map(|file| file.display().to_string());
+    let locate = |line: Option<usize>| match (&source, line) {
+        (Some(source), Some(line)) => format!("{source}:{line}"),
+        (Some(source), None) => source.clone(),
+        (None, Some(line)) => format!("line {line}"),
+        (None, None) => String::from("configuration"),
+    };
+
+    let mut value: Value = match format {
+        ConfigFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+        ConfigFormat::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+        ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()).and_then(yaml_to_json),
+    }.map_err(|e| format!("{}: {e}", locate(None)))?;
+    substitute_value_variables(&mut value).map_err(|(reference, e)| format!("{}: {e}", locate(find_line(text, 0, &[&reference]))))?;
+
+    let migration_warnings = migrate_config(&mut value).map_err(|e| format!("{}: {e}", locate(find_line(text, 0, &["version"]))))?;
+    warnings.extend(migration_warnings.into_iter().map(|warning| format!("{}: {warning}", locate(None))));
+
+    let includes: Vec<Value> = match value.as_object_mut().and_then(|object| object.shift_remove(INCLUDE_KEY)) {
+        None => vec![],
+        Some(Value::Array(includes)) => includes,
+        Some(include) => vec![include],
+    };
+    let directory = file.and_then(Path::parent).unwrap_or(Path::new(""));
+    let mut streams: Vec<Value> = Vec::new();
+    let mut templates: Vec<Value> = Vec::new();
+
+    for include in includes {
+        let Some(include) = include.as_str() else {
+            return Err(format!("{}: {INCLUDE_KEY} must be a file or a list of files", locate(find_line(text, 0, &[INCLUDE_KEY]))));
+        };
+        let included_from = locate(find_line(text, 0, &[include]));
+        let include_path = directory.join(include);
+        let canonical_path = include_path.canonicalize().map_err(|e| format!("{included_from}: {include}: {e}"))?;
+        if including_files.contains(&canonical_path) {
+            return Err(format!("{included_from}: {include} includes itself"));
+        }
+
+        let format = ConfigFormat::from_path(include).map_err(|e| format!("{included_from}: {e}"))?;
+        let included_text = fs::read_to_string(&include_path).map_err(|e| format!("{included_from}: {include}: {e}"))?;
+        including_files.push(canonical_path);
+        files.push(include_path.clone());
+        let included = parse_config_value(&included_text, format, Some(&include_path), including_files, origins, files, warnings)
+            .map_err(|e| format!("{e}\n  included from {included_from}"))?;
+        including_files.pop();
+
+        // Only the streams and templates are merged, any other setting of an included file would be lost.
+        let included_keys = included.as_object().map(|object| object.keys().collect::<Vec<&String>>()).unwrap_or_default();
+        if let Some(key) = included_keys.into_iter().find(|key| !["version", "stream_configs", "templates"].contains(&key.as_str())) {
+            let key_location = match find_line(&included_text, 0, &[key]) {
+                Some(line) => format!("{}:{line}", include_path.display()),
+                None => include_path.display().to_string(),
+            };
+            return Err(format!("{key_location}: {key} is not supported in an included file, only stream_configs and templates are merged\n  included from {included_from}"));
+        }
+
+        if let Some(Value::Array(included_streams)) = included.get("stream_configs") {
+            streams.extend(included_streams.iter().cloned());
+        }
+        if let Some(Value::Array(included_templates)) = included.get("templates") {
+            templates.extend(included_templates.iter().cloned());
+        }
+    }
+
+    if let Some(own_streams) = value.get("stream_configs").and_then(Value::as_array) {
+        let mut line = 0;
+        for stream in own_streams {
+            let (field, key) = match (stream.get("id").and_then(Value::as_str), stream.get("name").and_then(Value::as_str)) {
+                (Some(id), _) => ("id", id),
+                (None, Some(name)) => ("name", name),
+                (None, None) => ("", ""),
+            };
+            let stream_line = if key.is_empty() { None } else { find_line(text, line, &[field, key]) };
+            line = stream_line.unwrap_or(line);
+            origins.push(locate(stream_line));
+            streams.push(stream.clone());
+        }
+    }
+    if let Some(object) = value.as_object_mut() {
+        object.insert(String::from("stream_configs"), Value::Array(streams));
+        if let Some(Value::Array(own_templates)) = object.shift_remove("templates") {
+            templates.extend(own_templates);
+        }
+        if !templates.is_empty() {
+            object.insert(String::from("templates"), Value::Array(templates));
+        }
+    }
+    Ok(value)
+}
+
+/// Resolves the stream references of a configuration value and deserialises it. Errors are reported against the
+/// location the stream was defined at, when it is known.
+fn finish_config(mut value: Value, origins: &[String]) -> Result<StreamsConfig, String> {
+    resolve_stream_references(&mut value, origins)?;
+
+    // Deserialise the streams one by one first, so that an error points to the stream it was found in.
+    let streams = value.get("stream_configs").and_then(Value::as_array).cloned().unwrap_or_default();
+    let errors: Vec<String> = streams.into_iter().enumerate()
+        .filter_map(|(index, stream)| serde_json::from_value::<StreamConfig>(stream).err().map(|e| (index, e)))
+        .map(|(index, e)| format!("{}Stream {}: {e}", location_prefix(origins, index), index + 1))
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+/// Substitutes environment variables, see `substitute_variables`, in every string of a configuration value. Keys and
+/// other values are left as they are.
+///
+/// # Returns
+/// The variable reference the first error was found at, to locate it in the text, and the error.
+fn substitute_value_variables(value: &mut Value) -> Result<(), (String, String)> {
+    match value {
+        Value::String(text) => *text = substitute_variables(text)?,
+        Value::Array(values) => values.iter_mut().try_for_each(substitute_value_variables)?,
+        Value::Object(object) => object.values_mut().try_for_each(substitute_value_variables)?,
+        _ => {},
+    }
+    Ok(())
+}
+
+/// Replaces `${NAME}` with the value of the environment variable `NAME`, and `${NAME:-default}` with the default
+/// when the variable is not set or empty. `$${` is replaced with a literal `${`.
+///
+/// # Returns
+/// The text with the variables substituted, or the variable reference the first error was found at and the error.
+fn substitute_variables(text: &str) -> Result<String, (String, String)> {
+    let mut substituted = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            substituted.push_str(&rest[..start - 1]);
+            substituted.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        substituted.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| (rest[start..].lines().next().unwrap_or_default().to_string(), String::from("unclosed ${")))? + start;
+        let reference = &rest[start..=end];
+        let (name, default) = match rest[start + 2..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&rest[start + 2..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err((reference.to_string(), format!("invalid environment variable name '{name}'")));
+        }
+        match (std::env::var(name).ok().filter(|value| !value.is_empty()), default) {
+            (Some(value), _) => substituted.push_str(&value),
+            (None, Some(default)) => substituted.push_str(default),
+            (None, None) => return Err((reference.to_string(), format!("environment variable '{name}' is not set and has no default"))),
+        }
+        rest = &rest[end + 1..];
+    }
+    substituted.push_str(rest);
+
+    Ok(substituted)
+}
 
-pub fn save_config(config: &StreamConfig, config_file_path:String) -> Result<(), Box<dyn Error>>{
-    let file = OpenOptions::new()
+/// Returns the number of the first line, from line `start` onwards, containing all the given texts.
+fn find_line(text: &str, start: usize, texts: &[&str]) -> Option<usize> {
+    text.lines().enumerate().skip(start.saturating_sub(1))
+        .find(|(_, line)| texts.iter().all(|text| line.contains(text)))
+        .map(|(index, _)| index + 1)
+}
+
+/// The location a stream was defined at, followed by a separator, or nothing when it is not known.
+fn location_prefix(origins: &[String], index: usize) -> String {
+    origins.get(index).map(|origin| format!("{origin}: ")).unwrap_or_default()
+}
+
+/// Converts a YAML value to a JSON value. Tagged values, e.g. `!File {config: ...}`, are converted to the maps serde
+/// uses for enum variants, e.g. `{"File": {"config": ...}}`.
+fn yaml_to_json(value: serde_yaml::Value) -> Result<Value, String> {
+    match value {
+        serde_yaml::Value::Tagged(tagged) => {
+            let variant = tagged.tag.to_string().trim_start_matches('!').to_string();
+            match yaml_to_json(tagged.value)? {
+                Value::Null => Ok(Value::String(variant)),
+                value => Ok(Value::Object([(variant, value)].into_iter().collect())),
+            }
+        },
+        serde_yaml::Value::Sequence(sequence) => sequence.into_iter().map(yaml_to_json).collect::<Result<Vec<Value>, String>>().map(Value::Array),
+        serde_yaml::Value::Mapping(mapping) => {
+            let mut object = serde_json::Map::new();
+            for (key, value) in mapping {
+                let key = match key {
+                    serde_yaml::Value::String(key) => key,
+                    serde_yaml::Value::Number(key) => key.to_string(),
+                    serde_yaml::Value::Bool(key) => key.to_string(),
+                    key => return Err(format!("Unsupported mapping key: {key:?}")),
+                };
+                object.insert(key, yaml_to_json(value)?);
+            }
+            Ok(Value::Object(object))
+        },
+        value => serde_json::to_value(value).map_err(|e| e.to_string()),
+    }
+}
+
+/// Derives the UUID of a stream from its ID, so that the stream keeps its UUID every time the configuration is loaded.
+pub fn stream_uuid_from_id(id: &str) -> Uuid {
+    Uuid::new_v5(&STREAM_ID_NAMESPACE, id.as_bytes())
+}
+
+/// Assigns the UUIDs of streams identified by `id` only, and replaces the references to stream IDs with UUIDs.
+/// Every unknown reference is reported, with the closest stream ID when there is a likely match.
+fn resolve_stream_references(value: &mut Value, origins: &[String]) -> Result<(), String> {
+    let mut errors: Vec<String> = Vec::new();
+    let mut ids: HashMap<String, String> = HashMap::new();
+    let mut uuids: HashSet<String> = HashSet::new();
+
+    if let Some(streams) = value.get_mut("stream_configs").and_then(Value::as_array_mut) {
+        for (index, stream) in streams.iter_mut().enumerate() {
+            let Some(stream) = stream.as_object_mut() else { continue };
+            let id = stream.get("id").and_then(Value::as_str).map(String::from);
+            let name = stream.get("name").and_then(Value::as_str).unwrap_or_default().to_string();
+
+            if !stream.contains_key("uuid") {
+                match &id {
+                    Some(id) => { stream.insert(String::from("uuid"), Value::String(stream_uuid_from_id(id).to_string())); },
+                    None => errors.push(format!("{}Stream {} '{name}' has neither an id nor a uuid", location_prefix(origins, index), index + 1)),
+                }
+            }
+            let uuid = stream.get("uuid").and_then(Value::as_str).unwrap_or_default().to_string();
+            if let Some(id) = id {
+                if ids.insert(id.clone(), uuid.clone()).is_some() {
+                    errors.push(format!("{}Stream id '{id}' is used by more than one stream", location_prefix(origins, index)));
+                }
+            }
+            uuids.insert(uuid.to_ascii_lowercase());
+        }
+
+        for (index, stream) in streams.iter_mut().enumerate() {
+            let label = stream.get("id").or(stream.get("name")).and_then(Value::as_str).unwrap_or_default().to_string();
+            let context = format!("{}Stream '{label}'", location_prefix(origins, index));
+            resolve_stream_links(stream, &context, &ids, &uuids, &mut errors);
+        }
+    }
+
+    // The streams of templates are given a UUID as other streams are, but cannot be referred to.
+    let templates = value.get_mut("templates").and_then(Value::as_array_mut);
+    for (index, stream) in templates.into_iter().flatten().filter_map(|template| template.get_mut("stream")).enumerate() {
+        let Some(id) = stream.get("id").and_then(Value::as_str).map(String::from) else {
+            let name = stream.get("name").and_then(Value::as_str).unwrap_or_default();
+            errors.push(format!("Template {} '{name}' has no id", index + 1));
+            continue;
+        };
+        if let Some(stream) = stream.as_object_mut().filter(|stream| !stream.contains_key("uuid")) {
+            stream.insert(String::from("uuid"), Value::String(stream_uuid_from_id(&id).to_string()));
+        }
+        resolve_stream_links(stream, &format!("Template '{id}'"), &ids, &uuids, &mut errors);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+/// Resolves the references of a stream to its output streams and to the streams targeted by its rules.
+fn resolve_stream_links(stream: &mut Value, context: &str, ids: &HashMap<String, String>, uuids: &HashSet<String>, errors: &mut Vec<String>) {
+    if let Some(outputs) = stream.get_mut("output_streams").and_then(Value::as_array_mut) {
+        for output in outputs.iter_mut() {
+            resolve_reference(output, &format!("{context} output"), ids, uuids, errors);
+        }
+    }
+
+    let rules = stream.pointer_mut("/type_config/RuleEngine/config/rules").and_then(Value::as_array_mut);
+    for rule in rules.into_iter().flatten() {
+        let rule_name = rule.get("name").and_then(Value::as_str).unwrap_or_default().to_string();
+        for action in ["/action/Inject/stream", "/action/Write/stream", "/action/Control/stream"] {
+            if let Some(target) = rule.pointer_mut(action) {
+                resolve_reference(target, &format!("{context} rule '{rule_name}' target"), ids, uuids, errors);
+            }
+        }
+    }
+}
+
+fn remove_nulls(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            object.retain(|_, value| !value.is_null());
+            object.values_mut().for_each(remove_nulls);
+        },
+        Value::Array(array) => array.iter_mut().for_each(remove_nulls),
+        _ => {},
+    }
+}
+
+/// The reverse of `resolve_stream_references`.
+fn use_stream_ids(value: &mut Value) {
+    let mut ids: HashMap<String, String> = HashMap::new();
+    let drop_derived_uuid = |stream: &mut Value| {
+        let stream = stream.as_object_mut()?;
+        let (Some(id), Some(uuid)) = (stream.get("id").and_then(Value::as_str), stream.get("uuid").and_then(Value::as_str)) else { return None };
+        let id_and_uuid = (id.to_string(), uuid.to_string());
+        if stream_uuid_from_id(id).to_string() == uuid {
+            stream.shift_remove("uuid");
+        }
+        Some(id_and_uuid)
+    };
+
+    let streams = value.get_mut("stream_configs").and_then(Value::as_array_mut);
+    for stream in streams.into_iter().flatten() {
+        if let Some((id, uuid)) = drop_derived_uuid(stream) {
+            ids.insert(uuid, id);
+        }
+    }
+    let templates = value.get_mut("templates").and_then(Value::as_array_mut);
+    for stream in templates.into_iter().flatten().filter_map(|template| template.get_mut("stream")) {
+        drop_derived_uuid(stream);
+    }
+
+    let use_id = |reference: &mut Value| {
+        if let Some(id) = reference.as_str().and_then(|uuid| ids.get(uuid)) {
+            *reference = Value::String(id.clone());
+        }
+    };
+    let mut all_streams: Vec<&mut Value> = Vec::new();
+    for (key, entries) in value.as_object_mut().into_iter().flatten() {
+        match key.as_str() {
+            "stream_configs" => all_streams.extend(entries.as_array_mut().into_iter().flatten()),
+            "templates" => all_streams.extend(entries.as_array_mut().into_iter().flatten().filter_map(|template| template.get_mut("stream"))),
+            _ => {},
+        }
+    }
+    for stream in all_streams {
+        for output in stream.get_mut("output_streams").and_then(Value::as_array_mut).into_iter().flatten() {
+            use_id(output);
+        }
+        let rules = stream.pointer_mut("/type_config/RuleEngine/config/rules").and_then(Value::as_array_mut);
+        for rule in rules.into_iter().flatten() {
+            for action in ["/action/Inject/stream", "/action/Write/stream", "/action/Control/stream"] {
+                if let Some(target) = rule.pointer_mut(action) {
+                    use_id(target);
+                }
+            }
+        }
+    }
+}
+
+fn resolve_reference(reference: &mut Value, context: &str, ids: &HashMap<String, String>, uuids: &HashSet<String>, errors: &mut Vec<String>) {
+    let Some(text) = reference.as_str() else { return };
+
+    if let Some(uuid) = ids.get(text) {
+        *reference = Value::String(uuid.clone());
+    } else if Uuid::parse_str(text).is_ok() {
+        if !uuids.contains(&text.to_ascii_lowercase()) {
+            errors.push(format!("{context}: unknown stream uuid {text}"));
+        }
+    } else {
+        let suggestion = closest_match(text, ids.keys().map(String::as_str))
+            .map(|id| format!(", did you mean '{id}'?"))
+            .unwrap_or_default();
+        errors.push(format!("{context}: unknown stream '{text}'{suggestion}"));
+    }
+}
+
+/// Returns the candidate closest to the text, if it is close enough to be a likely typo.
+fn closest_match<'a>(text: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (text.chars().count() / 3).max(2);
+    candidates
+        .map(|candidate| (edit_distance(&text.to_lowercase(), &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current: Vec<usize> = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Formats a configuration. Streams with an `id` are referred to by their `id`, and their `uuid` is left out when it
+/// is the one derived from their `id`.
+pub fn format_config(config: &StreamsConfig, format: ConfigFormat) -> Result<String, Box<dyn Error>>{
+    let mut value = serde_json::to_value(config)?;
+    use_stream_ids(&mut value);
+    let text = match format {
+        ConfigFormat::Json => serde_json::to_string_pretty(&value)? + "\n",
+        ConfigFormat::Toml => {
+            // TOML has no null, fields left out are read back as `None`.
+            remove_nulls(&mut value);
+            toml::to_string_pretty(&value)?
+        },
+        ConfigFormat::Yaml => serde_yaml::to_string(&value)?,
+    };
+    Ok(text)
+}
+
+/// Loads a configuration file, in the format given by its extension.
+///
+/// Configuration files of earlier versions are migrated to the current version, with a warning for each change.
+///
+/// String values may be taken from environment variables with `${NAME}`, or `${NAME:-default}` to fall back on a
+/// default. Variables are only substituted within strings, so references in comments are left alone.
+/// The streams of other configuration files, e.g. sinks shared by several configurations, are merged in with
+/// `include`, giving a file or a list of files relative to the including file. Included streams and templates come
+/// before those of the including file, and included streams may be referred to by their `id`.
+///
+/// Errors give the file, and the line when known, they were found at.
+pub fn load_config(config_file_path:String) -> Result<LoadedConfig, Box<dyn Error>>{
+    let format = ConfigFormat::from_path(&config_file_path)?;
+    let path = Path::new(&config_file_path);
+    let text = fs::read_to_string(path).map_err(|e| format!("{config_file_path}: {e}"))?;
+    let mut including_files: Vec<PathBuf> = path.canonicalize().into_iter().collect();
+    let mut origins: Vec<String> = Vec::new();
+    let mut files: Vec<PathBuf> = vec![path.to_path_buf()];
+    let mut warnings: Vec<String> = Vec::new();
+    let value = parse_config_value(&text, format, Some(path), &mut including_files, &mut origins, &mut files, &mut warnings)?;
+    Ok(LoadedConfig { config: finish_config(value, &origins)?, files, warnings })
+}
+
+
+pub fn save_config(config: &StreamsConfig, config_file_path:String) -> Result<(), Box<dyn Error>>{
+    let format = ConfigFormat::from_path(&config_file_path)?;
+    let text = format_config(config, format)?;
+    let mut file = OpenOptions::new()
         .create(true)
         .write(true)
         .truncate(true)
         .open(config_file_path)?;
-    serde_json::to_writer(&file, &config)?;
+    file.write_all(text.as_bytes())?;
     Ok(())
 }
 
-/*
+/// Returns the JSON Schema of configuration files, for editors and tools to validate configuration files against.
+///
+/// Streams may be referred to by their `id` or their UUID, and the `uuid` of streams with an `id` may be left out.
+pub fn config_schema() -> String {
+    let mut generator = SchemaGenerator::default();
+    let mut schema = generator.clone().into_root_schema_for::<StreamsConfig>();
+    let include = generator.subschema_for::<Vec<String>>();
+    schema.schema.object().properties.insert(String::from(INCLUDE_KEY), include);
+    serde_json::to_string_pretty(&schema).expect("Schemas are always serialisable") + "\n"
+}
+
+/// Converts a configuration file to another format, chosen by the extension of the output file.
+///
+/// # Returns
+/// The warnings of the migration of the input file, see `LoadedConfig`.
+pub fn convert_config(input_file_path:String, output_file_path:String) -> Result<Vec<String>, Box<dyn Error>>{
+    let loaded = load_config(input_file_path)?;
+    save_config(&loaded.config, output_file_path)?;
+    Ok(loaded.warnings)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::stream::{DataType, Direction, StreamTypeConfig};
+    use crate::stream::{file_stream::FileStreamConfig, StreamConfig, StreamTypeConfig};
     use super::*;
     use tempfile::Builder;
     use uuid::Uuid;
@@ -34,15 +548,12 @@ mod tests {
      
         let uuid = Uuid::new_v4();
         let name = String::from("Test Stream");
-        let direction = Direction::Output;
-        let data_type = DataType::Binary;
         let output_streams = vec![Uuid::new_v4(), Uuid::new_v4()];
         let input_filter = String::from("test_filter");
-        let config = StreamTypeConfig::None;
+        let config = StreamTypeConfig::File { config: FileStreamConfig::new(String::from("log.txt")) };
 
         let temp_dir = Builder::new()
         .rand_bytes(5)
-        .keep(true)
         .tempdir().unwrap();
 
         let write_file_path = temp_dir.path().join("test_json_file.json");
@@ -50,19 +561,232 @@ mod tests {
         let written_stream_config = StreamConfig::new(
             uuid,
             name.clone(),
-            direction,
-            data_type,
             output_streams.clone(),
             input_filter.clone(),
             config,
+            String::from("\n"),
         );
+        let mut written_config = StreamsConfig::new();
+        written_config.stream_configs.push(written_stream_config);
+        for output_stream in output_streams {
+            written_config.stream_configs.push(StreamConfig::new(output_stream, String::from("Output"), vec![], String::new(), StreamTypeConfig::None, String::from("\n")));
+        }
         println!("Using file {}", String::from(write_file_path.to_str().unwrap()));
 
-        save_config(&written_stream_config, String::from(write_file_path.to_str().unwrap()));
+        save_config(&written_config, String::from(write_file_path.to_str().unwrap())).unwrap();
+
+        let read_config = load_config(String::from(write_file_path.to_str().unwrap())).unwrap().config;
+
+        assert_eq!(written_config, read_config);
+    }
+
+    #[test]
+    fn test_formats_round_trip(){
+        let mut stream_config = StreamConfig {
+            name: String::from("Device"),
+            type_config: StreamTypeConfig::RingBuffer { config: crate::stream::ring_buffer_stream::RingBufferStreamConfig::new() },
+            ..StreamConfig::default()
+        };
+        stream_config.add_stage(crate::stage::StageConfig::Sequence { config: crate::stage::sequence_stage::SequenceStageConfig::new() });
+        let mut config = StreamsConfig::new();
+        config.stream_configs.push(stream_config);
+        config.stream_configs.push(StreamConfig::default());
+
+        let temp_dir = Builder::new().tempdir().unwrap();
+        for file_name in ["config.toml", "config.yaml", "config.yml", "config.json"] {
+            let file_path = String::from(temp_dir.path().join(file_name).to_str().unwrap());
+            save_config(&config, file_path.clone()).unwrap();
+            assert_eq!(load_config(file_path).unwrap().config, config, "{file_name}");
+        }
+    }
+
+    #[test]
+    fn test_convert_json_to_yaml(){
+        let temp_dir = Builder::new().tempdir().unwrap();
+        let json_path = String::from(temp_dir.path().join("config.json").to_str().unwrap());
+        let yaml_path = String::from(temp_dir.path().join("config.yaml").to_str().unwrap());
+        let mut config = StreamsConfig::new();
+        config.stream_configs.push(StreamConfig::default());
+
+        save_config(&config, json_path.clone()).unwrap();
+        convert_config(json_path, yaml_path.clone()).unwrap();
+        assert!(std::fs::read_to_string(&yaml_path).unwrap().starts_with(&format!("version: {}\nstream_configs:", crate::streams_config::CONFIG_VERSION)));
+        assert_eq!(load_config(yaml_path).unwrap().config, config);
+    }
+
+    #[test]
+    fn test_streams_referenced_by_id(){
+        let text = r#"
+stream_configs:
+  - id: device
+    name: Device
+    input_filter: ""
+    message_delimiter: "\n"
+    output_streams: [log]
+    type_config: None
+  - id: log
+    name: Log
+    input_filter: ""
+    message_delimiter: "\n"
+    output_streams: []
+    type_config:
+      File:
+        config:
+          file_path: log.txt
+"#;
+        let config = parse_config(text, ConfigFormat::Yaml).unwrap().config;
+        let log_uuid = stream_uuid_from_id("log");
+        assert_eq!(config.stream_configs[0].uuid, stream_uuid_from_id("device"));
+        assert_eq!(config.stream_configs[1].uuid, log_uuid);
+        assert_eq!(config.stream_configs[0].output_streams, vec![log_uuid]);
+
+        let saved = format_config(&config, ConfigFormat::Json).unwrap();
+        assert!(!saved.contains("uuid"));
+        assert!(saved.contains(r#""output_streams": [
+        "log"
+      ]"#));
+        assert_eq!(parse_config(&saved, ConfigFormat::Json).unwrap().config, config);
+    }
+
+    #[test]
+    fn test_unknown_references_reported_with_suggestions(){
+        let text = r#"{"stream_configs": [
+            {"id": "uart-console", "name": "Console", "input_filter": "", "message_delimiter": "\n", "type_config": "None",
+             "output_streams": ["uart-consle", "nothing-like-it", "6c0b8a57-0000-4f0e-9d1b-3f6f2a4b9e17"]},
+            {"name": "No id", "input_filter": "", "message_delimiter": "\n", "type_config": "None", "output_streams": []}
+        ]}"#;
+        let error = parse_config(text, ConfigFormat::Json).unwrap_err().to_string();
+        let errors: Vec<&str> = error.lines().collect();
+        assert_eq!(errors, vec![
+            "line 4: Stream 2 'No id' has neither an id nor a uuid",
+            "line 2: Stream 'uart-console' output: unknown stream 'uart-consle', did you mean 'uart-console'?",
+            "line 2: Stream 'uart-console' output: unknown stream 'nothing-like-it'",
+            "line 2: Stream 'uart-console' output: unknown stream uuid 6c0b8a57-0000-4f0e-9d1b-3f6f2a4b9e17",
+        ]);
+    }
+
+    #[test]
+    fn test_yaml_tags_accepted(){
+        let text = "stream_configs:\n  - id: log\n    name: Log\n    input_filter: ''\n    message_delimiter: ''\n    output_streams: []\n    type_config: !File\n      config:\n        file_path: log.txt\n    stages:\n      - !Dedup\n        config: {collapse_repeats: true, repeat_summary_period_ms: 10}\n  - {id: none, name: None, input_filter: '', message_delimiter: '', output_streams: [], type_config: !None }\n";
+        let config = parse_config(text, ConfigFormat::Yaml).unwrap().config;
+        assert_eq!(config.stream_configs[0].type_config.to_string(), "File");
+        assert_eq!(config.stream_configs[0].stages.len(), 1);
+        assert_eq!(config.stream_configs[1].type_config, StreamTypeConfig::None);
+    }
+
+    #[test]
+    fn test_environment_variables_substituted(){
+        std::env::set_var("LOG_FLUX_TEST_PORT", "/dev/ttyUSB3");
+        std::env::remove_var("LOG_FLUX_TEST_UNSET");
+        let text = "port = ${LOG_FLUX_TEST_PORT}, ip = ${LOG_FLUX_TEST_UNSET:-127.0.0.1}, literal = $${LOG_FLUX_TEST_PORT}";
+        assert_eq!(substitute_variables(text).unwrap(), "port = /dev/ttyUSB3, ip = 127.0.0.1, literal = ${LOG_FLUX_TEST_PORT}");
+
+        let error = parse_config("{\n  \"stream_configs\": [],\n  \"x\": \"${LOG_FLUX_TEST_UNSET}\"\n}", ConfigFormat::Json).unwrap_err();
+        assert_eq!(error.to_string(), "line 3: environment variable 'LOG_FLUX_TEST_UNSET' is not set and has no default");
+
+        // Only strings are substituted, so quotes in a value stay within the string and comments are ignored.
+        std::env::set_var("LOG_FLUX_TEST_QUOTED", "say \"hi\"");
+        let text = "# Set ${LOG_FLUX_TEST_UNSET} to change the path\nstream_configs:\n  - id: log\n    name: ${LOG_FLUX_TEST_QUOTED}\n    input_filter: ''\n    message_delimiter: ''\n    output_streams: []\n    type_config: !File\n      config:\n        file_path: ${LOG_FLUX_TEST_UNSET:-log.txt}\n";
+        let config = parse_config(text, ConfigFormat::Yaml).unwrap().config;
+        assert_eq!(config.stream_configs[0].name, "say \"hi\"");
+        let StreamTypeConfig::File { config: file_config } = &config.stream_configs[0].type_config else { panic!("not a file stream") };
+        assert_eq!(file_config.file_path, "log.txt");
+    }
+
+    #[test]
+    fn test_included_streams_merged(){
+        let temp_dir = Builder::new().tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("sinks.yaml"), "stream_configs:\n  - id: log\n    name: Log\n    input_filter: ''\n    message_delimiter: ''\n    output_streams: []\n    type_config: !File\n      config:\n        file_path: log.txt\n").unwrap();
+        let main_path = temp_dir.path().join("bench.json");
+        std::fs::write(&main_path, r#"{
+  "include": ["sinks.yaml"],
+  "stream_configs": [
+    {"id": "device", "name": "Device", "input_filter": "", "message_delimiter": "", "type_config": "None", "output_streams": ["log"]}
+  ]
+}"#).unwrap();
+
+        let loaded = load_config(String::from(main_path.to_str().unwrap())).unwrap();
+        let ids: Vec<Option<String>> = loaded.config.stream_configs.iter().map(|stream| stream.id.clone()).collect();
+        assert_eq!(ids, vec![Some(String::from("log")), Some(String::from("device"))]);
+        assert_eq!(loaded.config.stream_configs[1].output_streams, vec![stream_uuid_from_id("log")]);
+        assert_eq!(loaded.files, vec![main_path.clone(), temp_dir.path().join("sinks.yaml")]);
+
+        std::fs::write(temp_dir.path().join("sinks.yaml"), "stream_configs: []\nsettings:\n  verbose: true\n").unwrap();
+        let error = load_config(String::from(main_path.to_str().unwrap())).unwrap_err().to_string();
+        assert_eq!(error, format!("{}:2: settings is not supported in an included file, only stream_configs and templates are merged\n  included from {}:2",
+            temp_dir.path().join("sinks.yaml").display(), main_path.display()));
+    }
 
-        let read_config = load_config(String::from(write_file_path.to_str().unwrap())).unwrap();
+    #[test]
+    fn test_errors_point_to_original_file_and_line(){
+        let temp_dir = Builder::new().tempdir().unwrap();
+        let sinks_path = temp_dir.path().join("sinks.yaml");
+        std::fs::write(&sinks_path, "stream_configs:\n  - id: log\n    name: Log\n    input_filter: ''\n    message_delimiter: ''\n    output_streams: [archive]\n    type_config: None\n").unwrap();
+        let main_path = temp_dir.path().join("bench.yaml");
+        std::fs::write(&main_path, "include:\n  - sinks.yaml\nstream_configs:\n  - id: device\n    name: Device\n    type_config: None\n").unwrap();
+
+        let error = load_config(String::from(main_path.to_str().unwrap())).unwrap_err().to_string();
+        let errors: Vec<&str> = error.lines().collect();
+        assert_eq!(errors, vec![format!("{}:2: Stream 'log' output: unknown stream 'archive'", sinks_path.display())]);
+
+        std::fs::write(&sinks_path, "include: bench.yaml\n").unwrap();
+        let error = load_config(String::from(main_path.to_str().unwrap())).unwrap_err().to_string();
+        assert_eq!(error, format!("{}:1: bench.yaml includes itself\n  included from {}:2", sinks_path.display(), main_path.display()));
+
+        std::fs::write(&sinks_path, "stream_configs: []\n").unwrap();
+        let error = load_config(String::from(main_path.to_str().unwrap())).unwrap_err().to_string();
+        assert!(error.starts_with(&format!("{}:4: Stream 1: missing field", main_path.display())), "{error}");
+    }
 
-        assert_eq!(written_stream_config, read_config);
+    #[test]
+    fn test_old_version_migrated_on_load(){
+        let text = r#"{"stream_configs": [
+            {"id": "udp", "name": "UDP Writer A", "input_filter": "", "message_delimiter": "\n", "output_streams": [], "direction": "BiDirectional",
+             "type_config": {"Udp": {"config": {"output_ip_address": "localhost", "output_port": 65000, "output_enabled": true}}}}
+        ]}"#;
+        let loaded = parse_config(text, ConfigFormat::Json).unwrap();
+        assert_eq!(loaded.warnings, vec![
+            String::from("configuration: Stream 'udp': 'direction' was removed, streams send messages to their 'output_streams'"),
+            String::from("configuration: Stream 'udp': 'output_enabled' and 'input_enabled' were replaced by 'direction': UdpOutput"),
+        ]);
+        let config = loaded.config;
+        assert_eq!(config.version, crate::streams_config::CONFIG_VERSION);
+        let StreamTypeConfig::Udp { config: udp_config } = &config.stream_configs[0].type_config else { panic!("not a UDP stream") };
+        assert_eq!(udp_config.direction, crate::stream::udp_stream::UdpDirection::UdpOutput);
+    }
+
+    #[test]
+    fn test_schema_accepts_ids(){
+        let schema: Value = serde_json::from_str(&config_schema()).unwrap();
+        assert_eq!(schema.pointer("/properties/include/type"), Some(&Value::from("array")));
+        assert_eq!(schema.pointer("/properties/stream_configs/type"), Some(&Value::from("array")));
+        let required = schema.pointer("/definitions/StreamConfig/required").and_then(Value::as_array).unwrap();
+        assert!(required.contains(&Value::from("name")));
+        assert!(!required.contains(&Value::from("uuid")));
     }
 
-}*/
\ No newline at end of file
+    #[test]
+    fn test_templates_refer_to_streams_by_id(){
+        let text = r#"
+stream_configs:
+  - {id: log, name: Log, input_filter: '', message_delimiter: '', output_streams: [], type_config: None}
+templates:
+  - device_glob: /dev/ttyUSB*
+    stream: {id: uart, name: UART, input_filter: '', message_delimiter: '', output_streams: [log], type_config: None}
+"#;
+        let config = parse_config(text, ConfigFormat::Yaml).unwrap().config;
+        assert_eq!(config.templates[0].stream.uuid, stream_uuid_from_id("uart"));
+        assert_eq!(config.templates[0].stream.output_streams, vec![stream_uuid_from_id("log")]);
+
+        let saved = format_config(&config, ConfigFormat::Json).unwrap();
+        assert!(!saved.contains("uuid"));
+        assert_eq!(parse_config(&saved, ConfigFormat::Json).unwrap().config, config);
+    }
+
+    #[test]
+    fn test_unknown_extension_rejected(){
+        assert!(ConfigFormat::from_path("config.ini").is_err());
+        assert_eq!(ConfigFormat::from_path("dir.v2/config.YML").unwrap(), ConfigFormat::Yaml);
+    }
+
+}
@@ -0,0 +1,169 @@
+use crate::streams_config::CONFIG_VERSION;
+use serde_json::Value;
+
+/// A migration upgrading a configuration value by one version, adding a warning for each change made.
+type Migration = fn(&mut Value, &mut Vec<String>);
+
+/// The migrations, in order. The migration at index `n` upgrades version `n + 1` to version `n + 2`.
+const MIGRATIONS: [Migration; (CONFIG_VERSION - 1) as usize] = [
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+];
+
+/// Upgrades a configuration value, as read from a configuration file, to the current version of the schema.
+///
+/// Configurations without a `version` are version 1.
+///
+/// # Returns
+/// A warning for each change made, so that the configuration file can be updated, or an error message if the
+/// configuration is of a later version than this library supports.
+pub fn migrate_config(value: &mut Value) -> Result<Vec<String>, String> {
+    let Some(config) = value.as_object_mut() else {
+        return Ok(vec![]);
+    };
+    let version = match config.get("version") {
+        None => 1,
+        Some(version) => version.as_u64().filter(|version| *version >= 1).ok_or(format!("Invalid version {version}"))?,
+    };
+    if version > CONFIG_VERSION as u64 {
+        return Err(format!("Version {version} is not supported, the latest supported version is {CONFIG_VERSION}"));
+    }
+
+    let mut warnings: Vec<String> = Vec::new();
+    for migration in MIGRATIONS[(version - 1) as usize..].iter() {
+        migration(value, &mut warnings);
+    }
+    if let Some(config) = value.as_object_mut() {
+        config.insert(String::from("version"), Value::from(CONFIG_VERSION));
+    }
+    Ok(warnings)
+}
+
+/// Calls `migrate` with the label and the object of each stream.
+fn for_each_stream(value: &mut Value, mut migrate: impl FnMut(&str, &mut Value)) {
+    let streams = value.get_mut("stream_configs").and_then(Value::as_array_mut);
+    for stream in streams.into_iter().flatten().filter(|stream| stream.is_object()) {
+        let label = stream.get("id").or(stream.get("name")).and_then(Value::as_str).unwrap_or_default().to_string();
+        migrate(&label, stream);
+    }
+}
+
+/// Version 2 removed the `direction` of streams, which only send messages to their `output_streams`, and replaced the
+/// `output_enabled` and `input_enabled` flags of UDP streams with their `direction`.
+fn migrate_v1_to_v2(value: &mut Value, warnings: &mut Vec<String>) {
+    for_each_stream(value, |label, stream| {
+        if stream.as_object_mut().and_then(|stream| stream.shift_remove("direction")).is_some() {
+            warnings.push(format!("Stream '{label}': 'direction' was removed, streams send messages to their 'output_streams'"));
+        }
+
+        let Some(udp) = stream.pointer_mut("/type_config/Udp/config").and_then(Value::as_object_mut) else {
+            return;
+        };
+        let output_enabled = udp.shift_remove("output_enabled").map(|enabled| enabled.as_bool().unwrap_or(false));
+        let input_enabled = udp.shift_remove("input_enabled").map(|enabled| enabled.as_bool().unwrap_or(false));
+        if output_enabled.is_none() && input_enabled.is_none() {
+            return;
+        }
+
+        let direction = if input_enabled == Some(true) && output_enabled != Some(true) { "UdpInput" } else { "UdpOutput" };
+        if input_enabled == Some(true) && output_enabled == Some(true) {
+            warnings.push(format!("Stream '{label}': UDP streams are either input or output, input was disabled, add a second stream with 'direction' UdpInput to receive"));
+        }
+        if !udp.contains_key("direction") {
+            udp.insert(String::from("direction"), Value::from(direction));
+            warnings.push(format!("Stream '{label}': 'output_enabled' and 'input_enabled' were replaced by 'direction': {direction}"));
+        } else {
+            warnings.push(format!("Stream '{label}': 'output_enabled' and 'input_enabled' were removed, 'direction' is used instead"));
+        }
+        for (field, default) in [("output_ip_address", Value::from("")), ("output_port", Value::from(0)), ("input_port", Value::from(0))] {
+            udp.entry(field).or_insert(default);
+        }
+    });
+}
+
+/// Version 3 removed the `start_bits` of serial streams, which are always 1, and the `Etc` flow control. The `None` flow
+/// control, written `{"None": []}` by earlier versions, became a plain `None`.
+fn migrate_v2_to_v3(value: &mut Value, warnings: &mut Vec<String>) {
+    for_each_stream(value, |label, stream| {
+        let Some(serial) = stream.pointer_mut("/type_config/Serial/config").and_then(Value::as_object_mut) else {
+            return;
+        };
+        if let Some(start_bits) = serial.shift_remove("start_bits") {
+            if start_bits != 1 {
+                warnings.push(format!("Stream '{label}': 'start_bits' {start_bits} was removed, serial ports always use 1 start bit"));
+            }
+        }
+        if serial.get("flow_control").and_then(Value::as_str) == Some("Etc") {
+            serial.insert(String::from("flow_control"), Value::from("None"));
+            warnings.push(format!("Stream '{label}': 'flow_control' Etc was replaced by None, use XonXoff or RtsCts for flow control"));
+        }
+        if serial.get("flow_control").and_then(Value::as_object).is_some_and(|flow_control| flow_control.contains_key("None")) {
+            serial.insert(String::from("flow_control"), Value::from("None"));
+            warnings.push(format!("Stream '{label}': 'flow_control' {{\"None\": []}} was replaced by \"None\""));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_version_1_migrated() {
+        let mut value = json!({"stream_configs": [
+            {"name": "UDP Writer A", "direction": "BiDirectional", "type_config": {"Udp": {"config": {"output_ip_address": "localhost", "output_port": 65000, "output_enabled": true}}}},
+            {"name": "UDP Reader", "type_config": {"Udp": {"config": {"input_port": 65001, "input_enabled": true, "output_enabled": false}}}},
+        ]});
+
+        let warnings = migrate_config(&mut value).unwrap();
+        assert_eq!(warnings, vec![
+            "Stream 'UDP Writer A': 'direction' was removed, streams send messages to their 'output_streams'",
+            "Stream 'UDP Writer A': 'output_enabled' and 'input_enabled' were replaced by 'direction': UdpOutput",
+            "Stream 'UDP Reader': 'output_enabled' and 'input_enabled' were replaced by 'direction': UdpInput",
+        ]);
+        assert_eq!(value, json!({"stream_configs": [
+            {"name": "UDP Writer A", "type_config": {"Udp": {"config": {"output_ip_address": "localhost", "output_port": 65000, "direction": "UdpOutput", "input_port": 0}}}},
+            {"name": "UDP Reader", "type_config": {"Udp": {"config": {"input_port": 65001, "direction": "UdpInput", "output_ip_address": "", "output_port": 0}}}},
+        ], "version": CONFIG_VERSION}));
+    }
+
+    #[test]
+    fn test_version_2_serial_settings_migrated() {
+        let mut value = json!({"version": 2, "stream_configs": [
+            {"name": "UART", "type_config": {"Serial": {"config": {"baud_rate": 9600, "port_path": "/dev/ttyUSB0", "start_bits": 1, "stop_bits": 1, "flow_control": "Etc"}}}},
+        ]});
+
+        let warnings = migrate_config(&mut value).unwrap();
+        assert_eq!(warnings, vec!["Stream 'UART': 'flow_control' Etc was replaced by None, use XonXoff or RtsCts for flow control"]);
+        assert_eq!(value, json!({"version": CONFIG_VERSION, "stream_configs": [
+            {"name": "UART", "type_config": {"Serial": {"config": {"baud_rate": 9600, "port_path": "/dev/ttyUSB0", "stop_bits": 1, "flow_control": "None"}}}},
+        ]}));
+    }
+
+    #[test]
+    fn test_version_2_none_flow_control_migrated() {
+        let mut value = json!({"version": 2, "stream_configs": [
+            {"name": "UART", "type_config": {"Serial": {"config": {"baud_rate": 115200, "port_path": "/dev/ttyUSB0", "start_bits": 1, "stop_bits": 1, "flow_control": {"None": []}}}}},
+        ]});
+
+        let warnings = migrate_config(&mut value).unwrap();
+        assert_eq!(warnings, vec![r#"Stream 'UART': 'flow_control' {"None": []} was replaced by "None""#]);
+        assert_eq!(value.pointer("/stream_configs/0/type_config/Serial/config/flow_control"), Some(&json!("None")));
+    }
+
+    #[test]
+    fn test_current_version_unchanged() {
+        let mut value = json!({"version": CONFIG_VERSION, "stream_configs": [{"name": "Log", "direction": "kept"}]});
+        let expected = value.clone();
+        assert!(migrate_config(&mut value).unwrap().is_empty());
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn test_later_version_rejected() {
+        let mut value = json!({"version": CONFIG_VERSION + 1, "stream_configs": []});
+        assert!(migrate_config(&mut value).is_err());
+        assert!(migrate_config(&mut json!({"version": "two"})).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use crate::filter::{Filter, FilterType};
+use crate::stage::StagePipeline;
+use crate::stream::rule_engine_stream::RuleAction;
+use crate::stream::serial_stream::SerialControl;
+use crate::stream::udp_stream::UdpDirection;
+use crate::stream::{StreamConfig, StreamTypeConfig};
+use crate::streams_config::StreamsConfig;
+use crate::tools::waveforms_i2c::waveforms_i2c::WaveformsI2cControl;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{TcpListener, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, PartialEq)]
+/// The `ConfigProblem` struct describes a problem found in the configuration of a stream, which would stop the
+/// stream from starting or working as intended.
+pub struct ConfigProblem {
+    pub stream: String,
+    pub problem: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}': {}", self.stream, self.problem)
+    }
+}
+
+/// Checks a `StreamsConfig` without starting any stream, returning every problem found.
+///
+/// Besides the links between the streams, the checks look at the environment the streams will run in: serial ports
+/// and capture files must exist, the directories written to must be writable, network addresses must be valid and
+/// the ports listened on must be free on their address and not used by two streams. Stages, input filters and regular
+/// expressions must compile.
+///
+/// # Returns
+/// The problems found, in the order of the streams. An empty vector means the configuration is valid.
+///
+/// Templates are checked through the streams created for the devices currently present.
+pub fn validate_config(config: &StreamsConfig) -> Vec<ConfigProblem> {
+    let mut problems: Vec<ConfigProblem> = Vec::new();
+    let mut stream_configs: Vec<StreamConfig> = config.stream_configs.clone();
+    for template in config.templates.iter() {
+        match template.expand() {
+            Ok(instances) => stream_configs.extend(instances),
+            Err(problem) => problems.push(ConfigProblem { stream: template.stream.name.clone(), problem }),
+        }
+    }
+
+    let uuids: HashSet<Uuid> = stream_configs.iter().map(|stream_config| stream_config.uuid).collect();
+    let mut seen_uuids: HashSet<Uuid> = HashSet::new();
+    let mut bound_ports: HashMap<(&str, u16), &str> = HashMap::new();
+
+    for stream_config in stream_configs.iter() {
+        let mut report = |problem: String| problems.push(ConfigProblem { stream: stream_config.name.clone(), problem });
+
+        if !seen_uuids.insert(stream_config.uuid) {
+            report(format!("UUID {} is used by another stream", stream_config.uuid));
+        }
+        for output_uuid in stream_config.output_streams.iter().filter(|uuid| !uuids.contains(uuid)) {
+            report(format!("Output stream {output_uuid} does not exist"));
+        }
+        for target_uuid in stream_config.target_streams().iter().filter(|uuid| !uuids.contains(uuid)) {
+            report(format!("Target stream {target_uuid} does not exist"));
+        }
+        if let Err(e) = StagePipeline::new(&stream_config.input_stages) {
+            report(format!("Input stages: {e}"));
+        }
+        if let Err(e) = StagePipeline::new(&stream_config.stages) {
+            report(format!("Stages: {e}"));
+        }
+        if !stream_config.input_filter.is_empty() {
+            if let Err(e) = Filter::new("input_filter", FilterType::Regex, &stream_config.input_filter).validate() {
+                report(e);
+            }
+        }
+
+        for (protocol, bind_address, port) in listened_ports(stream_config) {
+            let address = format!("{bind_address}:{port}");
+            if let Err(e) = address.to_socket_addrs() {
+                report(format!("Invalid bind address {address}: {e}"));
+                continue;
+            }
+            match bound_ports.get(&(protocol, port)) {
+                Some(other_stream) => report(format!("{protocol} port {port} is also listened on by '{other_stream}'")),
+                None => {
+                    bound_ports.insert((protocol, port), &stream_config.name);
+                    if let Err(e) = check_port_free(protocol, &address) {
+                        report(format!("{protocol} port {port} is unavailable: {e}"));
+                    }
+                },
+            }
+        }
+
+        for problem in validate_type_config(&stream_config.type_config) {
+            report(problem);
+        }
+    }
+
+    problems
+}
+
+/// Checks the type-specific configuration of a stream.
+fn validate_type_config(type_config: &StreamTypeConfig) -> Vec<String> {
+    let mut problems: Vec<String> = Vec::new();
+
+    match type_config {
+        StreamTypeConfig::Serial{config} => {
+            if let Some(usb_device) = &config.usb_device {
+                problems.extend(usb_device.find_port().err());
+            } else if config.port_path.is_empty() {
+                problems.push(String::from("No serial port given"));
+            } else if !Path::new(&config.port_path).exists() {
+                problems.push(format!("Serial port {} does not exist", config.port_path));
+            }
+            problems.extend(config.port_builder().err());
+        },
+        StreamTypeConfig::File{config} => {
+            problems.extend(check_directory_writable(&config.file_path).err());
+        },
+        StreamTypeConfig::Udp{config} => {
+            if config.direction == UdpDirection::UdpOutput {
+                let address = format!("{}:{}", config.output_ip_address, config.output_port);
+                if config.output_port == 0 {
+                    problems.push(String::from("No output port given"));
+                } else if let Err(e) = address.to_socket_addrs() {
+                    problems.push(format!("Invalid output address {address}: {e}"));
+                }
+            } else if config.input_port == 0 {
+                problems.push(String::from("No input port given"));
+            }
+        },
+        StreamTypeConfig::WaveformsI2c{..} => {
+            problems.extend(WaveformsI2cControl::check_library().err().map(|e| format!("Waveforms SDK unavailable: {e}")));
+        },
+        StreamTypeConfig::RingBuffer{..} | StreamTypeConfig::Terminal{..} => {},
+        StreamTypeConfig::Replay{config} => {
+            if !Path::new(&config.file_path).is_file() {
+                problems.push(format!("Capture file {} does not exist", config.file_path));
+            }
+            if config.speed.is_nan() || config.speed <= 0.0 {
+                problems.push(String::from("Replay speed must be greater than zero"));
+            }
+        },
+        StreamTypeConfig::TriggerCapture{config} => {
+            problems.extend(check_directory_writable(&config.file_path).err());
+            if let Err(e) = Regex::new(&config.trigger_pattern) {
+                problems.push(format!("Trigger pattern: {e}"));
+            }
+        },
+        StreamTypeConfig::RuleEngine{config} => {
+            for rule in config.rules.iter() {
+                if let Err(e) = Regex::new(&rule.pattern) {
+                    problems.push(format!("Rule '{}': {e}", rule.name));
+                }
+                // Commands using the captures of the pattern can only be checked when the rule fires.
+                if let RuleAction::Control{command, ..} = &rule.action {
+                    if !command.contains('$') {
+                        problems.extend(command.parse::<SerialControl>().err().map(|e| format!("Rule '{}': {e}", rule.name)));
+                    }
+                }
+            }
+        },
+        StreamTypeConfig::Pty{config} => {
+            if let Some(link_path) = &config.link_path {
+                problems.extend(check_directory_writable(link_path).err());
+                if Path::new(link_path).symlink_metadata().is_ok_and(|metadata| !metadata.file_type().is_symlink()) {
+                    problems.push(format!("{link_path} exists and is not a link"));
+                }
+            }
+        },
+        StreamTypeConfig::Mqtt{..} | StreamTypeConfig::None => {
+            problems.push(format!("{type_config} streams are not supported"));
+        },
+    }
+
+    problems
+}
+
+/// Returns the protocols, addresses and ports a stream listens on.
+fn listened_ports(stream_config: &StreamConfig) -> Vec<(&'static str, &str, u16)> {
+    match &stream_config.type_config {
+        StreamTypeConfig::Udp{config} if config.direction == UdpDirection::UdpInput && config.input_port != 0 => vec![("UDP", "0.0.0.0", config.input_port)],
+        StreamTypeConfig::RingBuffer{config} => config.query_port.map(|port| ("TCP", config.query_bind_address.as_str(), port)).into_iter().collect(),
+        StreamTypeConfig::Serial{config} => config.tcp_bridge.iter().filter(|bridge| bridge.port != 0).map(|bridge| ("TCP", bridge.bind_address.as_str(), bridge.port)).collect(),
+        _ => vec![],
+    }
+}
+
+/// Checks that a port is free, by binding to its address as the stream would.
+fn check_port_free(protocol: &str, address: &str) -> Result<(), String> {
+    match protocol {
+        "UDP" => UdpSocket::bind(address).map(|_| ()).map_err(|e| e.to_string()),
+        _ => TcpListener::bind(address).map(|_| ()).map_err(|e| e.to_string()),
+    }
+}
+
+/// Checks that a file can be created in the directory of the file path.
+fn check_directory_writable(file_path: &str) -> Result<(), String> {
+    let directory = match Path::new(file_path).parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    if !directory.is_dir() {
+        return Err(format!("Directory {} does not exist", directory.display()));
+    }
+    tempfile::tempfile_in(directory).map(|_| ()).map_err(|e| format!("Directory {} is not writable: {e}", directory.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stage::StageConfig;
+    use crate::stage::timestamp_stage::TimestampStageConfig;
+    use crate::stream::file_stream::FileStreamConfig;
+    use crate::stream::ring_buffer_stream::RingBufferStreamConfig;
+    use crate::stream::rule_engine_stream::{Rule, RuleEngineStreamConfig};
+    use crate::stream::serial_stream::SerialStreamConfig;
+    use crate::stream::udp_stream::UdpStreamConfig;
+
+    fn stream(name: &str, type_config: StreamTypeConfig) -> StreamConfig {
+        StreamConfig { name: String::from(name), type_config, ..StreamConfig::default() }
+    }
+
+    #[test]
+    fn test_valid_config_has_no_problems() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_path = directory.path().join("log.txt").to_str().unwrap().to_string();
+        let mut ring_buffer = stream("Scrollback", StreamTypeConfig::RingBuffer { config: RingBufferStreamConfig::new() });
+        let file = stream("File", StreamTypeConfig::File { config: FileStreamConfig::new(file_path) });
+        ring_buffer.add_output_stream(file.uuid);
+
+        let config = StreamsConfig { stream_configs: vec![ring_buffer, file], ..StreamsConfig::new() };
+        assert_eq!(validate_config(&config), vec![]);
+    }
+
+    #[test]
+    fn test_all_problems_are_reported() {
+        let mut serial_config = SerialStreamConfig::new();
+        serial_config.port_path = String::from("/dev/does-not-exist");
+        let mut serial = stream("Serial", StreamTypeConfig::Serial { config: serial_config });
+        serial.add_output_stream(Uuid::new_v4());
+        serial.add_stage(StageConfig::Timestamp { config: TimestampStageConfig { pattern: String::from("(unclosed"), ..TimestampStageConfig::new() } });
+
+        let file = stream("File", StreamTypeConfig::File { config: FileStreamConfig::new(String::from("/does-not-exist/log.txt")) });
+
+        let mut rules_config = RuleEngineStreamConfig::new();
+        rules_config.rules.push(Rule::new(String::from("broken"), String::from("[a-"), RuleAction::RunCommand { program: String::from("true"), args: vec![] }));
+        let rules = stream("Rules", StreamTypeConfig::RuleEngine { config: rules_config });
+
+        let mut udp_config = UdpStreamConfig::new();
+        udp_config.output_ip_address = String::from("not an address");
+        udp_config.output_port = 5000;
+        let udp = stream("UDP", StreamTypeConfig::Udp { config: udp_config });
+
+        let config = StreamsConfig { stream_configs: vec![serial, file, rules, udp], ..StreamsConfig::new() };
+        let problems: Vec<String> = validate_config(&config).iter().map(|problem| problem.to_string()).collect();
+
+        assert_eq!(problems.len(), 6, "{problems:#?}");
+        assert!(problems[0].starts_with("'Serial': Output stream"));
+        assert!(problems[1].starts_with("'Serial': Stages: Invalid Timestamp stage"));
+        assert_eq!(problems[2], "'Serial': Serial port /dev/does-not-exist does not exist");
+        assert_eq!(problems[3], "'File': Directory /does-not-exist does not exist");
+        assert!(problems[4].starts_with("'Rules': Rule 'broken'"));
+        assert!(problems[5].starts_with("'UDP': Invalid output address not an address:5000"));
+    }
+
+    #[test]
+    fn test_ports_listened_on_twice_are_reported() {
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let busy_port = socket.local_addr().unwrap().port();
+
+        let mut udp_config = UdpStreamConfig::new();
+        udp_config.direction = UdpDirection::UdpInput;
+        udp_config.input_port = busy_port;
+        let busy = stream("Busy", StreamTypeConfig::Udp { config: udp_config.clone() });
+        let twice = stream("Twice", StreamTypeConfig::Udp { config: udp_config });
+
+        let config = StreamsConfig { stream_configs: vec![busy, twice], ..StreamsConfig::new() };
+        let problems: Vec<String> = validate_config(&config).iter().map(|problem| problem.to_string()).collect();
+
+        assert_eq!(problems.len(), 2, "{problems:#?}");
+        assert!(problems[0].starts_with(&format!("'Busy': UDP port {busy_port} is unavailable")));
+        assert_eq!(problems[1], format!("'Twice': UDP port {busy_port} is also listened on by 'Busy'"));
+    }
+
+    #[test]
+    fn test_bind_addresses_and_input_filters_checked() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy_port = listener.local_addr().unwrap().port();
+
+        let busy = stream("Busy", StreamTypeConfig::RingBuffer { config: RingBufferStreamConfig { query_port: Some(busy_port), ..RingBufferStreamConfig::new() } });
+        let invalid = stream("Invalid", StreamTypeConfig::RingBuffer { config: RingBufferStreamConfig { query_port: Some(0), query_bind_address: String::from("not an address"), ..RingBufferStreamConfig::new() } });
+        let filtered = StreamConfig { input_filter: String::from("(unclosed"), ..stream("Filtered", StreamTypeConfig::RingBuffer { config: RingBufferStreamConfig::new() }) };
+
+        let config = StreamsConfig { stream_configs: vec![busy, invalid, filtered], ..StreamsConfig::new() };
+        let problems: Vec<String> = validate_config(&config).iter().map(|problem| problem.to_string()).collect();
+
+        assert_eq!(problems.len(), 3, "{problems:#?}");
+        assert!(problems[0].starts_with(&format!("'Busy': TCP port {busy_port} is unavailable")));
+        assert!(problems[1].starts_with("'Invalid': Invalid bind address not an address:0"));
+        assert!(problems[2].starts_with("'Filtered': Filter 'input_filter'"));
+    }
+}
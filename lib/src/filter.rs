@@ -1,3 +1,5 @@
+use std::sync::OnceLock;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -7,11 +9,19 @@ pub enum FilterType {
     Regex,        // Matches using a regular expression
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Filter {
     pub name: String,
     pub filter_type: FilterType,
     pub value: String,
+    #[serde(skip)]
+    regex: OnceLock<Option<Regex>>, // Compiled on first use, `None` if the expression is invalid.
+}
+
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.filter_type == other.filter_type && self.value == other.value
+    }
 }
 
 impl Filter {
@@ -21,6 +31,15 @@ impl Filter {
             name: name.to_string(),
             filter_type,
             value: value.to_string(),
+            regex: OnceLock::new(),
+        }
+    }
+
+    // Method to check that the filter can be applied, i.e. that a regular expression compiles
+    pub fn validate(&self) -> Result<(), String> {
+        match self.filter_type {
+            FilterType::Regex => Regex::new(&self.value).map(|_| ()).map_err(|e| format!("Filter '{}': {}", self.name, e)),
+            _ => Ok(()),
         }
     }
 
@@ -30,12 +49,34 @@ impl Filter {
             FilterType::WholeMatch => self.value == input,
             FilterType::PartialMatch => input.contains(&self.value),
             FilterType::Regex => {
-                // Implement regex matching here
-                // Example (requires `regex` crate): 
-                // let re = regex::Regex::new(&self.value).unwrap();
-                // re.is_match(input)
-                unimplemented!("Regex matching not implemented yet")
+                // An invalid expression never matches, use `validate` to report it.
+                self.regex.get_or_init(|| Regex::new(&self.value).ok()).as_ref().is_some_and(|re| re.is_match(input))
             },
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_matching() {
+        assert!(Filter::new("whole", FilterType::WholeMatch, "boot ok").matches("boot ok"));
+        assert!(!Filter::new("whole", FilterType::WholeMatch, "boot").matches("boot ok"));
+        assert!(Filter::new("partial", FilterType::PartialMatch, "boot").matches("boot ok"));
+        assert!(Filter::new("regex", FilterType::Regex, r"^boot \w+$").matches("boot ok"));
+        assert!(!Filter::new("regex", FilterType::Regex, r"^ok").matches("boot ok"));
+
+        let filter: Filter = serde_json::from_str(r#"{"name": "json", "filter_type": "Regex", "value": "^boot"}"#).unwrap();
+        assert!(filter.matches("boot ok") && filter.clone().matches("boot again") && !filter.matches("ok"));
+    }
+
+    #[test]
+    fn test_invalid_regex_filter() {
+        let filter = Filter::new("broken", FilterType::Regex, "[unclosed");
+        assert!(filter.validate().is_err());
+        assert!(!filter.matches("[unclosed"));
+    }
+}
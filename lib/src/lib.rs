@@ -1,6 +1,14 @@
 
 pub mod streams_engine;
 pub mod streams_config;
+pub mod stream_template;
+pub mod config_manager;
+pub mod config_migration;
+pub mod config_validator;
 pub mod message;
+pub mod filter;
 pub mod stream;
+pub mod stage;
+pub mod script;
+pub mod stream_statistics;
 pub mod tools;
@@ -16,17 +16,44 @@
 /// - `timestamp_ms`: The timestamp of the message in milliseconds since the EPOC.
 /// - `originator`: The string representing the originator of the message.
 /// - `text`: The text content of the message.
+/// - `device_timestamp_ms`: The time the device reported for the message, if known, in milliseconds since the EPOC.
+/// - `device_uptime_ms`: The device uptime reported for the message, if known, in milliseconds.
+/// - `kind`: Whether the message carries data, or is a marker, a warning or a control command, see `MessageKind`.
+/// - `boot_session`: The device boot session the message belongs to, if sessions are being tracked.
 use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+/// The `MessageKind` enum distinguishes data received from a source from messages generated by the relay.
+///
+/// - `Data`: A message carrying data from a source.
+/// - `Marker`: An event generated by the relay itself, e.g. a detected device reboot.
+/// - `Warning`: A problem detected by the relay, e.g. lost lines.
+/// - `Control`: A command for the stream it is sent to rather than data, e.g. `dtr pulse 100` for a serial stream.
+pub enum MessageKind {
+    #[default]
+    Data,
+    Marker,
+    Warning,
+    Control,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 /// A message with a timestamp, originator, and text content.
 ///
 /// This struct represents a message with a timestamp in milliseconds since the EPOC,
 /// an originator string, and the text content of the message.
 pub struct Message {
-    pub timestamp_ms: i64, // Number of milliseconds since EPOC.
+    pub timestamp_ms: i64, // Number of milliseconds since EPOC, taken when the message was received.
     pub originator: String,
     pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_timestamp_ms: Option<i64>, // Number of milliseconds since EPOC, as reported by the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_uptime_ms: Option<i64>,
+    #[serde(default)]
+    pub kind: MessageKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_session: Option<u32>,
 }
 
 // Add new function called clear
@@ -41,6 +68,34 @@ impl Message {
             timestamp_ms: timestamp,
             originator,
             text,
+            device_timestamp_ms: None,
+            device_uptime_ms: None,
+            kind: MessageKind::Data,
+            boot_session: None,
+        }
+    }
+
+    /// Creates a new marker `Message`, used for events generated by the relay rather than by a source.
+    pub fn new_marker(timestamp: i64, originator: String, text: String) -> Message {
+        Message {
+            kind: MessageKind::Marker,
+            ..Message::new(timestamp, originator, text)
+        }
+    }
+
+    /// Creates a new control `Message`, carrying a command for the stream it is sent to.
+    pub fn new_control(timestamp: i64, originator: String, command: String) -> Message {
+        Message {
+            kind: MessageKind::Control,
+            ..Message::new(timestamp, originator, command)
+        }
+    }
+
+    /// Creates a new warning `Message`, used for problems detected by the relay.
+    pub fn new_warning(timestamp: i64, originator: String, text: String) -> Message {
+        Message {
+            kind: MessageKind::Warning,
+            ..Message::new(timestamp, originator, text)
         }
     }
 }
@@ -123,4 +178,20 @@ mod tests {
         assert_eq!(message.originator, "");
         assert_eq!(message.text, "");
     }
+
+    #[test]
+    /// Tests that a `Message` serialised without the optional device fields can still be deserialised,
+    /// and that the optional fields round-trip when present.
+    fn test_message_optional_fields_serde() {
+        let message: Message = serde_json::from_str(r#"{"timestamp_ms":1,"originator":"Old","text":"Old format"}"#).unwrap();
+        assert_eq!(message, Message::new(1, "Old".to_string(), "Old format".to_string()));
+
+        let mut message = Message::new(2, "New".to_string(), "New format".to_string());
+        message.device_timestamp_ms = Some(3);
+        message.device_uptime_ms = Some(4);
+        message.kind = MessageKind::Marker;
+        message.boot_session = Some(5);
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), message);
+    }
 }
\ No newline at end of file
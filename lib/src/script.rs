@@ -0,0 +1,381 @@
+use std::{collections::{HashMap, VecDeque}, fmt, fs::File, io::{BufReader, Write}, sync::mpsc::{Receiver, RecvTimeoutError, Sender}, time::{Duration, Instant}};
+use chrono::Utc;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::message::Message;
+use crate::stream::file_stream::{FileFormat, FileStream};
+use crate::streams_engine::StreamsEngine;
+
+/// The number of recent messages of a stream included in the report of a failed step.
+const EXCERPT_LINES: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// The `ScriptStep` enum represents a step of a `Script`. Streams are referred to by ID or name.
+///
+/// - `WaitFor`: Waits for a message matching the regular expression on the stream, failing after the timeout.
+///   Like `expect`, the messages output since the previous step matched are searched first.
+/// - `Send`: Sends a message to the stream, as if it was routed to it. Bidirectional streams write it to their device.
+/// - `AssertAbsent`: Fails if a message matching the regular expression is output by the stream during the duration.
+///
+/// `WaitFor` and `AssertAbsent` ignore the messages sent by the script itself, which streams pass on to their outputs.
+pub enum ScriptStep {
+    WaitFor{stream: String, pattern: String, timeout_ms: u64},
+    Send{stream: String, text: String},
+    AssertAbsent{stream: String, pattern: String, duration_ms: u64},
+}
+
+impl ScriptStep {
+    fn stream(&self) -> &str {
+        match self {
+            ScriptStep::WaitFor{stream, ..} | ScriptStep::Send{stream, ..} | ScriptStep::AssertAbsent{stream, ..} => stream,
+        }
+    }
+
+    fn pattern(&self) -> Option<&str> {
+        match self {
+            ScriptStep::WaitFor{pattern, ..} | ScriptStep::AssertAbsent{pattern, ..} => Some(pattern),
+            ScriptStep::Send{..} => None,
+        }
+    }
+}
+
+impl fmt::Display for ScriptStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptStep::WaitFor{stream, pattern, timeout_ms} => write!(f, "wait for /{pattern}/ on '{stream}' within {timeout_ms} ms"),
+            ScriptStep::Send{stream, text} => write!(f, "send {text:?} to '{stream}'"),
+            ScriptStep::AssertAbsent{stream, pattern, duration_ms} => write!(f, "assert no /{pattern}/ on '{stream}' for {duration_ms} ms"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// A named sequence of steps run, in order, against the streams of a running `StreamsEngine`.
+pub struct Script {
+    pub name: String,
+    pub steps: Vec<ScriptStep>,
+}
+
+impl Script {
+    pub fn new(name: String) -> Self {
+        Script { name, steps: vec![] }
+    }
+
+    /// Loads a script from a JSON file.
+    pub fn load(file_path: &str) -> Result<Script, String> {
+        let file = File::open(file_path).map_err(|e| format!("{file_path}: {e}"))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| format!("{file_path}: {e}"))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The `StepOutcome` enum represents the outcome of a script step. Steps following a failed step are skipped.
+pub enum StepOutcome {
+    Passed,
+    Failed{message: String, excerpt: Vec<String>},
+    Skipped,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepResult {
+    pub description: String,
+    pub duration: Duration,
+    pub outcome: StepOutcome,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The results of running a `Script`.
+pub struct ScriptReport {
+    pub name: String,
+    pub results: Vec<StepResult>,
+}
+
+impl ScriptReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.outcome == StepOutcome::Passed)
+    }
+
+    /// Formats the report as a JUnit XML document, with a test case per step.
+    pub fn to_junit_xml(&self) -> String {
+        let count = |predicate: fn(&StepOutcome) -> bool| self.results.iter().filter(|result| predicate(&result.outcome)).count();
+        let failures = count(|outcome| matches!(outcome, StepOutcome::Failed{..}));
+        let skipped = count(|outcome| matches!(outcome, StepOutcome::Skipped));
+        let total_time: f64 = self.results.iter().map(|result| result.duration.as_secs_f64()).sum();
+        let name = xml_escape(&self.name);
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+        xml += &format!("  <testsuite name=\"{name}\" tests=\"{}\" failures=\"{failures}\" errors=\"0\" skipped=\"{skipped}\" time=\"{total_time:.3}\">\n", self.results.len());
+        for (index, result) in self.results.iter().enumerate() {
+            let case_name = xml_escape(&format!("{}: {}", index + 1, result.description));
+            xml += &format!("    <testcase classname=\"{name}\" name=\"{case_name}\" time=\"{:.3}\"", result.duration.as_secs_f64());
+            match &result.outcome {
+                StepOutcome::Passed => xml += "/>\n",
+                StepOutcome::Skipped => xml += ">\n      <skipped/>\n    </testcase>\n",
+                StepOutcome::Failed{message, excerpt} => {
+                    xml += &format!(">\n      <failure message=\"{}\">", xml_escape(message));
+                    for line in excerpt {
+                        xml += &xml_escape(line);
+                        xml += "\n";
+                    }
+                    xml += "</failure>\n    </testcase>\n";
+                },
+            }
+        }
+        xml += "  </testsuite>\n</testsuites>\n";
+        xml
+    }
+
+    pub fn write_junit(&self, file_path: &str) -> Result<(), String> {
+        let mut file = File::create(file_path).map_err(|e| format!("{file_path}: {e}"))?;
+        file.write_all(self.to_junit_xml().as_bytes()).map_err(|e| format!("{file_path}: {e}"))
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than whitespace are not allowed in XML documents.
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The output of a stream watched by a script, with the recent messages kept for failure reports.
+struct WatchedStream {
+    receiver: Receiver<Message>,
+    excerpt: VecDeque<String>,
+}
+
+impl WatchedStream {
+    fn recv_until(&mut self, deadline: Instant) -> Result<Message, RecvTimeoutError> {
+        let msg = self.receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))?;
+        if self.excerpt.len() == EXCERPT_LINES {
+            self.excerpt.pop_front();
+        }
+        self.excerpt.push_back(FileStream::format_message(&msg, &FileFormat::Text));
+        Ok(msg)
+    }
+
+    fn failure(&self, message: String) -> StepOutcome {
+        StepOutcome::Failed{message, excerpt: self.excerpt.iter().cloned().collect()}
+    }
+}
+
+/// Runs a `Script` against the streams of a `StreamsEngine`.
+///
+/// The runner must be created before the engine is started, so that it can subscribe to the streams it watches.
+pub struct ScriptRunner {
+    script: Script,
+    regexes: Vec<Option<Regex>>,
+    watched: HashMap<String, WatchedStream>,
+    senders: HashMap<String, Sender<Message>>,
+}
+
+impl ScriptRunner {
+    pub fn new(script: Script, engine: &mut StreamsEngine) -> Result<Self, String> {
+        let mut regexes: Vec<Option<Regex>> = Vec::new();
+        let mut watched: HashMap<String, WatchedStream> = HashMap::new();
+        let mut senders: HashMap<String, Sender<Message>> = HashMap::new();
+
+        for (index, step) in script.steps.iter().enumerate() {
+            let stream_name = step.stream();
+            let uuid = engine.find_stream(stream_name).ok_or(format!("Step {}: unknown stream '{stream_name}'", index + 1))?;
+
+            match step.pattern() {
+                Some(pattern) => {
+                    regexes.push(Some(Regex::new(pattern).map_err(|e| format!("Step {}: {e}", index + 1))?));
+                    if !watched.contains_key(stream_name) {
+                        watched.insert(stream_name.to_string(), WatchedStream { receiver: engine.subscribe(&uuid)?, excerpt: VecDeque::new() });
+                    }
+                },
+                None => {
+                    regexes.push(None);
+                    senders.insert(stream_name.to_string(), engine.get_input_sender(&uuid).ok_or(format!("Unknown stream: {uuid}"))?);
+                },
+            }
+        }
+
+        Ok(ScriptRunner { script, regexes, watched, senders })
+    }
+
+    /// Runs the steps in order, stopping at the first failure.
+    pub fn run(&mut self) -> ScriptReport {
+        let mut results: Vec<StepResult> = Vec::new();
+        let mut failed = false;
+
+        for (index, step) in self.script.steps.iter().enumerate() {
+            let started = Instant::now();
+            let outcome = if failed {
+                StepOutcome::Skipped
+            } else {
+                println!("Script '{}' - Step {}: {step}", self.script.name, index + 1);
+                Self::run_step(&mut self.watched, &self.senders, &self.script.name, step, self.regexes[index].as_ref())
+            };
+
+            if let StepOutcome::Failed{message, ..} = &outcome {
+                println!("Script '{}' - Step {} failed: {message}", self.script.name, index + 1);
+                failed = true;
+            }
+            results.push(StepResult { description: step.to_string(), duration: started.elapsed(), outcome });
+        }
+
+        ScriptReport { name: self.script.name.clone(), results }
+    }
+
+    fn run_step(watched: &mut HashMap<String, WatchedStream>, senders: &HashMap<String, Sender<Message>>, originator: &str, step: &ScriptStep, regex: Option<&Regex>) -> StepOutcome {
+        match (step, regex) {
+            (ScriptStep::WaitFor{stream, pattern, timeout_ms}, Some(regex)) => {
+                let watched = watched.get_mut(stream).expect("Watched streams are subscribed when the runner is created");
+                let deadline = Instant::now() + Duration::from_millis(*timeout_ms);
+                loop {
+                    match watched.recv_until(deadline) {
+                        Ok(msg) if msg.originator != originator && regex.is_match(&msg.text) => return StepOutcome::Passed,
+                        Ok(_) => {},
+                        Err(RecvTimeoutError::Timeout) => return watched.failure(format!("Timed out after {timeout_ms} ms waiting for /{pattern}/ on '{stream}'")),
+                        Err(RecvTimeoutError::Disconnected) => return watched.failure(format!("Stream '{stream}' stopped while waiting for /{pattern}/")),
+                    }
+                }
+            },
+            (ScriptStep::AssertAbsent{stream, pattern, duration_ms}, Some(regex)) => {
+                let watched = watched.get_mut(stream).expect("Watched streams are subscribed when the runner is created");
+                let deadline = Instant::now() + Duration::from_millis(*duration_ms);
+                loop {
+                    match watched.recv_until(deadline) {
+                        Ok(msg) if msg.originator != originator && regex.is_match(&msg.text) => return watched.failure(format!("Unexpected message matching /{pattern}/ on '{stream}': '{}'", msg.text)),
+                        Ok(_) => {},
+                        Err(RecvTimeoutError::Timeout) => return StepOutcome::Passed,
+                        Err(RecvTimeoutError::Disconnected) => return watched.failure(format!("Stream '{stream}' stopped while asserting no /{pattern}/")),
+                    }
+                }
+            },
+            (ScriptStep::Send{stream, text}, _) => {
+                let sender = senders.get(stream).expect("Senders are resolved when the runner is created");
+                match sender.send(Message::new(Utc::now().timestamp_millis(), originator.to_string(), text.clone())) {
+                    Ok(_) => StepOutcome::Passed,
+                    Err(e) => StepOutcome::Failed{message: format!("Failed to send to '{stream}': {e}"), excerpt: vec![]},
+                }
+            },
+            _ => unreachable!("Regular expressions are compiled for every step with a pattern"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::{rule_engine_stream::{Rule, RuleAction, RuleEngineStreamConfig}, terminal_stream::TerminalStreamConfig, StreamConfig, StreamTypeConfig};
+
+    fn terminal_config(name: &str) -> StreamConfig {
+        let mut terminal_config = TerminalStreamConfig::new();
+        terminal_config.print_to_standard_out = false;
+        let mut config = StreamConfig::default();
+        config.name = String::from(name);
+        config.type_config = StreamTypeConfig::Terminal { config: terminal_config };
+        config
+    }
+
+    /// Runs the steps against a "Device" stream that answers `reset` with `boot ok` and `crash` with `ERROR: watchdog`.
+    fn run_script(steps: Vec<ScriptStep>) -> ScriptReport {
+        let mut device = terminal_config("Device");
+        let reply = |pattern: &str, text: &str| Rule::new(String::from(pattern), String::from(pattern), RuleAction::Inject{stream: device.uuid, template: String::from(text)});
+        let responder = StreamConfig {
+            name: String::from("Responder"),
+            type_config: StreamTypeConfig::RuleEngine { config: RuleEngineStreamConfig { rules: vec![reply("^reset$", "boot ok"), reply("^crash$", "ERROR: watchdog")] } },
+            ..StreamConfig::default()
+        };
+        device.add_output_stream(responder.uuid);
+
+        let mut engine = StreamsEngine::new();
+        engine.add_stream(device).unwrap();
+        engine.add_stream(responder).unwrap();
+        engine.initialise().unwrap();
+
+        let mut script = Script::new(String::from("Boot test"));
+        script.steps = steps;
+        let mut runner = ScriptRunner::new(script, &mut engine).unwrap();
+        engine.start().unwrap();
+        let report = runner.run();
+        engine.stop().unwrap();
+        report
+    }
+
+    #[test]
+    fn test_send_and_wait_for() {
+        let report = run_script(vec![
+            ScriptStep::Send{stream: String::from("Device"), text: String::from("reset")},
+            ScriptStep::WaitFor{stream: String::from("Device"), pattern: String::from("boot (ok|done)"), timeout_ms: 5000},
+            ScriptStep::AssertAbsent{stream: String::from("Device"), pattern: String::from("reset|ERROR"), duration_ms: 50},
+        ]);
+        assert!(report.passed(), "{report:?}");
+    }
+
+    #[test]
+    fn test_own_messages_ignored() {
+        let report = run_script(vec![
+            ScriptStep::Send{stream: String::from("Device"), text: String::from("boot ok")},
+            ScriptStep::WaitFor{stream: String::from("Device"), pattern: String::from("boot ok"), timeout_ms: 200},
+        ]);
+        assert!(matches!(&report.results[1].outcome, StepOutcome::Failed{message, ..} if message.starts_with("Timed out after 200 ms")));
+    }
+
+    #[test]
+    fn test_failure_skips_remaining_steps() {
+        let report = run_script(vec![
+            ScriptStep::Send{stream: String::from("Device"), text: String::from("crash")},
+            ScriptStep::AssertAbsent{stream: String::from("Device"), pattern: String::from("ERROR"), duration_ms: 5000},
+            ScriptStep::Send{stream: String::from("Device"), text: String::from("reset")},
+        ]);
+
+        assert!(!report.passed());
+        match &report.results[1].outcome {
+            StepOutcome::Failed{message, excerpt} => {
+                assert!(message.contains("ERROR: watchdog"));
+                assert_eq!(excerpt.len(), 2);
+            },
+            outcome => panic!("Unexpected outcome: {outcome:?}"),
+        }
+        assert_eq!(report.results[2].outcome, StepOutcome::Skipped);
+    }
+
+    #[test]
+    fn test_wait_for_times_out() {
+        let report = run_script(vec![ScriptStep::WaitFor{stream: String::from("Device"), pattern: String::from("never"), timeout_ms: 50}]);
+        assert!(matches!(&report.results[0].outcome, StepOutcome::Failed{message, ..} if message.starts_with("Timed out after 50 ms")));
+    }
+
+    #[test]
+    fn test_unknown_stream_rejected() {
+        let mut engine = StreamsEngine::new();
+        engine.add_stream(terminal_config("Device")).unwrap();
+        let mut script = Script::new(String::from("Typo"));
+        script.steps.push(ScriptStep::Send{stream: String::from("Devcie"), text: String::from("reset")});
+
+        assert!(ScriptRunner::new(script, &mut engine).is_err());
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let report = ScriptReport {
+            name: String::from("Boot <test>"),
+            results: vec![
+                StepResult { description: String::from("send \"reset\" to 'Device'"), duration: Duration::from_millis(1), outcome: StepOutcome::Passed },
+                StepResult { description: String::from("wait"), duration: Duration::from_millis(1500), outcome: StepOutcome::Failed{message: String::from("Timed out"), excerpt: vec![String::from("a & b")]} },
+                StepResult { description: String::from("assert"), duration: Duration::ZERO, outcome: StepOutcome::Skipped },
+            ],
+        };
+
+        let xml = report.to_junit_xml();
+        assert!(xml.contains("<testsuite name=\"Boot &lt;test&gt;\" tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\" time=\"1.501\">"));
+        assert!(xml.contains("name=\"1: send &quot;reset&quot; to &apos;Device&apos;\""));
+        assert!(xml.contains("<failure message=\"Timed out\">a &amp; b\n</failure>"));
+        assert!(xml.contains("<skipped/>"));
+    }
+}
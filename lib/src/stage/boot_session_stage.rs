@@ -0,0 +1,149 @@
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use super::Stage;
+use crate::message::Message;
+use crate::stream_statistics::StreamStatistics;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `BootSessionStageConfig` struct configures a `BootSessionStage`.
+///
+/// - `banner_pattern`: An optional regular expression matching the first line printed by the device after a reboot.
+/// - `detect_uptime_reset`: Treat the device uptime going backwards as a reboot. Requires a `Timestamp` stage in
+///   uptime mode earlier in the pipeline.
+pub struct BootSessionStageConfig {
+    pub banner_pattern: Option<String>,
+    pub detect_uptime_reset: bool,
+}
+
+impl BootSessionStageConfig {
+    pub fn new() -> Self {
+        BootSessionStageConfig {
+            banner_pattern: None,
+            detect_uptime_reset: true,
+        }
+    }
+}
+
+impl Default for BootSessionStageConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Detects device reboots and segments the messages into boot sessions.
+///
+/// Every message is tagged with the current boot session, starting at 1. When a reboot is detected a marker
+/// message is emitted ahead of the message that revealed the reboot, and the session number is incremented.
+pub struct BootSessionStage {
+    config: BootSessionStageConfig,
+    banner: Option<Regex>,
+    session: u32,
+    last_uptime_ms: Option<i64>,
+}
+
+impl BootSessionStage {
+    pub fn new(config: BootSessionStageConfig) -> Result<Self, String> {
+        let banner = match &config.banner_pattern {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        Ok(BootSessionStage { config, banner, session: 1, last_uptime_ms: None })
+    }
+
+    fn is_reboot(&mut self, msg: &Message) -> bool {
+        if let Some(banner) = &self.banner {
+            if banner.is_match(&msg.text) {
+                // The uptime restarts with the new session, so it must not be detected as a second reboot.
+                self.last_uptime_ms = None;
+                return true;
+            }
+        }
+
+        if self.config.detect_uptime_reset {
+            if let Some(uptime_ms) = msg.device_uptime_ms {
+                let reset = self.last_uptime_ms.is_some_and(|last_uptime_ms| uptime_ms < last_uptime_ms);
+                self.last_uptime_ms = Some(uptime_ms);
+                return reset;
+            }
+        }
+
+        false
+    }
+}
+
+impl Stage for BootSessionStage {
+    fn process(&mut self, mut msg: Message, _stats: &mut StreamStatistics) -> Vec<Message> {
+        let mut output: Vec<Message> = Vec::new();
+
+        if self.is_reboot(&msg) {
+            self.session += 1;
+            let mut marker = Message::new_marker(msg.timestamp_ms, msg.originator.clone(), format!("Device reboot detected, boot session {}", self.session));
+            marker.boot_session = Some(self.session);
+            output.push(marker);
+        }
+
+        msg.boot_session = Some(self.session);
+        output.push(msg);
+        output
+    }
+
+    fn tick(&mut self, _now_ms: i64, _stats: &mut StreamStatistics) -> Vec<Message> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageKind;
+
+    fn msg(text: &str, uptime_ms: Option<i64>) -> Message {
+        let mut msg = Message::new(0, "Device".to_string(), text.to_string());
+        msg.device_uptime_ms = uptime_ms;
+        msg
+    }
+
+    #[test]
+    fn test_banner_starts_new_session() {
+        let mut stats = StreamStatistics::new();
+        let config = BootSessionStageConfig { banner_pattern: Some(String::from("^U-Boot")), detect_uptime_reset: true };
+        let mut stage = BootSessionStage::new(config).unwrap();
+
+        let output = stage.process(msg("running", None), &mut stats);
+        assert_eq!(output[0].boot_session, Some(1));
+
+        let output = stage.process(msg("U-Boot 2024.01", None), &mut stats);
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].kind, MessageKind::Marker);
+        assert_eq!(output[0].text, "Device reboot detected, boot session 2");
+        assert_eq!(output[1].boot_session, Some(2));
+        assert_eq!(output[1].kind, MessageKind::Data);
+    }
+
+    #[test]
+    fn test_uptime_reset_starts_new_session() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = BootSessionStage::new(BootSessionStageConfig::new()).unwrap();
+
+        stage.process(msg("a", Some(1000)), &mut stats);
+        assert_eq!(stage.process(msg("b", Some(2000)), &mut stats).len(), 1);
+
+        let output = stage.process(msg("c", Some(10)), &mut stats);
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[1].boot_session, Some(2));
+    }
+
+    #[test]
+    fn test_banner_and_uptime_reset_count_once() {
+        let mut stats = StreamStatistics::new();
+        let config = BootSessionStageConfig { banner_pattern: Some(String::from("^Booting")), detect_uptime_reset: true };
+        let mut stage = BootSessionStage::new(config).unwrap();
+
+        stage.process(msg("a", Some(5000)), &mut stats);
+        assert_eq!(stage.process(msg("Booting", None), &mut stats).len(), 2);
+        let output = stage.process(msg("b", Some(0)), &mut stats);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].boot_session, Some(2));
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use super::Stage;
+use crate::message::Message;
+use crate::stream_statistics::StreamStatistics;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// Token bucket settings applied to each originator independently.
+///
+/// - `messages_per_second`: The rate at which the bucket refills.
+/// - `burst`: The capacity of the bucket, i.e. the number of messages that may be sent back-to-back.
+pub struct RateLimitConfig {
+    pub messages_per_second: u32,
+    pub burst: u32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `DedupStageConfig` struct configures a `DedupStage`.
+///
+/// - `collapse_repeats`: Collapse identical consecutive messages from an originator into one.
+/// - `repeat_summary_period_ms`: The maximum time a run of repeats is held back before its summary is emitted.
+/// - `rate_limit`: An optional per-originator rate limit.
+pub struct DedupStageConfig {
+    pub collapse_repeats: bool,
+    pub repeat_summary_period_ms: u64,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl DedupStageConfig {
+    pub fn new() -> Self {
+        DedupStageConfig {
+            collapse_repeats: true,
+            repeat_summary_period_ms: 1000,
+            rate_limit: None,
+        }
+    }
+}
+
+impl Default for DedupStageConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct RepeatRun {
+    last: Message,
+    repeats: u64,
+    summary_due_ms: i64,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill_ms: i64,
+    suppressed: u64,
+}
+
+#[derive(Default)]
+struct OriginatorState {
+    run: Option<RepeatRun>,
+    bucket: Option<TokenBucket>,
+}
+
+/// Collapses identical consecutive messages into a single message followed by a
+/// "repeated N times" summary, and enforces a token bucket rate limit per originator.
+///
+/// Summaries are never rate limited themselves, so suppressed traffic is always accounted for.
+pub struct DedupStage {
+    config: DedupStageConfig,
+    originators: HashMap<String, OriginatorState>,
+}
+
+impl DedupStage {
+    pub fn new(config: DedupStageConfig) -> Result<Self, String> {
+        if let Some(rate_limit) = &config.rate_limit {
+            if rate_limit.messages_per_second == 0 || rate_limit.burst == 0 {
+                return Err("rate_limit messages_per_second and burst must be greater than zero".to_string());
+            }
+        }
+        Ok(DedupStage { config, originators: HashMap::new() })
+    }
+
+    fn repeat_summary(run: &RepeatRun) -> Message {
+        Message::new(run.last.timestamp_ms, run.last.originator.clone(), format!("Last message repeated {} times", run.repeats))
+    }
+
+    fn suppression_summary(originator: &str, bucket: &TokenBucket, timestamp_ms: i64) -> Message {
+        Message::new(timestamp_ms, originator.to_string(), format!("Rate limit exceeded, suppressed {} messages", bucket.suppressed))
+    }
+
+    /// Tops up the bucket for the time elapsed since it was last refilled.
+    fn refill(bucket: &mut TokenBucket, rate_limit: &RateLimitConfig, now_ms: i64) {
+        let elapsed_ms = (now_ms - bucket.last_refill_ms).max(0);
+        bucket.tokens = (bucket.tokens + elapsed_ms as f64 * rate_limit.messages_per_second as f64 / 1000.0).min(rate_limit.burst as f64);
+        bucket.last_refill_ms = bucket.last_refill_ms.max(now_ms);
+    }
+
+    /// Applies the rate limit to a message, returning the messages allowed through.
+    fn rate_limit(&mut self, msg: Message, stats: &mut StreamStatistics) -> Vec<Message> {
+        let Some(rate_limit) = &self.config.rate_limit else {
+            return vec![msg];
+        };
+
+        let state = self.originators.entry(msg.originator.clone()).or_default();
+        let bucket = state.bucket.get_or_insert(TokenBucket {
+            tokens: rate_limit.burst as f64,
+            last_refill_ms: msg.timestamp_ms,
+            suppressed: 0,
+        });
+        Self::refill(bucket, rate_limit, msg.timestamp_ms);
+
+        if bucket.tokens < 1.0 {
+            bucket.suppressed += 1;
+            stats.messages_suppressed += 1;
+            return vec![];
+        }
+
+        bucket.tokens -= 1.0;
+        let mut output: Vec<Message> = Vec::new();
+        if bucket.suppressed > 0 {
+            output.push(Self::suppression_summary(&msg.originator, bucket, msg.timestamp_ms));
+            bucket.suppressed = 0;
+        }
+        output.push(msg);
+        output
+    }
+}
+
+impl Stage for DedupStage {
+    fn process(&mut self, msg: Message, stats: &mut StreamStatistics) -> Vec<Message> {
+        let mut output: Vec<Message> = Vec::new();
+
+        if self.config.collapse_repeats {
+            let summary_period_ms = self.config.repeat_summary_period_ms as i64;
+            let state = self.originators.entry(msg.originator.clone()).or_default();
+
+            if let Some(run) = state.run.as_mut() {
+                if run.last.text == msg.text {
+                    if run.repeats == 0 {
+                        run.summary_due_ms = msg.timestamp_ms + summary_period_ms;
+                    }
+                    run.repeats += 1;
+                    run.last = msg;
+                    stats.messages_suppressed += 1;
+                    return output;
+                }
+                if run.repeats > 0 {
+                    output.push(Self::repeat_summary(run));
+                }
+            }
+
+            state.run = Some(RepeatRun { last: msg.clone(), repeats: 0, summary_due_ms: 0 });
+        }
+
+        // Summaries bypass the rate limit, only the original messages consume tokens.
+        output.extend(self.rate_limit(msg, stats));
+        output
+    }
+
+    fn tick(&mut self, now_ms: i64, _stats: &mut StreamStatistics) -> Vec<Message> {
+        let mut output: Vec<Message> = Vec::new();
+
+        for (originator, state) in self.originators.iter_mut() {
+            if let Some(run) = state.run.as_mut() {
+                if run.repeats > 0 && now_ms >= run.summary_due_ms {
+                    output.push(Self::repeat_summary(run));
+                    run.repeats = 0;
+                }
+            }
+
+            if let (Some(bucket), Some(rate_limit)) = (state.bucket.as_mut(), &self.config.rate_limit) {
+                Self::refill(bucket, rate_limit, now_ms);
+                if bucket.suppressed > 0 && bucket.tokens >= 1.0 {
+                    output.push(Self::suppression_summary(originator, bucket, now_ms));
+                    bucket.suppressed = 0;
+                }
+            }
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(timestamp_ms: i64, text: &str) -> Message {
+        Message::new(timestamp_ms, "Device".to_string(), text.to_string())
+    }
+
+    fn texts(msgs: &[Message]) -> Vec<String> {
+        msgs.iter().map(|m| m.text.clone()).collect()
+    }
+
+    #[test]
+    fn test_repeats_are_collapsed_into_summary() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = DedupStage::new(DedupStageConfig::new()).unwrap();
+
+        assert_eq!(texts(&stage.process(msg(0, "fault"), &mut stats)), vec!["fault"]);
+        assert!(stage.process(msg(1, "fault"), &mut stats).is_empty());
+        assert!(stage.process(msg(2, "fault"), &mut stats).is_empty());
+
+        let output = stage.process(msg(3, "recovered"), &mut stats);
+        assert_eq!(texts(&output), vec!["Last message repeated 2 times", "recovered"]);
+        assert_eq!(output[0].timestamp_ms, 2);
+        assert_eq!(stats.messages_suppressed, 2);
+    }
+
+    #[test]
+    fn test_repeat_summary_emitted_on_tick() {
+        let mut config = DedupStageConfig::new();
+        config.repeat_summary_period_ms = 100;
+        let mut stats = StreamStatistics::new();
+        let mut stage = DedupStage::new(config).unwrap();
+
+        stage.process(msg(0, "fault"), &mut stats);
+        stage.process(msg(10, "fault"), &mut stats);
+        assert!(stage.tick(50, &mut stats).is_empty());
+        assert_eq!(texts(&stage.tick(110, &mut stats)), vec!["Last message repeated 1 times"]);
+
+        // The run continues, but its repeats have already been reported.
+        assert!(stage.process(msg(120, "fault"), &mut stats).is_empty());
+        assert_eq!(texts(&stage.process(msg(130, "ok"), &mut stats)), vec!["Last message repeated 1 times", "ok"]);
+    }
+
+    #[test]
+    fn test_repeats_tracked_per_originator() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = DedupStage::new(DedupStageConfig::new()).unwrap();
+
+        stage.process(msg(0, "same"), &mut stats);
+        let other = Message::new(1, "Other".to_string(), "same".to_string());
+        assert_eq!(stage.process(other, &mut stats).len(), 1);
+    }
+
+    #[test]
+    fn test_rate_limit_suppresses_and_summarises() {
+        let mut config = DedupStageConfig::new();
+        config.collapse_repeats = false;
+        config.rate_limit = Some(RateLimitConfig { messages_per_second: 10, burst: 2 });
+        let mut stats = StreamStatistics::new();
+        let mut stage = DedupStage::new(config).unwrap();
+
+        assert_eq!(stage.process(msg(0, "a"), &mut stats).len(), 1);
+        assert_eq!(stage.process(msg(0, "b"), &mut stats).len(), 1);
+        assert!(stage.process(msg(0, "c"), &mut stats).is_empty());
+        assert!(stage.process(msg(50, "d"), &mut stats).is_empty());
+
+        // One token has been refilled after 100ms.
+        assert_eq!(texts(&stage.process(msg(100, "e"), &mut stats)), vec!["Rate limit exceeded, suppressed 2 messages", "e"]);
+        assert_eq!(stats.messages_suppressed, 2);
+    }
+
+    #[test]
+    fn test_rate_limit_summary_emitted_on_tick() {
+        let mut config = DedupStageConfig::new();
+        config.collapse_repeats = false;
+        config.rate_limit = Some(RateLimitConfig { messages_per_second: 10, burst: 1 });
+        let mut stats = StreamStatistics::new();
+        let mut stage = DedupStage::new(config).unwrap();
+
+        stage.process(msg(0, "a"), &mut stats);
+        stage.process(msg(0, "b"), &mut stats);
+        assert!(stage.tick(50, &mut stats).is_empty());
+        assert_eq!(texts(&stage.tick(100, &mut stats)), vec!["Rate limit exceeded, suppressed 1 messages"]);
+    }
+
+    #[test]
+    fn test_invalid_rate_limit_rejected() {
+        let mut config = DedupStageConfig::new();
+        config.rate_limit = Some(RateLimitConfig { messages_per_second: 0, burst: 1 });
+        assert!(DedupStage::new(config).is_err());
+    }
+}
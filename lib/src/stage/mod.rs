@@ -0,0 +1,133 @@
+/// Stages are optional processing steps that a `StreamCore` applies to messages as they pass through it.
+/// A stream has two pipelines: one for the messages it receives from other streams and one for the
+/// messages generated by its specialised stream.
+///
+/// Each stage receives messages one at a time and may drop, modify or add messages. Stages are also
+/// ticked on every iteration of the core thread so that time based behaviour (e.g. emitting a summary
+/// once a flood of messages has ended) does not depend on new messages arriving.
+use core::fmt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub mod boot_session_stage;
+pub mod dedup_stage;
+pub mod reorder_stage;
+pub mod sequence_stage;
+pub mod timestamp_stage;
+
+use boot_session_stage::{BootSessionStage, BootSessionStageConfig};
+use dedup_stage::{DedupStage, DedupStageConfig};
+use reorder_stage::{ReorderStage, ReorderStageConfig};
+use sequence_stage::{SequenceStage, SequenceStageConfig};
+use timestamp_stage::{TimestampStage, TimestampStageConfig};
+
+use crate::message::Message;
+use crate::stream_statistics::StreamStatistics;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `StageConfig` enum represents the different types of stage configurations that can be
+/// attached to a stream. Each variant contains a configuration struct specific to that stage type.
+///
+/// The available stage types are:
+/// - `Dedup`: Collapses repeated messages and rate limits each originator.
+/// - `Reorder`: Buffers messages for a latency window and releases them in timestamp order.
+/// - `Timestamp`: Extracts device-side timestamps from the message text.
+/// - `BootSession`: Detects device reboots and tags messages with a boot session number.
+/// - `Sequence`: Detects gaps and duplicates in a sequence counter carried by each message.
+pub enum StageConfig {
+    Dedup{config: DedupStageConfig},
+    Reorder{config: ReorderStageConfig},
+    Timestamp{config: TimestampStageConfig},
+    BootSession{config: BootSessionStageConfig},
+    Sequence{config: SequenceStageConfig},
+}
+
+impl fmt::Display for StageConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StageConfig::Dedup{..} => write!(f, "Dedup"),
+            StageConfig::Reorder{..} => write!(f, "Reorder"),
+            StageConfig::Timestamp{..} => write!(f, "Timestamp"),
+            StageConfig::BootSession{..} => write!(f, "BootSession"),
+            StageConfig::Sequence{..} => write!(f, "Sequence"),
+        }
+    }
+}
+
+/// The `Stage` trait defines a single processing step in a `StagePipeline`.
+pub trait Stage: Send {
+    /// Processes a single message, returning the messages to pass on to the next stage.
+    fn process(&mut self, msg: Message, stats: &mut StreamStatistics) -> Vec<Message>;
+
+    /// Called periodically with the current time in milliseconds since the EPOC, returning any
+    /// messages the stage wants to emit without having received a new message.
+    ///
+    /// Calling `tick` with `i64::MAX` must release everything the stage is holding back.
+    fn tick(&mut self, now_ms: i64, stats: &mut StreamStatistics) -> Vec<Message>;
+}
+
+/// Creates the stage described by the given `StageConfig`.
+///
+/// # Returns
+/// * `Ok(Box<dyn Stage>)` if the stage was successfully created.
+/// * `Err(String)` if the configuration is invalid.
+pub fn create_stage(config: &StageConfig) -> Result<Box<dyn Stage>, String> {
+    match config {
+        StageConfig::Dedup{config} => Ok(Box::new(DedupStage::new(config.clone())?)),
+        StageConfig::Reorder{config} => Ok(Box::new(ReorderStage::new(config.clone())?)),
+        StageConfig::Timestamp{config} => Ok(Box::new(TimestampStage::new(config.clone())?)),
+        StageConfig::BootSession{config} => Ok(Box::new(BootSessionStage::new(config.clone())?)),
+        StageConfig::Sequence{config} => Ok(Box::new(SequenceStage::new(config.clone())?)),
+    }
+}
+
+/// An ordered chain of stages. Messages leaving one stage are fed into the next.
+#[derive(Default)]
+pub struct StagePipeline {
+    stages: Vec<Box<dyn Stage>>
+}
+
+impl StagePipeline {
+    /// Creates a pipeline containing one stage per entry in `configs`, in the same order.
+    pub fn new(configs: &[StageConfig]) -> Result<Self, String> {
+        let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+        for config in configs {
+            stages.push(create_stage(config).map_err(|e| format!("Invalid {config} stage: {e}"))?);
+        }
+        Ok(StagePipeline { stages })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Passes a message through every stage of the pipeline.
+    pub fn process(&mut self, msg: Message, stats: &mut StreamStatistics) -> Vec<Message> {
+        Self::process_from(&mut self.stages, vec![msg], stats)
+    }
+
+    /// Ticks every stage of the pipeline. Messages emitted by a stage are passed through the
+    /// stages that follow it.
+    pub fn tick(&mut self, now_ms: i64, stats: &mut StreamStatistics) -> Vec<Message> {
+        let mut output: Vec<Message> = Vec::new();
+        for index in 0..self.stages.len() {
+            let emitted = self.stages[index].tick(now_ms, stats);
+            if !emitted.is_empty() {
+                output.extend(Self::process_from(&mut self.stages[index + 1..], emitted, stats));
+            }
+        }
+        output
+    }
+
+    /// Releases every message held back by the stages, e.g. when the stream is stopping.
+    pub fn flush(&mut self, stats: &mut StreamStatistics) -> Vec<Message> {
+        self.tick(i64::MAX, stats)
+    }
+
+    fn process_from(stages: &mut [Box<dyn Stage>], mut msgs: Vec<Message>, stats: &mut StreamStatistics) -> Vec<Message> {
+        for stage in stages.iter_mut() {
+            msgs = msgs.into_iter().flat_map(|msg| stage.process(msg, stats)).collect();
+        }
+        msgs
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use super::Stage;
+use crate::message::Message;
+use crate::stream_statistics::StreamStatistics;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `ReorderStageConfig` struct configures a `ReorderStage`.
+///
+/// - `latency_window_ms`: How long a message is held back, waiting for older messages from other sources.
+pub struct ReorderStageConfig {
+    pub latency_window_ms: u64,
+}
+
+impl ReorderStageConfig {
+    pub fn new() -> Self {
+        ReorderStageConfig { latency_window_ms: 100 }
+    }
+}
+
+impl Default for ReorderStageConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Buffers messages for a latency window and releases them sorted by `timestamp_ms`.
+///
+/// Messages arriving with a timestamp older than the last released message cannot be put back in
+/// order. They are released immediately and counted in `StreamStatistics::late_messages`.
+pub struct ReorderStage {
+    config: ReorderStageConfig,
+    // Keyed by timestamp and arrival order, so messages with equal timestamps keep their arrival order.
+    pending: BTreeMap<(i64, u64), Message>,
+    arrival_counter: u64,
+    last_released_ms: Option<i64>,
+}
+
+impl ReorderStage {
+    pub fn new(config: ReorderStageConfig) -> Result<Self, String> {
+        Ok(ReorderStage {
+            config,
+            pending: BTreeMap::new(),
+            arrival_counter: 0,
+            last_released_ms: None,
+        })
+    }
+}
+
+impl Stage for ReorderStage {
+    fn process(&mut self, msg: Message, stats: &mut StreamStatistics) -> Vec<Message> {
+        if let Some(last_released_ms) = self.last_released_ms {
+            if msg.timestamp_ms < last_released_ms {
+                stats.late_messages += 1;
+                return vec![msg];
+            }
+        }
+
+        self.arrival_counter += 1;
+        self.pending.insert((msg.timestamp_ms, self.arrival_counter), msg);
+        vec![]
+    }
+
+    fn tick(&mut self, now_ms: i64, _stats: &mut StreamStatistics) -> Vec<Message> {
+        let release_before_ms = now_ms.saturating_sub(self.config.latency_window_ms as i64);
+        let mut output: Vec<Message> = Vec::new();
+
+        while let Some(entry) = self.pending.first_entry() {
+            if entry.key().0 > release_before_ms {
+                break;
+            }
+            let msg = entry.remove();
+            self.last_released_ms = Some(msg.timestamp_ms);
+            output.push(msg);
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(timestamp_ms: i64, originator: &str) -> Message {
+        Message::new(timestamp_ms, originator.to_string(), format!("{originator} at {timestamp_ms}"))
+    }
+
+    #[test]
+    fn test_messages_released_in_timestamp_order() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = ReorderStage::new(ReorderStageConfig { latency_window_ms: 100 }).unwrap();
+
+        assert!(stage.process(msg(1010, "Host"), &mut stats).is_empty());
+        assert!(stage.process(msg(1000, "Coprocessor"), &mut stats).is_empty());
+        assert!(stage.process(msg(1005, "Host"), &mut stats).is_empty());
+
+        // Nothing is old enough to be released yet.
+        assert!(stage.tick(1050, &mut stats).is_empty());
+
+        let released: Vec<i64> = stage.tick(1108, &mut stats).iter().map(|m| m.timestamp_ms).collect();
+        assert_eq!(released, vec![1000, 1005]);
+
+        let released: Vec<i64> = stage.tick(1110, &mut stats).iter().map(|m| m.timestamp_ms).collect();
+        assert_eq!(released, vec![1010]);
+        assert_eq!(stats.late_messages, 0);
+    }
+
+    #[test]
+    fn test_equal_timestamps_keep_arrival_order() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = ReorderStage::new(ReorderStageConfig { latency_window_ms: 0 }).unwrap();
+
+        stage.process(msg(5, "First"), &mut stats);
+        stage.process(msg(5, "Second"), &mut stats);
+
+        let released: Vec<String> = stage.tick(5, &mut stats).into_iter().map(|m| m.originator).collect();
+        assert_eq!(released, vec!["First", "Second"]);
+    }
+
+    #[test]
+    fn test_late_messages_are_counted_and_passed_through() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = ReorderStage::new(ReorderStageConfig { latency_window_ms: 10 }).unwrap();
+
+        stage.process(msg(100, "Host"), &mut stats);
+        assert_eq!(stage.tick(200, &mut stats).len(), 1);
+
+        let output = stage.process(msg(90, "Coprocessor"), &mut stats);
+        assert_eq!(output.len(), 1);
+        assert_eq!(stats.late_messages, 1);
+    }
+
+    #[test]
+    fn test_flush_releases_everything() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = ReorderStage::new(ReorderStageConfig { latency_window_ms: 60000 }).unwrap();
+
+        stage.process(msg(2, "Host"), &mut stats);
+        stage.process(msg(1, "Host"), &mut stats);
+        assert_eq!(stage.tick(i64::MAX, &mut stats).len(), 2);
+    }
+}
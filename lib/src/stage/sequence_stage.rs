@@ -0,0 +1,274 @@
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use super::Stage;
+use crate::message::Message;
+use crate::stream_statistics::StreamStatistics;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `SequenceSource` enum describes where a `SequenceStage` finds the sequence counter of a message.
+///
+/// - `Regex`: A regular expression whose named group `sequence`, or else first group, captures the counter.
+///   Decimal and `0x` prefixed hexadecimal counters are supported.
+/// - `BinaryHeader`: An unsigned integer of `length` bytes (1 to 8) starting at byte `offset` of the message.
+///   Sources decode their input as UTF-8, replacing invalid bytes, so only headers that are valid UTF-8, e.g. ASCII
+///   counter bytes below 0x80, can be read. Messages whose header was replaced are treated as having no counter.
+pub enum SequenceSource {
+    Regex{pattern: String},
+    BinaryHeader{offset: usize, length: usize, little_endian: bool},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `SequenceStageConfig` struct configures a `SequenceStage`.
+///
+/// - `source`: Where the sequence counter is found.
+/// - `modulus`: The value at which the counter wraps around to zero, e.g. `65536` for a 16 bit counter.
+///   Zero means the counter never wraps.
+pub struct SequenceStageConfig {
+    pub source: SequenceSource,
+    pub modulus: u64,
+}
+
+impl SequenceStageConfig {
+    pub fn new() -> Self {
+        SequenceStageConfig {
+            source: SequenceSource::Regex{pattern: String::from(r"seq=(?P<sequence>\d+)")},
+            modulus: 0,
+        }
+    }
+}
+
+impl Default for SequenceStageConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Verifies the rolling sequence counter printed on every line by a device.
+///
+/// A warning is emitted ahead of any message whose counter does not follow the previous one:
+/// - Gaps are counted in `StreamStatistics::lost_messages`.
+/// - Repeated counters are counted in `StreamStatistics::duplicate_messages`.
+/// - Counters jumping backwards by more than half the counter range are reported as a reset, e.g. after a reboot.
+///
+/// Counters wrapping around at `modulus` are not reported.
+pub struct SequenceStage {
+    config: SequenceStageConfig,
+    regex: Option<Regex>,
+    last_sequence: Option<u64>,
+}
+
+impl SequenceStage {
+    pub fn new(config: SequenceStageConfig) -> Result<Self, String> {
+        let regex = match &config.source {
+            SequenceSource::Regex{pattern} => Some(Regex::new(pattern).map_err(|e| e.to_string())?),
+            SequenceSource::BinaryHeader{length, ..} => {
+                if *length == 0 || *length > 8 {
+                    return Err("BinaryHeader length must be between 1 and 8 bytes".to_string());
+                }
+                None
+            }
+        };
+        Ok(SequenceStage { config, regex, last_sequence: None })
+    }
+
+    fn extract_sequence(&self, msg: &Message) -> Option<u64> {
+        match &self.config.source {
+            SequenceSource::Regex{..} => {
+                let captures = self.regex.as_ref()?.captures(&msg.text)?;
+                let sequence = captures.name("sequence").or_else(|| captures.get(1))?.as_str();
+                match sequence.strip_prefix("0x").or_else(|| sequence.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                    None => sequence.parse().ok(),
+                }
+            },
+            SequenceSource::BinaryHeader{offset, length, little_endian} => {
+                let end = offset.checked_add(*length)?;
+                let bytes = msg.text.as_bytes().get(*offset..end)?;
+                if msg.text.char_indices().take_while(|(index, _)| *index < end).any(|(_, c)| c == char::REPLACEMENT_CHARACTER) {
+                    return None;
+                }
+                let fold = |value: u64, byte: &u8| (value << 8) | *byte as u64;
+                if *little_endian {
+                    Some(bytes.iter().rev().fold(0, fold))
+                } else {
+                    Some(bytes.iter().fold(0, fold))
+                }
+            },
+        }
+    }
+
+    /// The forward distance from `from` to `to`, taking wraparound into account.
+    fn distance(&self, from: u64, to: u64) -> u64 {
+        if self.config.modulus == 0 {
+            to.wrapping_sub(from)
+        } else {
+            let (from, to) = (from % self.config.modulus, to % self.config.modulus);
+            if to >= from { to - from } else { self.config.modulus - (from - to) }
+        }
+    }
+
+    fn check(&self, last: u64, sequence: u64, stats: &mut StreamStatistics) -> Option<String> {
+        let distance = self.distance(last, sequence);
+        let half_range = if self.config.modulus == 0 { u64::MAX / 2 } else { self.config.modulus / 2 };
+
+        if distance == 1 {
+            None
+        } else if distance == 0 {
+            stats.duplicate_messages += 1;
+            Some(format!("Sequence duplicate: {sequence} repeated"))
+        } else if distance > half_range {
+            Some(format!("Sequence reset: {last} followed by {sequence}"))
+        } else {
+            let lost = distance - 1;
+            stats.lost_messages += lost;
+            Some(format!("Sequence gap: {last} followed by {sequence}, {lost} lines lost"))
+        }
+    }
+}
+
+impl Stage for SequenceStage {
+    fn process(&mut self, msg: Message, stats: &mut StreamStatistics) -> Vec<Message> {
+        let Some(sequence) = self.extract_sequence(&msg) else {
+            // A binary header replaced by the text decoding hides the counter, the next one cannot be compared to
+            // the last one seen.
+            if matches!(self.config.source, SequenceSource::BinaryHeader{..}) && msg.text.contains(char::REPLACEMENT_CHARACTER) {
+                self.last_sequence = None;
+            }
+            return vec![msg];
+        };
+
+        let mut output: Vec<Message> = Vec::new();
+        if let Some(last) = self.last_sequence {
+            if let Some(warning) = self.check(last, sequence, stats) {
+                let mut warning = Message::new_warning(msg.timestamp_ms, msg.originator.clone(), warning);
+                warning.boot_session = msg.boot_session;
+                output.push(warning);
+            }
+        }
+
+        self.last_sequence = Some(sequence);
+        output.push(msg);
+        output
+    }
+
+    fn tick(&mut self, _now_ms: i64, _stats: &mut StreamStatistics) -> Vec<Message> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageKind;
+
+    fn msg(text: &str) -> Message {
+        Message::new(0, "Device".to_string(), text.to_string())
+    }
+
+    fn process_all(stage: &mut SequenceStage, texts: &[&str], stats: &mut StreamStatistics) -> Vec<Message> {
+        texts.iter().flat_map(|text| stage.process(msg(text), stats)).collect()
+    }
+
+    #[test]
+    fn test_consecutive_sequence_passes() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = SequenceStage::new(SequenceStageConfig::new()).unwrap();
+
+        let output = process_all(&mut stage, &["seq=1 a", "seq=2 b", "no counter", "seq=3 c"], &mut stats);
+        assert_eq!(output.len(), 4);
+        assert_eq!(stats.lost_messages, 0);
+    }
+
+    #[test]
+    fn test_gap_reported_and_counted() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = SequenceStage::new(SequenceStageConfig::new()).unwrap();
+
+        let output = process_all(&mut stage, &["seq=1 a", "seq=5 b"], &mut stats);
+        assert_eq!(output.len(), 3);
+        assert_eq!(output[1].kind, MessageKind::Warning);
+        assert_eq!(output[1].text, "Sequence gap: 1 followed by 5, 3 lines lost");
+        assert_eq!(stats.lost_messages, 3);
+    }
+
+    #[test]
+    fn test_duplicate_reported_and_counted() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = SequenceStage::new(SequenceStageConfig::new()).unwrap();
+
+        let output = process_all(&mut stage, &["seq=7 a", "seq=7 a"], &mut stats);
+        assert_eq!(output[1].text, "Sequence duplicate: 7 repeated");
+        assert_eq!(stats.duplicate_messages, 1);
+    }
+
+    #[test]
+    fn test_wraparound_and_reset() {
+        let mut stats = StreamStatistics::new();
+        let config = SequenceStageConfig {
+            source: SequenceSource::Regex{pattern: String::from(r"^\[(0x[0-9a-f]+)\]")},
+            modulus: 256,
+        };
+        let mut stage = SequenceStage::new(config).unwrap();
+
+        let output = process_all(&mut stage, &["[0xfe]", "[0xff]", "[0x00]", "[0x01]"], &mut stats);
+        assert_eq!(output.len(), 4);
+
+        let output = process_all(&mut stage, &["[0x80]", "[0x02]"], &mut stats);
+        assert_eq!(output[0].text, "Sequence gap: 1 followed by 128, 126 lines lost");
+        assert_eq!(output[2].text, "Sequence reset: 128 followed by 2");
+        assert_eq!(stats.lost_messages, 126);
+    }
+
+    #[test]
+    fn test_binary_header() {
+        let mut stats = StreamStatistics::new();
+        let config = SequenceStageConfig {
+            source: SequenceSource::BinaryHeader{offset: 1, length: 2, little_endian: true},
+            modulus: 65536,
+        };
+        let mut stage = SequenceStage::new(config).unwrap();
+
+        let output = process_all(&mut stage, &["#\x01\x00data", "#\x03\x00data"], &mut stats);
+        assert_eq!(output[1].text, "Sequence gap: 1 followed by 3, 1 lines lost");
+    }
+
+    #[test]
+    fn test_binary_header_replaced_by_decoding_ignored() {
+        let mut stats = StreamStatistics::new();
+        let config = SequenceStageConfig {
+            source: SequenceSource::BinaryHeader{offset: 0, length: 1, little_endian: false},
+            modulus: 256,
+        };
+        let mut stage = SequenceStage::new(config).unwrap();
+
+        // Counters from 0x80 reach the stage as U+FFFD, they are neither read nor reported as gaps.
+        let texts: Vec<String> = [0x7e, 0x7f, 0x80, 0xff, 0x00, 0x01].iter().map(|&byte| String::from_utf8_lossy(&[byte, b'x']).to_string()).collect();
+        let output = process_all(&mut stage, &texts.iter().map(String::as_str).collect::<Vec<&str>>(), &mut stats);
+        assert!(output.iter().all(|msg| msg.kind == MessageKind::Data));
+        assert_eq!(stats.lost_messages, 0);
+    }
+
+    #[test]
+    fn test_large_modulus_and_offset() {
+        let mut stats = StreamStatistics::new();
+        let config = SequenceStageConfig { source: SequenceSource::Regex{pattern: String::from(r"seq=(\d+)")}, modulus: u64::MAX };
+        let mut stage = SequenceStage::new(config).unwrap();
+        let output = process_all(&mut stage, &[&format!("seq={}", u64::MAX - 1), "seq=0", "seq=2"], &mut stats);
+        assert_eq!(output.len(), 4);
+        assert_eq!(output[2].text, "Sequence gap: 0 followed by 2, 1 lines lost");
+
+        let config = SequenceStageConfig { source: SequenceSource::BinaryHeader{offset: usize::MAX, length: 2, little_endian: false}, modulus: 0 };
+        let mut stage = SequenceStage::new(config).unwrap();
+        assert_eq!(stage.process(msg("data"), &mut stats), vec![msg("data")]);
+    }
+
+    #[test]
+    fn test_invalid_binary_header_rejected() {
+        let config = SequenceStageConfig {
+            source: SequenceSource::BinaryHeader{offset: 0, length: 9, little_endian: false},
+            modulus: 0,
+        };
+        assert!(SequenceStage::new(config).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use super::Stage;
+use crate::message::Message;
+use crate::stream_statistics::StreamStatistics;
+
+/// The default pattern for Linux kernel style uptime prefixes, e.g. `[   12.345678] `.
+pub const UPTIME_PATTERN: &str = r"^\[\s*(?P<timestamp>[0-9]+(?:\.[0-9]+)?)\]\s?";
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `TimestampFormat` enum describes how the text captured by a `TimestampStage` is interpreted.
+///
+/// - `Uptime`: Seconds (with optional fraction) since the device booted.
+/// - `DateTime`: A wall-clock time parsed with a strptime-like `chrono` format string, e.g. `%Y-%m-%d %H:%M:%S%.3f`.
+///   Formats containing only a time of day are combined with the date the message was received.
+pub enum TimestampFormat {
+    Uptime,
+    DateTime{format: String, utc: bool},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `TimestampStageConfig` struct configures a `TimestampStage`.
+///
+/// - `pattern`: A regular expression locating the timestamp in the message text. The named group `timestamp`,
+///   or else the first group, or else the whole match is used as the timestamp.
+/// - `format`: How the captured timestamp is interpreted.
+/// - `strip_timestamp`: Remove the text matched by `pattern` from the message.
+pub struct TimestampStageConfig {
+    pub pattern: String,
+    pub format: TimestampFormat,
+    pub strip_timestamp: bool,
+}
+
+impl TimestampStageConfig {
+    pub fn new() -> Self {
+        TimestampStageConfig {
+            pattern: String::from(UPTIME_PATTERN),
+            format: TimestampFormat::Uptime,
+            strip_timestamp: false,
+        }
+    }
+}
+
+impl Default for TimestampStageConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Extracts device-side timestamps from message text.
+///
+/// The receive time is kept in `Message::timestamp_ms` and the device time, mapped to wall-clock time, is stored in
+/// `Message::device_timestamp_ms`.
+///
+/// In uptime mode the device uptime is anchored to the receive time of the first timestamped message. If the uptime
+/// goes backwards the device is assumed to have rebooted, the uptime is re-anchored and the reboot is counted in
+/// `StreamStatistics::device_reboots`.
+pub struct TimestampStage {
+    config: TimestampStageConfig,
+    regex: Regex,
+    uptime_anchor_ms: Option<i64>,
+    last_uptime_ms: Option<i64>,
+}
+
+impl TimestampStage {
+    pub fn new(config: TimestampStageConfig) -> Result<Self, String> {
+        let regex = Regex::new(&config.pattern).map_err(|e| e.to_string())?;
+        Ok(TimestampStage { config, regex, uptime_anchor_ms: None, last_uptime_ms: None })
+    }
+
+    /// Parses an uptime in seconds, e.g. `12.345678`, into milliseconds. Patterns may capture any text, so text other
+    /// than ASCII digits is rejected rather than assumed.
+    fn parse_uptime_ms(text: &str) -> Option<i64> {
+        let (seconds, fraction) = text.split_once('.').unwrap_or((text, ""));
+        if !seconds.bytes().chain(fraction.bytes()).all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        let seconds: i64 = seconds.parse().ok()?;
+        let milliseconds: i64 = fraction.chars().chain(std::iter::repeat('0')).take(3).collect::<String>().parse().ok()?;
+        seconds.checked_mul(1000)?.checked_add(milliseconds)
+    }
+
+    /// Parses a wall-clock time with the given format, falling back to a time of day on the receive date.
+    fn parse_datetime_ms(text: &str, format: &str, utc: bool, received_ms: i64) -> Option<i64> {
+        let naive = match NaiveDateTime::parse_from_str(text, format) {
+            Ok(naive) => naive,
+            Err(_) => {
+                let time = NaiveTime::parse_from_str(text, format).ok()?;
+                let date: NaiveDate = if utc {
+                    Utc.timestamp_millis_opt(received_ms).single()?.date_naive()
+                } else {
+                    Local.timestamp_millis_opt(received_ms).single()?.date_naive()
+                };
+                date.and_time(time)
+            }
+        };
+
+        if utc {
+            Some(Utc.from_utc_datetime(&naive).timestamp_millis())
+        } else {
+            Local.from_local_datetime(&naive).earliest().map(|dt| dt.timestamp_millis())
+        }
+    }
+
+    fn apply_uptime(&mut self, msg: &mut Message, uptime_ms: i64, stats: &mut StreamStatistics) {
+        if let Some(last_uptime_ms) = self.last_uptime_ms {
+            if uptime_ms < last_uptime_ms {
+                stats.device_reboots += 1;
+                self.uptime_anchor_ms = None;
+            }
+        }
+
+        let anchor_ms = *self.uptime_anchor_ms.get_or_insert(msg.timestamp_ms - uptime_ms);
+        self.last_uptime_ms = Some(uptime_ms);
+        msg.device_uptime_ms = Some(uptime_ms);
+        msg.device_timestamp_ms = Some(anchor_ms + uptime_ms);
+    }
+}
+
+impl Stage for TimestampStage {
+    fn process(&mut self, mut msg: Message, stats: &mut StreamStatistics) -> Vec<Message> {
+        let Some(captures) = self.regex.captures(&msg.text) else {
+            return vec![msg];
+        };
+
+        let whole_match = captures.get(0).expect("Capture group 0 is always present");
+        let timestamp = captures.name("timestamp")
+            .or_else(|| captures.get(1))
+            .unwrap_or(whole_match)
+            .as_str()
+            .trim()
+            .to_string();
+        let matched_range = whole_match.range();
+
+        match &self.config.format {
+            TimestampFormat::Uptime => {
+                if let Some(uptime_ms) = Self::parse_uptime_ms(&timestamp) {
+                    self.apply_uptime(&mut msg, uptime_ms, stats);
+                }
+            },
+            TimestampFormat::DateTime{format, utc} => {
+                msg.device_timestamp_ms = Self::parse_datetime_ms(&timestamp, format, *utc, msg.timestamp_ms);
+            },
+        }
+
+        if self.config.strip_timestamp && msg.device_timestamp_ms.is_some() {
+            msg.text.replace_range(matched_range, "");
+        }
+
+        vec![msg]
+    }
+
+    fn tick(&mut self, _now_ms: i64, _stats: &mut StreamStatistics) -> Vec<Message> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(timestamp_ms: i64, text: &str) -> Message {
+        Message::new(timestamp_ms, "Device".to_string(), text.to_string())
+    }
+
+    #[test]
+    fn test_uptime_anchored_to_first_message() {
+        let mut stats = StreamStatistics::new();
+        let mut config = TimestampStageConfig::new();
+        config.strip_timestamp = true;
+        let mut stage = TimestampStage::new(config).unwrap();
+
+        let first = stage.process(msg(100_000, "[   12.345678] booting"), &mut stats).remove(0);
+        assert_eq!(first.text, "booting");
+        assert_eq!(first.timestamp_ms, 100_000);
+        assert_eq!(first.device_uptime_ms, Some(12_345));
+        assert_eq!(first.device_timestamp_ms, Some(100_000));
+
+        // The receive time is late, but the device time follows the device's uptime.
+        let second = stage.process(msg(100_900, "[   12.845000] ready"), &mut stats).remove(0);
+        assert_eq!(second.device_timestamp_ms, Some(100_500));
+        assert_eq!(second.timestamp_ms, 100_900);
+    }
+
+    #[test]
+    fn test_uptime_reset_detected_as_reboot() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = TimestampStage::new(TimestampStageConfig::new()).unwrap();
+
+        stage.process(msg(100_000, "[   50.000000] running"), &mut stats);
+        let rebooted = stage.process(msg(200_000, "[    0.500000] booting"), &mut stats).remove(0);
+
+        assert_eq!(stats.device_reboots, 1);
+        assert_eq!(rebooted.device_timestamp_ms, Some(200_000));
+        assert_eq!(rebooted.text, "[    0.500000] booting");
+    }
+
+    #[test]
+    fn test_messages_without_timestamp_pass_unchanged() {
+        let mut stats = StreamStatistics::new();
+        let mut stage = TimestampStage::new(TimestampStageConfig::new()).unwrap();
+
+        let output = stage.process(msg(1, "no timestamp"), &mut stats);
+        assert_eq!(output, vec![msg(1, "no timestamp")]);
+    }
+
+    #[test]
+    fn test_non_ascii_digits_ignored() {
+        let mut stats = StreamStatistics::new();
+        let config = TimestampStageConfig { pattern: String::from(r"^\[(?P<timestamp>\d+\.\d+)\]"), ..TimestampStageConfig::new() };
+        let mut stage = TimestampStage::new(config).unwrap();
+
+        let output = stage.process(msg(1, "[12.\u{661}\u{662}] arabic-indic digits"), &mut stats).remove(0);
+        assert_eq!(output.device_uptime_ms, None);
+        let output = stage.process(msg(1, "[99999999999999999999.5] too large"), &mut stats).remove(0);
+        assert_eq!(output.device_uptime_ms, None);
+        let output = stage.process(msg(1, "[12.5] ascii digits"), &mut stats).remove(0);
+        assert_eq!(output.device_uptime_ms, Some(12_500));
+    }
+
+    #[test]
+    fn test_datetime_format() {
+        let mut stats = StreamStatistics::new();
+        let config = TimestampStageConfig {
+            pattern: String::from(r"^(\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}\.\d{3}) "),
+            format: TimestampFormat::DateTime{format: String::from("%Y-%m-%d %H:%M:%S%.3f"), utc: true},
+            strip_timestamp: true,
+        };
+        let mut stage = TimestampStage::new(config).unwrap();
+
+        let output = stage.process(msg(0, "2024-01-02 03:04:05.678 rtc line"), &mut stats).remove(0);
+        assert_eq!(output.device_timestamp_ms, Some(1_704_164_645_678));
+        assert_eq!(output.text, "rtc line");
+        assert_eq!(output.device_uptime_ms, None);
+    }
+
+    #[test]
+    fn test_time_of_day_uses_receive_date() {
+        let mut stats = StreamStatistics::new();
+        let config = TimestampStageConfig {
+            pattern: String::from(r"^(\d{2}:\d{2}:\d{2})"),
+            format: TimestampFormat::DateTime{format: String::from("%H:%M:%S"), utc: true},
+            strip_timestamp: false,
+        };
+        let mut stage = TimestampStage::new(config).unwrap();
+
+        // 2024-01-02 12:00:00 UTC
+        let output = stage.process(msg(1_704_196_800_000, "03:04:05 line"), &mut stats).remove(0);
+        assert_eq!(output.device_timestamp_ms, Some(1_704_164_645_000));
+    }
+
+    #[test]
+    fn test_invalid_pattern_rejected() {
+        let mut config = TimestampStageConfig::new();
+        config.pattern = String::from("[unclosed");
+        assert!(TimestampStage::new(config).is_err());
+    }
+}
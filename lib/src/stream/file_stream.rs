@@ -1,5 +1,6 @@
 use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc}, thread::{self, JoinHandle}, time::Duration};
 use chrono::{Local, TimeZone, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use crate::stream::INTERNAL_STREAM_TICK_MS;
@@ -8,14 +9,29 @@ use std::fs::File;
 use std::io::Write;
 
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Default)]
+/// The `FileFormat` enum selects how a `FileStream` writes messages.
+///
+/// - `Text`: A human readable line per message, preceded by a header line with the stream name.
+/// - `Capture`: A JSON line per message, preserving every field so that the file can be replayed by a `ReplayStream`.
+pub enum FileFormat {
+    #[default]
+    Text,
+    Capture,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub struct FileStreamConfig {
     pub file_path: String,
+    #[serde(default)]
+    pub new_file_per_session: bool, // Start a new file whenever the boot session of the messages changes.
+    #[serde(default)]
+    pub format: FileFormat,
 }
 
 impl FileStreamConfig {
     pub fn new(file_name: String) -> Self {
-        FileStreamConfig {file_path: file_name}
+        FileStreamConfig {file_path: file_name, new_file_per_session: false, format: FileFormat::Text}
     }
 }
 
@@ -34,41 +50,47 @@ impl Stream for FileStream {
         let stream_name = self.config.name.clone();
         let receiver: Receiver<Message> = self.new_message_received_receiver.take().expect("Receiver unavailable");
         let file_path: String;
-        let full_file_path: String;
+        let new_file_per_session: bool;
+        let format: FileFormat;
+        let mut current_session: Option<u32> = None;
         let stop_requested = Arc::clone(&self.thread_stop_requsted);
 
         if let StreamTypeConfig::File {config} = &self.config.type_config {
             file_path = config.file_path.clone();
+            new_file_per_session = config.new_file_per_session;
+            format = config.format.clone();
         }
         else{
             todo!("Handle this error");
         }
 
-        let datetime = Local.timestamp_millis_opt(Utc::now().timestamp_millis());
-        let formatted_datetime = datetime.single().map(|dt| dt.format("%Y-%m-%d_%H%M%S").to_string()).unwrap_or_else(|| "Invalid timestamp".to_string());
-        full_file_path = format!("{formatted_datetime}_{file_path}");
-        
         println!("'{}' - FileStream starting thread", stream_name);
-        let mut file = match File::create(full_file_path.clone()) {
-            Ok(file) => file,
-            Err(e) => panic!("Error opening file: {}", e),
+        // With a file per session the first file is only created once the first message shows which session it belongs to.
+        let mut file: Option<File> = if new_file_per_session {
+            None
+        } else {
+            match Self::create_log_file(&stream_name, &file_path, None, &format) {
+                Ok(file) => Some(file),
+                Err(e) => panic!("Error opening file: {}", e),
+            }
         };
-        println!("File opened: '{full_file_path}'");
-
-        let log_message = format!("'{stream_name}'");
-        writeln!(file, "{}", log_message).expect("Failed to write to file");
 
         self.thread_handle = Some(thread::spawn(move || loop {
 
             // Handle Message received from core
             while let Ok(msg) = receiver.try_recv() {
-                let datetime = Local.timestamp_millis_opt(msg.timestamp_ms);
-                let formatted_datetime = datetime.single().map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_else(|| "Invalid timestamp".to_string());
-                let ms = msg.timestamp_ms%1000;
-                let originator = msg.originator;
-                let text = msg.text;
-                let log_message = format!("'{originator}' - {formatted_datetime}:{ms:0>3} - '{text}'");
-                writeln!(file, "{}", log_message).expect("Failed to write to file");
+                if new_file_per_session && (file.is_none() || (msg.boot_session.is_some() && msg.boot_session != current_session)) {
+                    let tag = msg.boot_session.map(|session| format!("session{session}"));
+                    match Self::create_log_file(&stream_name, &file_path, tag.as_deref(), &format) {
+                        Ok(new_file) => file = Some(new_file),
+                        Err(e) => eprintln!("'{stream_name}' - Failed to start file for new session, continuing in current file: {e}"),
+                    }
+                    current_session = msg.boot_session;
+                }
+
+                if let Some(file) = file.as_mut() {
+                    writeln!(file, "{}", Self::format_message(&msg, &format)).expect("Failed to write to file");
+                }
             }
             
             thread::sleep(Duration::from_millis(INTERNAL_STREAM_TICK_MS));
@@ -80,7 +102,7 @@ impl Stream for FileStream {
             }
         }));
 
-        self.core.start()?;
+        self.core.start(&self.config)?;
         
         Ok(())
     }
@@ -122,6 +144,10 @@ impl Stream for FileStream {
         self.core.add_external_outputs(senders)
     }
 
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
+
 }
 
 impl FileStream {
@@ -141,4 +167,77 @@ impl FileStream {
             Err("Invalid type_config for a FileStream")
         }
     }
-}
\ No newline at end of file
+
+    /// Builds the path of a new log file by prefixing the file name with the current local date and time,
+    /// e.g. `logs/2024-01-02_030405_log.txt` for `logs/log.txt`. When a tag is given it is included as well,
+    /// e.g. `logs/2024-01-02_030405_session2_log.txt`.
+    pub fn timestamped_file_path(file_path: &str, tag: Option<&str>) -> String {
+        let datetime = Local.timestamp_millis_opt(Utc::now().timestamp_millis());
+        let formatted_datetime = datetime.single().map(|dt| dt.format("%Y-%m-%d_%H%M%S").to_string()).unwrap_or_else(|| "Invalid timestamp".to_string());
+        let (directory, file_name) = match file_path.rfind('/') {
+            Some(index) => file_path.split_at(index + 1),
+            None => ("", file_path),
+        };
+        match tag {
+            Some(tag) => format!("{directory}{formatted_datetime}_{tag}_{file_name}"),
+            None => format!("{directory}{formatted_datetime}_{file_name}"),
+        }
+    }
+
+    /// Creates a new log file. Text files start with the stream name as their header line.
+    pub(crate) fn create_log_file(stream_name: &str, file_path: &str, tag: Option<&str>, format: &FileFormat) -> Result<File, String> {
+        let full_file_path = Self::timestamped_file_path(file_path, tag);
+        let mut file = File::create(&full_file_path).map_err(|e| format!("{full_file_path}: {e}"))?;
+        println!("File opened: '{full_file_path}'");
+
+        if *format == FileFormat::Text {
+            writeln!(file, "'{stream_name}'").map_err(|e| format!("{full_file_path}: {e}"))?;
+        }
+        Ok(file)
+    }
+
+    /// Formats a message as a single line, without the line terminator, in the given file format.
+    pub fn format_message(msg: &Message, format: &FileFormat) -> String {
+        match format {
+            FileFormat::Text => {
+                let datetime = Local.timestamp_millis_opt(msg.timestamp_ms);
+                let formatted_datetime = datetime.single().map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_else(|| "Invalid timestamp".to_string());
+                let ms = msg.timestamp_ms%1000;
+                let originator = &msg.originator;
+                let text = &msg.text;
+                format!("'{originator}' - {formatted_datetime}:{ms:0>3} - '{text}'")
+            },
+            FileFormat::Capture => serde_json::to_string(msg).expect("Messages are always serialisable"),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_every_session_gets_a_tagged_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_path = directory.path().join("log.txt").to_str().unwrap().to_string();
+        let config = FileStreamConfig { new_file_per_session: true, ..FileStreamConfig::new(file_path) };
+        let mut stream = FileStream::new(StreamConfig { type_config: StreamTypeConfig::File { config }, ..StreamConfig::default() }).unwrap();
+        stream.start().unwrap();
+
+        let sender = stream.get_status().get_external_input_sender_clone();
+        for (session, text) in [(1, "first boot"), (2, "second boot")] {
+            let mut msg = Message::new(0, String::from("Device"), String::from(text));
+            msg.boot_session = Some(session);
+            sender.send(msg).unwrap();
+        }
+        thread::sleep(Duration::from_millis(200));
+        stream.stop().unwrap();
+
+        let mut file_names: Vec<String> = fs::read_dir(directory.path()).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        file_names.sort();
+        assert_eq!(file_names.len(), 2);
+        assert!(file_names[0].ends_with("_session1_log.txt"));
+        assert!(file_names[1].ends_with("_session2_log.txt"));
+        assert!(fs::read_to_string(directory.path().join(&file_names[0])).unwrap().contains("first boot"));
+    }
+}
@@ -9,8 +9,10 @@
 /// - Starting and stopping the stream's internal processing thread
 ///
 /// The `StreamCore` is designed to be used as the base implementation for various specialized
-/// stream types, such as serial, file, MQTT, terminal, UDP, and Waveforms I2C streams.
+/// stream types, such as serial, file, MQTT, terminal, UDP, Waveforms I2C, ring buffer, replay, triggered capture, rule engine and pseudo-terminal streams.
 use uuid::Uuid;
+use chrono::Utc;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread::{self, JoinHandle};
@@ -18,28 +20,41 @@ use std::time::Duration;
 use core::fmt;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 
 pub mod serial_stream;
+pub mod serial_bridge;
 pub mod file_stream;
 pub mod mqtt_stream;
+pub mod pty_stream;
+pub mod replay_stream;
+pub mod ring_buffer_stream;
+pub mod rule_engine_stream;
 pub mod terminal_stream;
+pub mod trigger_capture_stream;
 pub mod udp_stream;
 pub mod waveforms_i2c_stream;
 
 use serial_stream::SerialStreamConfig;
 use file_stream::FileStreamConfig;
 use mqtt_stream::MqttStreamConfig;
+use pty_stream::PtyStreamConfig;
+use replay_stream::ReplayStreamConfig;
+use ring_buffer_stream::{RingBufferHandle, RingBufferStreamConfig};
+use rule_engine_stream::RuleEngineStreamConfig;
 use terminal_stream::TerminalStreamConfig;
+use trigger_capture_stream::TriggerCaptureStreamConfig;
 use udp_stream::UdpStreamConfig;
 use waveforms_i2c_stream::WaveformsI2cStreamConfig;
 
 use crate::message::Message;
+use crate::stage::{StageConfig, StagePipeline};
+use crate::stream_statistics::StreamStatistics;
 
 pub const INTERNAL_STREAM_TICK_MS: u64 = 10; //Maximum internal TICK rate is 1000/HZ.
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 /// The `StreamTypeConfig` enum represents the different types of stream configurations
 /// that can be used in the system. Each variant of the enum contains a configuration
 /// struct specific to that stream type.
@@ -51,6 +66,11 @@ pub const INTERNAL_STREAM_TICK_MS: u64 = 10; //Maximum internal TICK rate is 100
 /// - `Terminal`: Represents a terminal stream configuration.
 /// - `Udp`: Represents a UDP stream configuration.
 /// - `WaveformsI2c`: Represents a Waveforms I2C stream configuration.
+/// - `RingBuffer`: Represents an in-memory ring buffer stream configuration.
+/// - `Replay`: Represents a capture replay stream configuration.
+/// - `TriggerCapture`: Represents a triggered capture stream configuration.
+/// - `RuleEngine`: Represents a rule engine stream configuration.
+/// - `Pty`: Represents a pseudo-terminal stream configuration.
 /// - `None`: Represents no stream configuration.
 pub enum StreamTypeConfig {
     Serial{config: SerialStreamConfig},
@@ -59,6 +79,11 @@ pub enum StreamTypeConfig {
     Terminal{config: TerminalStreamConfig},
     Udp{config: UdpStreamConfig},
     WaveformsI2c{config: WaveformsI2cStreamConfig},
+    RingBuffer{config: RingBufferStreamConfig},
+    Replay{config: ReplayStreamConfig},
+    TriggerCapture{config: TriggerCaptureStreamConfig},
+    RuleEngine{config: RuleEngineStreamConfig},
+    Pty{config: PtyStreamConfig},
     None
 }
 
@@ -76,28 +101,45 @@ impl fmt::Display for StreamTypeConfig {
             StreamTypeConfig::Terminal{..} => write!(f, "Terminal"),
             StreamTypeConfig::Udp{..} => write!(f, "Udp"),
             StreamTypeConfig::WaveformsI2c{..} => write!(f, "WaveformsI2c"),
+            StreamTypeConfig::RingBuffer{..} => write!(f, "RingBuffer"),
+            StreamTypeConfig::Replay{..} => write!(f, "Replay"),
+            StreamTypeConfig::TriggerCapture{..} => write!(f, "TriggerCapture"),
+            StreamTypeConfig::RuleEngine{..} => write!(f, "RuleEngine"),
+            StreamTypeConfig::Pty{..} => write!(f, "Pty"),
             StreamTypeConfig::None => write!(f, "None")
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 /// The `StreamConfig` struct represents the configuration for a stream in the system.
 /// It contains the following fields:
 ///
 /// - `uuid`: A unique identifier for the stream.
+/// - `id`: An optional unique, human-readable identifier, by which configuration files may refer to the stream.
+///   When a configuration file gives no `uuid`, it is derived from the `id`.
 /// - `name`: The name of the stream.
 /// - `input_filter`: A filter applied to incoming messages.
 /// - `type_config`: The type-specific configuration for the stream.
 /// - `message_delimiter`: The delimiter used to separate messages.
 /// - `output_streams`: A list of UUIDs for output streams that this stream sends messages to.
+/// - `input_stages`: Processing stages applied, in order, to the messages received from other streams.
+/// - `stages`: Processing stages applied, in order, to the messages generated by this stream.
 pub struct StreamConfig {
+    #[schemars(with = "Option<Uuid>")]
     pub uuid: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub name: String,
     pub input_filter: String,
     pub type_config: StreamTypeConfig,
     pub message_delimiter:String,
-    pub output_streams: Vec<Uuid>
+    #[schemars(with = "Vec<String>")]
+    pub output_streams: Vec<Uuid>,
+    #[serde(default)]
+    pub input_stages: Vec<StageConfig>,
+    #[serde(default)]
+    pub stages: Vec<StageConfig>
 }
 
 impl StreamConfig {
@@ -117,7 +159,7 @@ impl StreamConfig {
     /// A new `StreamConfig` instance with the provided parameters.
     pub fn new(uuid: Uuid, name: String, output_streams: Vec<Uuid>, input_filter: String, config:StreamTypeConfig, message_delimiter:String) -> StreamConfig{
         StreamConfig{
-            uuid, name, output_streams, input_filter, type_config: config, message_delimiter: message_delimiter
+            uuid, id: None, name, output_streams, input_filter, type_config: config, message_delimiter, input_stages: vec![], stages: vec![]
         }
     }
 
@@ -129,6 +171,32 @@ impl StreamConfig {
     pub fn add_output_stream(&mut self, output_stream: Uuid) {
         self.output_streams.push(output_stream);
     }
+
+    /// Appends a processing stage to the stages applied to messages generated by this stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - The configuration of the stage to add.
+    pub fn add_stage(&mut self, stage: StageConfig) {
+        self.stages.push(stage);
+    }
+
+    /// Returns the UUIDs of the streams this stream sends messages to directly, besides its output streams.
+    pub fn target_streams(&self) -> Vec<Uuid> {
+        match &self.type_config {
+            StreamTypeConfig::RuleEngine{config} => config.target_streams(),
+            _ => vec![],
+        }
+    }
+
+    /// Appends a processing stage to the stages applied to messages received from other streams.
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - The configuration of the stage to add.
+    pub fn add_input_stage(&mut self, stage: StageConfig) {
+        self.input_stages.push(stage);
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -165,6 +233,12 @@ pub struct StreamCore{
     // Sending Messages to external Streams.
     external_output_senders: Option<Vec<Sender<Message>>>,
 
+    // Sending Messages to the output Streams linked by the engine, which may be relinked while the stream runs.
+    linked_output_senders: Arc<Mutex<Vec<Sender<Message>>>>,
+
+    // Whether Messages received from external Streams are also forwarded to the outputs.
+    echo_received: bool,
+
     // Sending externally received Messages to the internal, specialised stream
     internal_output_sender: Sender<Message>,
     internal_output_receiver: Option<Receiver<Message>>,
@@ -173,6 +247,8 @@ pub struct StreamCore{
     internal_input_sender: Sender<Message>,
     internal_input_receiver: Option<Receiver<Message>>,
 
+    statistics: Arc<Mutex<StreamStatistics>>,
+
     thread_handle: Option<JoinHandle<()>>,
     thread_stop_requsted: Arc<AtomicBool>
 }
@@ -195,15 +271,25 @@ impl StreamCore{
             external_input_sender: tx_ext,
             external_input_receiver: Some(rx_ext),
             external_output_senders: Some(vec![]),
+            linked_output_senders: Arc::new(Mutex::new(vec![])),
+            echo_received: true,
             internal_output_sender: tx_int_output,
             internal_output_receiver: Some(rx_int_output),
             internal_input_sender: tx_int_input,
             internal_input_receiver: Some(rx_int_input),
+            statistics: Arc::new(Mutex::new(StreamStatistics::new())),
             thread_handle: Option::None,
             thread_stop_requsted: Arc::new(AtomicBool::new(false))
         }
     }
 
+    /// Sets whether the messages received from other streams are forwarded to the outputs as well as to the internal
+    /// stream, which is the default. Streams writing the messages they receive to a device turn it off, so that two of
+    /// them sending to each other do not pass the same messages back and forth forever.
+    pub fn set_echo_received(&mut self, echo_received: bool) {
+        self.echo_received = echo_received;
+    }
+
     /// Adds a new external output sender to the stream.
     ///
     /// This method allows adding an additional output channel to the stream, which can be used to forward messages
@@ -244,6 +330,17 @@ impl StreamCore{
         }
     }
     
+    /// Replaces the senders to the stream's linked output streams.
+    ///
+    /// Unlike the external outputs, the linked outputs may be replaced while the stream is running, so that the
+    /// engine can relink its streams without restarting them.
+    ///
+    /// # Arguments
+    /// * `senders` - The `Sender<Message>` instances of the stream's output streams.
+    pub fn set_linked_outputs(&self, senders: Vec<Sender<Message>>) {
+        *self.linked_output_senders.lock().expect("Linked outputs lock poisoned") = senders;
+    }
+
     /**
      * Used by the specialised stream to get a clone of the external message sender.
      */
@@ -273,54 +370,84 @@ impl StreamCore{
     /// spawns a new thread that continuously checks for incoming messages from the external and internal input
     /// receivers, processes them, and forwards them to the external output senders.
     ///
+    /// Messages received from other streams are passed through the stream's `input_stages` and messages generated by
+    /// the internal stream are passed through its `stages` before they are forwarded. When the stream is stopped, any
+    /// messages still held back by the stages are released.
+    ///
     /// The method returns `Ok(())` if the stream was successfully started, or an `Err(String)` if the stream was not
-    /// in the correct state to start or if any of the necessary components were unavailable.
-    pub fn start(&mut self) -> Result<(), String> {
+    /// in the correct state to start, if any of the necessary components were unavailable or if a stage could not be
+    /// created.
+    pub fn start(&mut self, config: &StreamConfig) -> Result<(), String> {
 
         if self.state != StreamState::Initialised {
             return Err(String::from("Stream not in correct state to start"))
         }
 
+        let mut input_stages: StagePipeline = StagePipeline::new(&config.input_stages)
+            .map_err(|e| format!("'{}' - {}", config.name, e))?;
+        let mut stages: StagePipeline = StagePipeline::new(&config.stages)
+            .map_err(|e| format!("'{}' - {}", config.name, e))?;
+
         let ext_receiver: Receiver<Message> = self.external_input_receiver.take().ok_or("External input receiver unavailable")?;
         let int_receiver: Receiver<Message> = self.internal_input_receiver.take().ok_or("Internal input receiver unavailable")?;
         let ext_outputs: Vec<Sender<Message>> = self.external_output_senders.take().ok_or("External output senders unavailable")?;
         let int_sender: Sender<Message> = self.internal_output_sender.clone();
+        let linked_outputs = Arc::clone(&self.linked_output_senders);
+        let echo_received = self.echo_received;
 
         let stop_requested = Arc::clone(&self.thread_stop_requsted);
+        let statistics = Arc::clone(&self.statistics);
 
         self.thread_handle = Some(thread::spawn(move || loop {
-            // Handle Message received from other Streams
-            while let Ok(msg) = ext_receiver.try_recv() {
-                // First we filter the messages
-                // Todo
-
-                // Forward the message to the internal, specialised stream
-                int_sender.send(msg.clone()).unwrap();
-                
-                // Next we forward the message to the external Streams.
-                for output in ext_outputs.iter() {
-                    // Forward the message to the external Streams
-                    output.send(msg.clone()).unwrap();
+            let stop = stop_requested.load(Ordering::Relaxed);
+            {
+                let mut stats = statistics.lock().expect("Statistics lock poisoned");
+                let linked_outputs = linked_outputs.lock().expect("Linked outputs lock poisoned");
+                let outputs: Vec<&Sender<Message>> = ext_outputs.iter().chain(linked_outputs.iter()).collect();
+                let received_outputs: &[&Sender<Message>] = if echo_received { &outputs } else { &[] };
+                let now_ms = if stop { i64::MAX } else { Utc::now().timestamp_millis() };
+
+                // Handle Message received from other Streams
+                while let Ok(msg) = ext_receiver.try_recv() {
+                    stats.messages_received += 1;
+
+                    // First we filter the messages
+                    // Todo
+
+                    // Then pass them through the stream's input stages
+                    for msg in input_stages.process(msg, &mut stats) {
+                        Self::forward_received_message(&int_sender, received_outputs, msg);
+                    }
                 }
-            }
-            
-            // Handle Messages received from the internal, specialised Stream
-            while let Ok(msg) = int_receiver.try_recv() {
-                // First we filter the messages
-                // Todo
-                
-                // Next we forward the message to external Streams
-                for output in ext_outputs.iter() {
-                    output.send(msg.clone()).unwrap();
+
+                // Handle Messages received from the internal, specialised Stream
+                while let Ok(msg) = int_receiver.try_recv() {
+                    stats.messages_generated += 1;
+
+                    // First we filter the messages
+                    // Todo
+
+                    // Then pass them through the stream's stages
+                    for msg in stages.process(msg, &mut stats) {
+                        Self::forward_generated_message(&outputs, msg);
+                    }
                 }
-            }
 
-            thread::sleep(Duration::from_millis(INTERNAL_STREAM_TICK_MS));
+                // Give the stages a chance to emit time based messages, releasing everything when stopping
+                for msg in input_stages.tick(now_ms, &mut stats) {
+                    Self::forward_received_message(&int_sender, received_outputs, msg);
+                }
+                for msg in stages.tick(now_ms, &mut stats) {
+                    Self::forward_generated_message(&outputs, msg);
+                }
+            }
 
             // Has stop been requested?
-            if stop_requested.load(Ordering::Relaxed) {
+            if stop {
                 break;
             }
+
+            thread::sleep(Duration::from_millis(INTERNAL_STREAM_TICK_MS));
         }));
 
         self.state = StreamState::Started;
@@ -331,6 +458,26 @@ impl StreamCore{
         self.await_thread_stop()
     }
 
+    /// Returns a snapshot of the stream's statistics.
+    pub fn get_statistics(&self) -> StreamStatistics {
+        self.statistics.lock().expect("Statistics lock poisoned").clone()
+    }
+
+    /// Forwards a message received from another stream to the internal, specialised stream and
+    /// then on to the external streams, unless echoing received messages is turned off. Messages are still forwarded once the internal stream's thread has exited.
+    fn forward_received_message(int_sender: &Sender<Message>, outputs: &[&Sender<Message>], msg: Message) {
+        let _ = int_sender.send(msg.clone());
+        Self::forward_generated_message(outputs, msg);
+    }
+
+    /// Forwards a message to the external streams. Streams that have stopped are skipped, they are unlinked
+    /// once they have been removed from the engine.
+    fn forward_generated_message(outputs: &[&Sender<Message>], msg: Message) {
+        for output in outputs.iter() {
+            let _ = output.send(msg.clone());
+        }
+    }
+
     fn await_thread_stop(&mut self) -> Result<(), String> {
         self.thread_stop_requsted.store(true, Ordering::Relaxed);
 
@@ -348,24 +495,41 @@ impl StreamCore{
 /// 
 /// This implementation sets the following default values:
 /// - `uuid`: A new version 4 UUID
+/// - `id`: `None`
 /// - `name`: An empty string
 /// - `output_streams`: An empty vector
 /// - `input_filter`: An empty string
 /// - `type_config`: `StreamTypeConfig::None`
 /// - `message_delimiter`: The newline character `"\n"`
+/// - `input_stages`: An empty vector
+/// - `stages`: An empty vector
 impl Default for StreamConfig{
     fn default() -> Self {
         Self {
             uuid: Uuid::new_v4(),
+            id: None,
             name: String::from(""),
             output_streams: vec![],
             input_filter: String::from(""),
             type_config: StreamTypeConfig::None,
-            message_delimiter: String::from("\n")
+            message_delimiter: String::from("\n"),
+            input_stages: vec![],
+            stages: vec![]
         }
     }
 }
 
+#[derive(Clone, Debug)]
+/// The `StreamTarget` struct holds the senders through which a stream sends messages directly to another stream,
+/// rather than through its outputs.
+///
+/// - `input_sender`: Delivers a message as if it was routed to the target stream from another stream.
+/// - `inject_sender`: Delivers a message as if the target stream generated it.
+pub struct StreamTarget {
+    pub input_sender: Sender<Message>,
+    pub inject_sender: Sender<Message>,
+}
+
 /// The `Stream` trait defines the core functionality for a stream of data.
 /// 
 /// Streams are responsible for managing the lifecycle of a data source, including
@@ -385,5 +549,21 @@ pub trait Stream {
     fn get_uuid(&self) -> &Uuid;
     fn add_output(&mut self, sender: Sender<Message>) -> Result<(), String>;
     fn add_outputs(&mut self, senders: Vec<Sender<Message>>) -> Result<(), String>;
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>);
     fn await_thread_stop(&mut self) -> Result<(), String>;
+
+    /// Returns a handle onto the stream's in-memory message buffer, for streams that keep one.
+    fn get_ring_buffer(&self) -> Option<RingBufferHandle> {
+        None
+    }
+
+    /// Returns the UUIDs of the streams this stream sends messages to directly, besides its output streams.
+    fn get_target_streams(&self) -> Vec<Uuid> {
+        self.get_config().target_streams()
+    }
+
+    /// Provides the senders of one of the streams returned by `get_target_streams`.
+    fn add_target_stream(&mut self, _uuid: Uuid, _target: StreamTarget) -> Result<(), String> {
+        Err("Stream does not send messages to target streams".to_string())
+    }
 }
@@ -0,0 +1,364 @@
+use std::{fs, io::{self, Read, Write}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc}, thread::{self, JoinHandle}, time::Duration};
+use chrono::Utc;
+use mio::{Events, Interest, Poll, Token};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::message::MessageKind;
+use crate::stream::INTERNAL_STREAM_TICK_MS;
+use crate::tools::stream_tools::stream_tools::process_raw_log_entry;
+use super::{Stream, StreamConfig, StreamTypeConfig, Message, StreamCore};
+use super::serial_stream::default_line_terminator;
+const PTY_TOKEN: Token = Token(0);
+/// How many bytes may wait for the tool to read them before messages are dropped.
+const PTY_QUEUE_BYTES: usize = 65536;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `PtyStreamConfig` struct configures a `PtyStream`.
+///
+/// - `link_path`: A symbolic link created to the pseudo-terminal, e.g. `/tmp/ttyRELAY`, so that tools can be
+///   configured with a path that stays the same across restarts. An existing link is replaced.
+/// - `line_terminator`: Written after the text of each message sent to the pseudo-terminal.
+pub struct PtyStreamConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_path: Option<String>,
+    #[serde(default = "default_line_terminator")]
+    pub line_terminator: String,
+}
+
+impl PtyStreamConfig {
+    pub fn new() -> Self {
+        PtyStreamConfig { link_path: None, line_terminator: default_line_terminator() }
+    }
+}
+
+impl Default for PtyStreamConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+/// A stream creating a pseudo-terminal, so that tools written for a serial port, such as minicom or vendor flashers,
+/// can attach to the relay as if it were the device.
+///
+/// The messages sent to the stream are written to the pseudo-terminal, rather than passed on to the outputs, and the
+/// lines written to it by a tool become messages. The path of the pseudo-terminal is sent as a marker message when the stream starts.
+pub struct PtyStream {
+    config: StreamConfig,
+    core: StreamCore,
+    new_message_generated_sender: Sender<Message>,
+    new_message_received_receiver: Option<Receiver<Message>>,
+    pty_path: Option<String>,
+    thread_handle: Option<JoinHandle<()>>,
+    thread_stop_requsted: Arc<AtomicBool>
+}
+
+impl Stream for PtyStream {
+
+    fn start(&mut self) -> Result<(), String> {
+        let stream_name = self.config.name.clone();
+        let receiver: Receiver<Message> = self.new_message_received_receiver.take().ok_or("Receiver unavailable")?;
+        let sender: Sender<Message> = self.new_message_generated_sender.clone();
+        let stop_requested = Arc::clone(&self.thread_stop_requsted);
+        let link_path: Option<String>;
+        let line_terminator: String;
+        let mut buf = [0u8; 10240];
+        let mut last_partial_line: String = String::new();
+        let mut events = Events::with_capacity(1);
+
+        if let StreamTypeConfig::Pty {config} = &self.config.type_config {
+            link_path = config.link_path.clone();
+            line_terminator = config.line_terminator.clone();
+        }
+        else{
+            return Err("Invalid type_config for a PtyStream".to_string());
+        }
+
+        // The stream keeps the slave end open, so that tools can detach and attach again without closing the PTY.
+        let (mut master, slave_path, slave) = open_pty().map_err(|e| format!("'{stream_name}' - Failed to create PTY: {e}"))?;
+        if let Some(link_path) = &link_path {
+            create_link(&slave_path, link_path).map_err(|e| format!("'{stream_name}' - Failed to link {link_path} to {slave_path}: {e}"))?;
+        }
+        let mut poll = Poll::new().map_err(|e| format!("'{stream_name}' - {e}"))?;
+        poll.registry().register(&mut master, PTY_TOKEN, Interest::READABLE).map_err(|e| format!("'{stream_name}' - {e}"))?;
+
+        let text = match &link_path {
+            Some(link_path) => format!("PTY available at {slave_path} ({link_path})"),
+            None => format!("PTY available at {slave_path}"),
+        };
+        println!("'{stream_name}' - {text}");
+        let _ = sender.send(Message::new_marker(Utc::now().timestamp_millis(), stream_name.clone(), text));
+        self.pty_path = Some(slave_path);
+
+        self.thread_handle = Some(thread::spawn(move || {
+            let _slave = slave;
+            // Lines are queued until the tool reads them, and dropped whole once too many are waiting. That is
+            // reported once until a write succeeds.
+            let mut pending: Vec<u8> = Vec::new();
+            let mut dropping = false;
+
+            loop {
+                if stop_requested.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                while let Ok(msg) = receiver.try_recv() {
+                    if msg.kind != MessageKind::Data {
+                        continue;
+                    }
+                    let line = format!("{}{}", msg.text, line_terminator);
+                    if pending.len() + line.len() > PTY_QUEUE_BYTES {
+                        if !dropping {
+                            let text = String::from("PTY is not being read, dropping messages");
+                            eprintln!("'{stream_name}' - {text}");
+                            let _ = sender.send(Message::new_warning(Utc::now().timestamp_millis(), stream_name.clone(), text));
+                            dropping = true;
+                        }
+                        continue;
+                    }
+                    pending.extend_from_slice(line.as_bytes());
+                }
+
+                while !pending.is_empty() {
+                    match master.write(&pending) {
+                        Ok(0) => break,
+                        Ok(count) => {
+                            pending.drain(..count);
+                            dropping = false;
+                        },
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                        Err(e) => {
+                            let text = format!("Failed to write to PTY, dropping {} bytes: {e}", pending.len());
+                            eprintln!("'{stream_name}' - {text}");
+                            let _ = sender.send(Message::new_warning(Utc::now().timestamp_millis(), stream_name.clone(), text));
+                            pending.clear();
+                        },
+                    }
+                }
+
+                if let Err(e) = poll.poll(&mut events, Some(Duration::from_millis(INTERNAL_STREAM_TICK_MS))) {
+                    eprintln!("'{stream_name}' - PtyStream stopping, failed to poll: {e}");
+                    break;
+                }
+
+                for event in events.iter() {
+                    if event.token() != PTY_TOKEN {
+                        continue;
+                    }
+                    loop {
+                        match master.read(&mut buf) {
+                            Ok(0) => break,
+                            Ok(count) => {
+                                let raw_string = String::from_utf8_lossy(&buf[..count]);
+                                let (complete_lines, partial_line) = process_raw_log_entry(raw_string.to_string(), last_partial_line);
+                                last_partial_line = partial_line;
+
+                                for line in complete_lines {
+                                    if sender.send(Message::new(Utc::now().timestamp_millis(), stream_name.clone(), line)).is_err() {
+                                        return;
+                                    }
+                                }
+                            },
+                            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(e) => {
+                                eprintln!("'{stream_name}' - Failed to read from PTY: {e}");
+                                break;
+                            },
+                        }
+                    }
+                }
+            }
+        }));
+
+        self.core.start(&self.config)?;
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        println!("'{}' - PtyStream stopping", self.config.name);
+        self.core.stop()?;
+        let result = self.await_thread_stop();
+
+        if let StreamTypeConfig::Pty {config: PtyStreamConfig {link_path: Some(link_path), ..}} = &self.config.type_config {
+            if fs::symlink_metadata(link_path).map(|metadata| metadata.file_type().is_symlink()).unwrap_or(false) {
+                let _ = fs::remove_file(link_path);
+            }
+        }
+        result
+    }
+
+    fn await_thread_stop(&mut self) -> Result<(), String> {
+        self.thread_stop_requsted.store(true, Ordering::Relaxed);
+
+        if let Some(thread_handle) = self.thread_handle.take() {
+            thread_handle.join().expect("Failed to join thread");
+            Ok(())
+        } else {
+            Err("Thread handle not available".to_string())
+        }
+    }
+
+    fn get_config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    fn get_status(&self) -> &StreamCore {
+        &self.core
+    }
+
+    fn get_uuid(&self) -> &Uuid{
+        &self.config.uuid
+    }
+
+    fn add_output(&mut self, receiver: Sender<Message>) -> Result<(), String>{
+        self.core.add_external_output(receiver)
+    }
+
+    fn add_outputs(&mut self, senders: Vec<Sender<Message>>) -> Result<(), String>{
+        self.core.add_external_outputs(senders)
+    }
+
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
+}
+
+impl PtyStream {
+    pub fn new(config: StreamConfig) -> Result<Self, &'static str> {
+        if let StreamTypeConfig::Pty {..} = config.type_config {
+            let mut core = StreamCore::new();
+            // Messages sent to the device are not passed on, the device's own output is.
+            core.set_echo_received(false);
+
+            Ok(Self{
+                config,
+                new_message_generated_sender: core.get_internal_input_sender_clone(),
+                new_message_received_receiver: Some(core.get_internal_output_receiver()),
+                core,
+                pty_path: None,
+                thread_handle: None,
+                thread_stop_requsted: Arc::new(AtomicBool::new(false))
+            })
+        }
+        else{
+            Err("Invalid type_config for a PtyStream")
+        }
+    }
+
+    /// Returns the path of the pseudo-terminal, once the stream has started.
+    pub fn pty_path(&self) -> Option<&str> {
+        self.pty_path.as_deref()
+    }
+}
+
+/// Creates a pseudo-terminal, returning its master end, the path of its slave end and the slave end.
+#[cfg(unix)]
+fn open_pty() -> Result<(mio_serial::SerialStream, String, mio_serial::SerialStream), String> {
+    use mio_serial::SerialPort;
+    let (master, slave) = mio_serial::SerialStream::pair().map_err(|e| e.to_string())?;
+    let slave_path = slave.name().ok_or("PTY has no path")?;
+    Ok((master, slave_path, slave))
+}
+
+#[cfg(not(unix))]
+fn open_pty() -> Result<(mio_serial::SerialStream, String, mio_serial::SerialStream), String> {
+    Err(String::from("PTY streams are only supported on Unix"))
+}
+
+/// Creates a symbolic link to the pseudo-terminal, replacing an existing link but not any other file.
+#[cfg(unix)]
+fn create_link(pty_path: &str, link_path: &str) -> io::Result<()> {
+    if let Ok(metadata) = fs::symlink_metadata(link_path) {
+        if !metadata.file_type().is_symlink() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "a file that is not a link already exists"));
+        }
+        fs::remove_file(link_path)?;
+    }
+    std::os::unix::fs::symlink(pty_path, link_path)
+}
+
+#[cfg(not(unix))]
+fn create_link(_pty_path: &str, _link_path: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "links are only supported on Unix"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Instant;
+    use mio_serial::SerialPortBuilderExt;
+
+    fn pty_config(link_path: Option<String>) -> StreamConfig {
+        StreamConfig {
+            name: String::from("Virtual UART"),
+            type_config: StreamTypeConfig::Pty { config: PtyStreamConfig { link_path, ..PtyStreamConfig::new() } },
+            ..StreamConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_tool_attached_to_link() {
+        let directory = tempfile::tempdir().unwrap();
+        let link_path = directory.path().join("ttyRELAY").to_str().unwrap().to_string();
+        let mut stream = PtyStream::new(pty_config(Some(link_path.clone()))).unwrap();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        stream.add_output(sender).unwrap();
+        stream.start().unwrap();
+
+        let marker = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(marker.text, format!("PTY available at {} ({link_path})", stream.pty_path().unwrap()));
+
+        let mut tool = mio_serial::new(&link_path, 115200).open_native_async().unwrap();
+        tool.write_all(b"flash ok\n").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap().text, "flash ok");
+
+        stream.get_status().get_external_input_sender_clone().send(Message::new(0, String::from("Device"), String::from("boot"))).unwrap();
+        let started = Instant::now();
+        let mut received: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 64];
+        while received.len() < 6 && started.elapsed() < Duration::from_secs(5) {
+            match tool.read(&mut buf) {
+                Ok(count) => received.extend_from_slice(&buf[..count]),
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        assert_eq!(received, b"boot\r\n");
+
+        stream.stop().unwrap();
+        assert!(fs::symlink_metadata(&link_path).is_err());
+    }
+
+    #[test]
+    fn test_long_message_written_whole() {
+        let mut stream = PtyStream::new(pty_config(None)).unwrap();
+        stream.start().unwrap();
+        let mut tool = mio_serial::new(stream.pty_path().unwrap(), 115200).open_native_async().unwrap();
+
+        // Far more than the PTY buffers, so it can only be written as the tool reads it.
+        let text = "x".repeat(20000);
+        stream.get_status().get_external_input_sender_clone().send(Message::new(0, String::from("Device"), text.clone())).unwrap();
+        let started = Instant::now();
+        let mut received: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 1024];
+        while received.len() < text.len() + 2 && started.elapsed() < Duration::from_secs(5) {
+            match tool.read(&mut buf) {
+                Ok(count) => received.extend_from_slice(&buf[..count]),
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        assert_eq!(received, format!("{text}\r\n").into_bytes());
+
+        stream.stop().unwrap();
+    }
+
+    #[test]
+    fn test_existing_file_not_replaced() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut stream = PtyStream::new(pty_config(Some(file.path().to_str().unwrap().to_string()))).unwrap();
+        assert!(stream.start().unwrap_err().contains("a file that is not a link already exists"));
+    }
+}
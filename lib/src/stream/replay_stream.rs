@@ -0,0 +1,329 @@
+use std::{fs::File, io::{BufRead, BufReader}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::stream::INTERNAL_STREAM_TICK_MS;
+use super::{Stream, StreamConfig, StreamTypeConfig, Message, StreamCore};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `ReplayStreamConfig` struct configures a `ReplayStream`.
+///
+/// - `file_path`: A capture file written by a `FileStream` using `FileFormat::Capture`.
+/// - `speed`: The replay speed relative to the original timing, e.g. `2.0` replays twice as fast.
+/// - `looping`: Start again from the beginning once the end has been reached.
+/// - `start_offset_ms`: Skip the messages captured within this time from the first message.
+/// - `end_offset_ms`: Stop at the messages captured this long after the first message, if set.
+pub struct ReplayStreamConfig {
+    pub file_path: String,
+    pub speed: f64,
+    pub looping: bool,
+    pub start_offset_ms: u64,
+    pub end_offset_ms: Option<u64>,
+}
+
+impl ReplayStreamConfig {
+    pub fn new(file_path: String) -> Self {
+        ReplayStreamConfig {file_path, speed: 1.0, looping: false, start_offset_ms: 0, end_offset_ms: None}
+    }
+}
+
+#[derive(Debug)]
+/// A source stream re-emitting the messages of a capture file, keeping their original inter-message timing.
+///
+/// The messages are emitted unchanged, including their original timestamps and originators.
+pub struct ReplayStream {
+    config: StreamConfig,
+    core: StreamCore,
+    new_message_generated_sender: Sender<Message>,
+    new_message_received_receiver: Option<Receiver<Message>>,
+    thread_handle: Option<JoinHandle<()>>,
+    thread_stop_requsted: Arc<AtomicBool>
+}
+
+impl Stream for ReplayStream {
+
+    fn start(&mut self) -> Result<(), String> {
+        let stream_name = self.config.name.clone();
+        let receiver: Receiver<Message> = self.new_message_received_receiver.take().ok_or("Receiver unavailable")?;
+        let sender: Sender<Message> = self.new_message_generated_sender.clone();
+        let stop_requested = Arc::clone(&self.thread_stop_requsted);
+        let speed: f64;
+        let looping: bool;
+        let messages: Vec<Message>;
+
+        if let StreamTypeConfig::Replay {config} = &self.config.type_config {
+            if config.speed.is_nan() || config.speed <= 0.0 {
+                return Err(format!("'{stream_name}' - Replay speed must be greater than zero"));
+            }
+            speed = config.speed;
+            looping = config.looping;
+            messages = Self::select_window(Self::load_capture(&config.file_path)?, config.start_offset_ms, config.end_offset_ms);
+            let first_timestamp_ms = messages.first().map(|msg| msg.timestamp_ms).unwrap_or(0);
+            let span_ms = messages.iter().map(|msg| msg.timestamp_ms.saturating_sub(first_timestamp_ms)).max().unwrap_or(0);
+            if Self::replay_offset(span_ms, speed).and_then(|offset| Instant::now().checked_add(offset)).is_none() {
+                return Err(format!("'{stream_name}' - Replaying {span_ms} ms of capture at speed {speed} takes too long"));
+            }
+        }
+        else{
+            return Err("Invalid type_config for a ReplayStream".to_string());
+        }
+
+        println!("'{}' - ReplayStream starting thread, {} messages to replay", stream_name, messages.len());
+
+        self.thread_handle = Some(thread::spawn(move || {
+            'replay: loop {
+                let replay_start = Instant::now();
+                let first_timestamp_ms = messages.first().map(|msg| msg.timestamp_ms).unwrap_or(0);
+
+                for msg in messages.iter() {
+                    let Some(deadline) = Self::replay_offset(msg.timestamp_ms.saturating_sub(first_timestamp_ms), speed).and_then(|offset| replay_start.checked_add(offset)) else {
+                        eprintln!("'{stream_name}' - Replay timing out of range, stopping");
+                        break 'replay;
+                    };
+                    if !Self::wait_until(deadline, &stop_requested) {
+                        break 'replay;
+                    }
+                    // The core has stopped when its receiver is gone.
+                    if sender.send(msg.clone()).is_err() {
+                        break 'replay;
+                    }
+
+                    // Messages routed to a replay stream are discarded.
+                    while receiver.try_recv().is_ok() {}
+                }
+
+                // Leave at least one tick between iterations, so a short capture does not flood the outputs.
+                if !looping || messages.is_empty() || !Self::wait_until(Instant::now() + Duration::from_millis(INTERNAL_STREAM_TICK_MS), &stop_requested) {
+                    break;
+                }
+            }
+
+            println!("'{}' - ReplayStream finished", stream_name);
+            while !stop_requested.load(Ordering::Relaxed) {
+                while receiver.try_recv().is_ok() {}
+                thread::sleep(Duration::from_millis(INTERNAL_STREAM_TICK_MS));
+            }
+        }));
+
+        self.core.start(&self.config)?;
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        println!("'{}' - ReplayStream stopping", self.config.name);
+        self.core.stop()?;
+        self.await_thread_stop()
+    }
+
+    fn await_thread_stop(&mut self) -> Result<(), String> {
+        self.thread_stop_requsted.store(true, Ordering::Relaxed);
+
+        if let Some(thread_handle) = self.thread_handle.take() {
+            thread_handle.join().expect("Failed to join thread");
+            Ok(())
+        } else {
+            Err("Thread handle not available".to_string())
+        }
+    }
+
+    fn get_config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    fn get_status(&self) -> &StreamCore {
+        &self.core
+    }
+
+    fn get_uuid(&self) -> &Uuid{
+        &self.config.uuid
+    }
+
+    fn add_output(&mut self, receiver: Sender<Message>) -> Result<(), String>{
+        self.core.add_external_output(receiver)
+    }
+
+    fn add_outputs(&mut self, senders: Vec<Sender<Message>>) -> Result<(), String>{
+        self.core.add_external_outputs(senders)
+    }
+
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
+}
+
+impl ReplayStream {
+    pub fn new(config: StreamConfig) -> Result<Self, &'static str> {
+        if let StreamTypeConfig::Replay {..} = config.type_config {
+            let mut core = StreamCore::new();
+
+            Ok(Self{
+                config,
+                new_message_generated_sender: core.get_internal_input_sender_clone(),
+                new_message_received_receiver: Some(core.get_internal_output_receiver()),
+                core,
+                thread_handle: None,
+                thread_stop_requsted: Arc::new(AtomicBool::new(false))
+            })
+        }
+        else{
+            Err("Invalid type_config for a ReplayStream")
+        }
+    }
+
+    /// Reads every message of a capture file. Empty lines are ignored.
+    pub fn load_capture(file_path: &str) -> Result<Vec<Message>, String> {
+        let file = File::open(file_path).map_err(|e| format!("{file_path}: {e}"))?;
+        let mut messages: Vec<Message> = Vec::new();
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("{file_path}: {e}"))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let msg: Message = serde_json::from_str(&line).map_err(|e| format!("{file_path}:{}: {e}", index + 1))?;
+            messages.push(msg);
+        }
+
+        Ok(messages)
+    }
+
+    /// Keeps the messages captured between the start and end offsets, relative to the first message.
+    fn select_window(messages: Vec<Message>, start_offset_ms: u64, end_offset_ms: Option<u64>) -> Vec<Message> {
+        let Some(first_timestamp_ms) = messages.first().map(|msg| msg.timestamp_ms) else {
+            return messages;
+        };
+
+        messages.into_iter()
+            .filter(|msg| {
+                let offset_ms = msg.timestamp_ms - first_timestamp_ms;
+                offset_ms >= start_offset_ms as i64 && end_offset_ms.is_none_or(|end_offset_ms| offset_ms <= end_offset_ms as i64)
+            })
+            .collect()
+    }
+
+    /// Converts an offset within the capture to an offset within the replay, `None` if it cannot be represented.
+    fn replay_offset(offset_ms: i64, speed: f64) -> Option<Duration> {
+        Duration::try_from_secs_f64(offset_ms.max(0) as f64 / speed / 1000.0).ok()
+    }
+
+    /// Sleeps until the deadline, returning `false` early if stop is requested.
+    fn wait_until(deadline: Instant, stop_requested: &AtomicBool) -> bool {
+        loop {
+            if stop_requested.load(Ordering::Relaxed) {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(INTERNAL_STREAM_TICK_MS)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::mpsc;
+    use crate::stream::file_stream::{FileFormat, FileStream};
+
+    fn write_capture(messages: &[Message]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for msg in messages {
+            writeln!(file, "{}", FileStream::format_message(msg, &FileFormat::Capture)).unwrap();
+        }
+        file
+    }
+
+    fn replay_config(file_path: &str) -> StreamConfig {
+        let mut config = StreamConfig::default();
+        config.name = String::from("Replay");
+        config.type_config = StreamTypeConfig::Replay { config: ReplayStreamConfig::new(file_path.to_string()) };
+        config
+    }
+
+    #[test]
+    fn test_capture_round_trips_messages() {
+        let mut marker = Message::new_marker(1500, String::from("Coprocessor"), String::from("Device reboot detected"));
+        marker.boot_session = Some(2);
+        let messages = vec![Message::new(1000, String::from("Host"), String::from("'quoted' text")), marker];
+        let file = write_capture(&messages);
+
+        assert_eq!(ReplayStream::load_capture(file.path().to_str().unwrap()).unwrap(), messages);
+    }
+
+    #[test]
+    fn test_select_window() {
+        let messages: Vec<Message> = (0..5).map(|i| Message::new(1000 + i * 100, String::from("Host"), i.to_string())).collect();
+
+        let selected = ReplayStream::select_window(messages, 100, Some(300));
+        let texts: Vec<String> = selected.into_iter().map(|m| m.text).collect();
+        assert_eq!(texts, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn test_replay_keeps_timing_and_messages() {
+        let messages = vec![
+            Message::new(0, String::from("Host"), String::from("first")),
+            Message::new(400, String::from("Host"), String::from("second")),
+        ];
+        let file = write_capture(&messages);
+
+        let mut config = replay_config(file.path().to_str().unwrap());
+        if let StreamTypeConfig::Replay { config } = &mut config.type_config {
+            config.speed = 2.0;
+        }
+        let mut stream = ReplayStream::new(config).unwrap();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        stream.add_output(sender).unwrap();
+
+        let started = Instant::now();
+        stream.start().unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), messages[0]);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), messages[1]);
+        let elapsed = started.elapsed();
+        stream.stop().unwrap();
+
+        assert!(elapsed >= Duration::from_millis(200), "replayed too fast: {elapsed:?}");
+        assert!(elapsed < Duration::from_millis(400), "replayed too slowly: {elapsed:?}");
+    }
+
+    #[test]
+    fn test_replay_loops() {
+        let file = write_capture(&[Message::new(0, String::from("Host"), String::from("again"))]);
+
+        let mut config = replay_config(file.path().to_str().unwrap());
+        if let StreamTypeConfig::Replay { config } = &mut config.type_config {
+            config.looping = true;
+        }
+        let mut stream = ReplayStream::new(config).unwrap();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        stream.add_output(sender).unwrap();
+
+        stream.start().unwrap();
+        for _ in 0..3 {
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap().text, "again");
+        }
+        stream.stop().unwrap();
+    }
+
+    #[test]
+    fn test_tiny_speed_fails_to_start() {
+        let file = write_capture(&[Message::new(0, String::from("Host"), String::from("first")), Message::new(1000, String::from("Host"), String::from("second"))]);
+
+        let mut config = replay_config(file.path().to_str().unwrap());
+        if let StreamTypeConfig::Replay { config } = &mut config.type_config {
+            config.speed = 1e-300;
+        }
+        let mut stream = ReplayStream::new(config).unwrap();
+        assert!(stream.start().unwrap_err().contains("takes too long"));
+    }
+
+    #[test]
+    fn test_missing_capture_fails_to_start() {
+        let mut stream = ReplayStream::new(replay_config("/nonexistent/capture.jsonl")).unwrap();
+        assert!(stream.start().is_err());
+    }
+}
@@ -0,0 +1,486 @@
+use std::{collections::VecDeque, io::{BufRead, BufReader, ErrorKind, Write}, net::{SocketAddr, TcpListener, TcpStream}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender}, Arc, Mutex}, thread::{self, JoinHandle}, time::Duration};
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::{filter::Filter, stream::INTERNAL_STREAM_TICK_MS};
+use super::{Stream, StreamConfig, StreamTypeConfig, Message, StreamCore};
+
+/// How long a query client may take to send its query.
+const QUERY_READ_TIMEOUT_MS: u64 = 5000;
+/// How many messages may wait for a follower before it is considered too slow and dropped.
+const FOLLOWER_QUEUE_LEN: usize = 1000;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `RingBufferStreamConfig` struct configures a `RingBufferStream`.
+///
+/// - `capacity`: The maximum number of messages kept, zero for no limit.
+/// - `max_age_ms`: The maximum age of the messages kept, zero for no limit.
+/// - `query_port`: An optional TCP port on which the buffer can be queried, see `RingBufferStream`.
+/// - `query_bind_address`: The address the query port is listened on, `127.0.0.1` by default. Queries are not
+///   authenticated, so only listen on other addresses in trusted networks.
+pub struct RingBufferStreamConfig {
+    pub capacity: usize,
+    pub max_age_ms: u64,
+    pub query_port: Option<u16>,
+    #[serde(default = "default_query_bind_address")]
+    pub query_bind_address: String,
+}
+
+fn default_query_bind_address() -> String {
+    String::from("127.0.0.1")
+}
+
+impl RingBufferStreamConfig {
+    pub fn new() -> Self {
+        RingBufferStreamConfig {capacity: 10000, max_age_ms: 0, query_port: None, query_bind_address: default_query_bind_address()}
+    }
+}
+
+impl Default for RingBufferStreamConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+/// A query over the messages held by a `RingBufferStream`. All conditions that are set must match.
+///
+/// - `from_ms`, `to_ms`: The inclusive range of `Message::timestamp_ms`.
+/// - `originator`: The exact originator of the messages.
+/// - `text_filter`: A filter applied to the message text.
+/// - `limit`: Return only the most recent `limit` matching messages.
+/// - `follow`: Over TCP, keep the connection open and send new matching messages as they arrive.
+pub struct RingBufferQuery {
+    #[serde(default)]
+    pub from_ms: Option<i64>,
+    #[serde(default)]
+    pub to_ms: Option<i64>,
+    #[serde(default)]
+    pub originator: Option<String>,
+    #[serde(default)]
+    pub text_filter: Option<Filter>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub follow: bool,
+}
+
+impl RingBufferQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn matches(&self, msg: &Message) -> bool {
+        self.from_ms.is_none_or(|from_ms| msg.timestamp_ms >= from_ms)
+            && self.to_ms.is_none_or(|to_ms| msg.timestamp_ms <= to_ms)
+            && self.originator.as_ref().is_none_or(|originator| &msg.originator == originator)
+            && self.text_filter.as_ref().is_none_or(|filter| filter.matches(&msg.text))
+    }
+}
+
+#[derive(Debug, Default)]
+struct RingBufferState {
+    messages: VecDeque<Message>,
+    followers: Vec<(RingBufferQuery, SyncSender<Message>)>,
+}
+
+#[derive(Clone, Debug)]
+/// A cloneable handle onto the messages held by a `RingBufferStream`, see `StreamsEngine::get_ring_buffer`.
+pub struct RingBufferHandle {
+    capacity: usize,
+    max_age_ms: u64,
+    state: Arc<Mutex<RingBufferState>>,
+}
+
+impl RingBufferHandle {
+    pub fn new(capacity: usize, max_age_ms: u64) -> Self {
+        RingBufferHandle {capacity, max_age_ms, state: Arc::new(Mutex::new(RingBufferState::default()))}
+    }
+
+    /// Adds a message to the buffer, evicting the oldest messages beyond the capacity,
+    /// and sends it to every follower whose query it matches. Followers that have gone or fall too far behind are dropped.
+    pub fn push(&self, msg: Message) {
+        let mut state = self.state.lock().expect("Ring buffer lock poisoned");
+        state.followers.retain(|(query, follower)| !query.matches(&msg) || follower.try_send(msg.clone()).is_ok());
+        state.messages.push_back(msg);
+        if self.capacity > 0 {
+            while state.messages.len() > self.capacity {
+                state.messages.pop_front();
+            }
+        }
+    }
+
+    /// Evicts the messages older than the maximum age.
+    pub fn expire(&self, now_ms: i64) {
+        if self.max_age_ms == 0 {
+            return;
+        }
+        let oldest_ms = now_ms.saturating_sub(self.max_age_ms as i64);
+        let mut state = self.state.lock().expect("Ring buffer lock poisoned");
+        while state.messages.front().is_some_and(|msg| msg.timestamp_ms < oldest_ms) {
+            state.messages.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().expect("Ring buffer lock poisoned").messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the buffered messages matching the query, oldest first.
+    pub fn query(&self, query: &RingBufferQuery) -> Vec<Message> {
+        let state = self.state.lock().expect("Ring buffer lock poisoned");
+        Self::query_locked(&state, query)
+    }
+
+    /// Returns the buffered messages matching the query together with a receiver for the matching messages that
+    /// arrive afterwards. No message is missed or repeated between the two. The receiver is disconnected if more
+    /// than `FOLLOWER_QUEUE_LEN` messages are left waiting on it.
+    pub fn follow(&self, query: &RingBufferQuery) -> (Vec<Message>, Receiver<Message>) {
+        let mut state = self.state.lock().expect("Ring buffer lock poisoned");
+        let (sender, receiver) = mpsc::sync_channel::<Message>(FOLLOWER_QUEUE_LEN);
+        let scrollback = Self::query_locked(&state, query);
+        state.followers.push((query.clone(), sender));
+        (scrollback, receiver)
+    }
+
+    fn query_locked(state: &RingBufferState, query: &RingBufferQuery) -> Vec<Message> {
+        let mut matching: Vec<Message> = state.messages.iter().filter(|msg| query.matches(msg)).cloned().collect();
+        if let Some(limit) = query.limit {
+            matching.drain(..matching.len().saturating_sub(limit));
+        }
+        matching
+    }
+}
+
+#[derive(Debug)]
+/// A sink stream keeping the most recent messages in memory.
+///
+/// The messages can be queried from the library through a `RingBufferHandle`, or over TCP when a `query_port` is
+/// configured. A TCP client sends one `RingBufferQuery` as a line of JSON and receives the matching messages as
+/// lines of JSON. The connection is closed after the scrollback, unless the query sets `follow`.
+pub struct RingBufferStream {
+    config: StreamConfig,
+    core: StreamCore,
+    handle: RingBufferHandle,
+    new_message_received_receiver: Option<Receiver<Message>>,
+    thread_handle: Option<JoinHandle<()>>,
+    server_thread_handle: Option<JoinHandle<()>>,
+    thread_stop_requsted: Arc<AtomicBool>
+}
+
+impl Stream for RingBufferStream {
+
+    fn start(&mut self) -> Result<(), String> {
+        let stream_name = self.config.name.clone();
+        let receiver: Receiver<Message> = self.new_message_received_receiver.take().ok_or("Receiver unavailable")?;
+        let handle = self.handle.clone();
+        let stop_requested = Arc::clone(&self.thread_stop_requsted);
+        let query_address: Option<String>;
+
+        if let StreamTypeConfig::RingBuffer {config} = &self.config.type_config {
+            query_address = config.query_port.map(|port| format!("{}:{}", config.query_bind_address, port));
+        }
+        else{
+            return Err("Invalid type_config for a RingBufferStream".to_string());
+        }
+
+        println!("'{}' - RingBufferStream starting thread", stream_name);
+
+        if let Some(query_address) = query_address {
+            let (address, server_thread) = Self::start_query_server(&query_address, self.handle.clone(), Arc::clone(&self.thread_stop_requsted))?;
+            println!("'{}' - RingBufferStream listening for queries on {}", stream_name, address);
+            self.server_thread_handle = Some(server_thread);
+        }
+
+        self.thread_handle = Some(thread::spawn(move || loop {
+
+            // Handle Message received from core
+            while let Ok(msg) = receiver.try_recv() {
+                handle.push(msg);
+            }
+            handle.expire(Utc::now().timestamp_millis());
+
+            thread::sleep(Duration::from_millis(INTERNAL_STREAM_TICK_MS));
+
+            // Has stop been requested?
+            if stop_requested.load(Ordering::Relaxed) {
+                break;
+            }
+        }));
+
+        self.core.start(&self.config)?;
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        println!("'{}' - RingBufferStream stopping", self.config.name);
+        self.core.stop()?;
+        self.await_thread_stop()
+    }
+
+    fn await_thread_stop(&mut self) -> Result<(), String> {
+        self.thread_stop_requsted.store(true, Ordering::Relaxed);
+
+        if let Some(server_thread_handle) = self.server_thread_handle.take() {
+            server_thread_handle.join().expect("Failed to join query server thread");
+        }
+
+        if let Some(thread_handle) = self.thread_handle.take() {
+            thread_handle.join().expect("Failed to join thread");
+            Ok(())
+        } else {
+            Err("Thread handle not available".to_string())
+        }
+    }
+
+    fn get_config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    fn get_status(&self) -> &StreamCore {
+        &self.core
+    }
+
+    fn get_uuid(&self) -> &Uuid{
+        &self.config.uuid
+    }
+
+    fn add_output(&mut self, receiver: Sender<Message>) -> Result<(), String>{
+        self.core.add_external_output(receiver)
+    }
+
+    fn add_outputs(&mut self, senders: Vec<Sender<Message>>) -> Result<(), String>{
+        self.core.add_external_outputs(senders)
+    }
+
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
+
+    fn get_ring_buffer(&self) -> Option<RingBufferHandle> {
+        Some(self.handle.clone())
+    }
+}
+
+impl RingBufferStream {
+    pub fn new(config: StreamConfig) -> Result<Self, &'static str> {
+        if let StreamTypeConfig::RingBuffer {config: ring_buffer_config} = &config.type_config {
+            let mut core = StreamCore::new();
+
+            Ok(Self{
+                handle: RingBufferHandle::new(ring_buffer_config.capacity, ring_buffer_config.max_age_ms),
+                config,
+                new_message_received_receiver: Some(core.get_internal_output_receiver()),
+                core,
+                thread_handle: None,
+                server_thread_handle: None,
+                thread_stop_requsted: Arc::new(AtomicBool::new(false))
+            })
+        }
+        else{
+            Err("Invalid type_config for a RingBufferStream")
+        }
+    }
+
+    /// Starts a thread accepting query connections on the given address, returning the bound address.
+    fn start_query_server(address: &str, handle: RingBufferHandle, stop_requested: Arc<AtomicBool>) -> Result<(SocketAddr, JoinHandle<()>), String> {
+        let listener = TcpListener::bind(address).map_err(|e| format!("Failed to bind query port {address}: {e}"))?;
+        let local_address = listener.local_addr().map_err(|e| e.to_string())?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        let server_thread = thread::spawn(move || loop {
+            match listener.accept() {
+                Ok((client, _)) => {
+                    let handle = handle.clone();
+                    let stop_requested = Arc::clone(&stop_requested);
+                    thread::spawn(move || {
+                        if let Err(e) = Self::serve_query(client, handle, stop_requested) {
+                            eprintln!("RingBufferStream - Query client error: {e}");
+                        }
+                    });
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(INTERNAL_STREAM_TICK_MS));
+                },
+                Err(e) => {
+                    eprintln!("RingBufferStream - Failed to accept query client: {e}");
+                }
+            }
+
+            if stop_requested.load(Ordering::Relaxed) {
+                break;
+            }
+        });
+
+        Ok((local_address, server_thread))
+    }
+
+    fn serve_query(client: TcpStream, handle: RingBufferHandle, stop_requested: Arc<AtomicBool>) -> std::io::Result<()> {
+        client.set_nonblocking(false)?;
+        // A client that never sends its query must not hold its thread forever.
+        client.set_read_timeout(Some(Duration::from_millis(QUERY_READ_TIMEOUT_MS)))?;
+        let mut line = String::new();
+        BufReader::new(client.try_clone()?).read_line(&mut line)?;
+        let mut writer = client;
+
+        let query: RingBufferQuery = match serde_json::from_str(&line) {
+            Ok(query) => query,
+            Err(e) => {
+                writeln!(writer, "{}", serde_json::json!({"error": e.to_string()}))?;
+                return Ok(());
+            }
+        };
+        if let Some(Err(e)) = query.text_filter.as_ref().map(Filter::validate) {
+            writeln!(writer, "{}", serde_json::json!({"error": e}))?;
+            return Ok(());
+        }
+
+        if !query.follow {
+            for msg in handle.query(&query) {
+                writeln!(writer, "{}", serde_json::to_string(&msg)?)?;
+            }
+            return Ok(());
+        }
+
+        let (scrollback, receiver) = handle.follow(&query);
+        for msg in scrollback {
+            writeln!(writer, "{}", serde_json::to_string(&msg)?)?;
+        }
+        while !stop_requested.load(Ordering::Relaxed) {
+            match receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(msg) => writeln!(writer, "{}", serde_json::to_string(&msg)?)?,
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterType;
+    use std::net::Shutdown;
+
+    fn msg(timestamp_ms: i64, originator: &str, text: &str) -> Message {
+        Message::new(timestamp_ms, originator.to_string(), text.to_string())
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let handle = RingBufferHandle::new(2, 0);
+        handle.push(msg(1, "A", "one"));
+        handle.push(msg(2, "A", "two"));
+        handle.push(msg(3, "A", "three"));
+
+        let texts: Vec<String> = handle.query(&RingBufferQuery::new()).into_iter().map(|m| m.text).collect();
+        assert_eq!(texts, vec!["two", "three"]);
+    }
+
+    #[test]
+    fn test_max_age_expires_messages() {
+        let handle = RingBufferHandle::new(0, 1000);
+        handle.push(msg(1000, "A", "old"));
+        handle.push(msg(5000, "A", "new"));
+        handle.expire(5500);
+
+        assert_eq!(handle.len(), 1);
+    }
+
+    #[test]
+    fn test_query_conditions() {
+        let handle = RingBufferHandle::new(0, 0);
+        handle.push(msg(1, "Host", "boot ok"));
+        handle.push(msg(2, "Coprocessor", "boot ok"));
+        handle.push(msg(3, "Host", "error 5"));
+        handle.push(msg(4, "Host", "error 6"));
+
+        let mut query = RingBufferQuery::new();
+        query.originator = Some(String::from("Host"));
+        assert_eq!(handle.query(&query).len(), 3);
+
+        query.from_ms = Some(2);
+        query.to_ms = Some(3);
+        assert_eq!(handle.query(&query).len(), 1);
+
+        let mut query = RingBufferQuery::new();
+        query.text_filter = Some(Filter::new("errors", FilterType::Regex, r"^error \d$"));
+        query.limit = Some(1);
+        let result = handle.query(&query);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].text, "error 6");
+    }
+
+    #[test]
+    fn test_follow_receives_new_matching_messages() {
+        let handle = RingBufferHandle::new(0, 0);
+        handle.push(msg(1, "Host", "before"));
+
+        let mut query = RingBufferQuery::new();
+        query.originator = Some(String::from("Host"));
+        let (scrollback, receiver) = handle.follow(&query);
+        assert_eq!(scrollback.len(), 1);
+
+        handle.push(msg(2, "Other", "ignored"));
+        handle.push(msg(3, "Host", "after"));
+        assert_eq!(receiver.try_recv().unwrap().text, "after");
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_slow_follower_dropped() {
+        let handle = RingBufferHandle::new(0, 0);
+        let (_, receiver) = handle.follow(&RingBufferQuery::new());
+        for timestamp_ms in 0..=FOLLOWER_QUEUE_LEN as i64 {
+            handle.push(msg(timestamp_ms, "Host", "flood"));
+        }
+
+        assert_eq!(receiver.iter().count(), FOLLOWER_QUEUE_LEN);
+    }
+
+    #[test]
+    fn test_query_over_tcp() {
+        let handle = RingBufferHandle::new(0, 0);
+        handle.push(msg(1, "Host", "one"));
+        handle.push(msg(2, "Host", "two"));
+
+        let stop_requested = Arc::new(AtomicBool::new(false));
+        let (address, server_thread) = RingBufferStream::start_query_server("127.0.0.1:0", handle, Arc::clone(&stop_requested)).unwrap();
+
+        let mut client = TcpStream::connect(address).unwrap();
+        writeln!(client, r#"{{"limit": 1}}"#).unwrap();
+        let lines: Vec<String> = BufReader::new(client).lines().map(|line| line.unwrap()).collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(serde_json::from_str::<Message>(&lines[0]).unwrap().text, "two");
+
+        // A query with an invalid filter is answered with an error.
+        let mut client = TcpStream::connect(address).unwrap();
+        writeln!(client, r#"{{"text_filter": {{"name": "broken", "filter_type": "Regex", "value": "[unclosed"}}}}"#).unwrap();
+        let lines: Vec<String> = BufReader::new(client).lines().map(|line| line.unwrap()).collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with(r#"{"error":"Filter 'broken'"#));
+
+        // A client that closes without sending a query is answered with an error too.
+        let client = TcpStream::connect(address).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let lines: Vec<String> = BufReader::new(client).lines().map(|line| line.unwrap()).collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with(r#"{"error":"#));
+
+        stop_requested.store(true, Ordering::Relaxed);
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_query_port_local_by_default() {
+        let config: RingBufferStreamConfig = serde_json::from_str(r#"{"capacity": 10, "max_age_ms": 0, "query_port": 0}"#).unwrap();
+        assert_eq!(config.query_bind_address, "127.0.0.1");
+    }
+}
@@ -0,0 +1,429 @@
+use std::{collections::HashMap, process::Command, sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc, Mutex}, thread::{self, JoinHandle}, time::Duration};
+use chrono::Utc;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::stream::INTERNAL_STREAM_TICK_MS;
+use super::{Stream, StreamConfig, StreamTypeConfig, StreamTarget, Message, StreamCore};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `RuleAction` enum describes what a rule does when its pattern matches.
+///
+/// Command arguments and templates may refer to the groups captured by the pattern, e.g. `$1` or `${name}`, and to the
+/// whole match with `$0`. A literal `$` is written `$$`.
+///
+/// - `RunCommand`: Runs a local program with the given arguments, without waiting for it to complete. The program
+///   is run as written, captures are only expanded in its arguments, so device output cannot choose what runs.
+/// - `Inject`: Sends a message into another stream, as if that stream generated it.
+/// - `Write`: Sends a message to another stream, as if it was routed to it. Bidirectional streams, such as
+///   serial streams, write it to their device followed by their `line_terminator`, so the template leaves it out,
+///   e.g. `start_test` rather than `start_test\r\n`.
+/// - `Control`: Sends a control message to another stream, e.g. `dtr pulse 100` to reset the board on a serial stream.
+pub enum RuleAction {
+    RunCommand{program: String, args: Vec<String>},
+    Inject{#[schemars(with = "String")] stream: Uuid, template: String},
+    Write{#[schemars(with = "String")] stream: Uuid, template: String},
+    Control{#[schemars(with = "String")] stream: Uuid, command: String},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `Rule` struct describes an action taken when a message matches a pattern.
+///
+/// - `name`: The name of the rule, used when reporting.
+/// - `pattern`: A regular expression matched against the text of every message received.
+/// - `action`: The action taken on a match.
+/// - `debounce_ms`: When non-zero, the action is only taken once no further match has been seen for this long,
+///   using the captures of the last match.
+/// - `cooldown_ms`: How long matches are ignored after the action has been taken.
+pub struct Rule {
+    pub name: String,
+    pub pattern: String,
+    pub action: RuleAction,
+    #[serde(default)]
+    pub debounce_ms: u64,
+    #[serde(default)]
+    pub cooldown_ms: u64,
+}
+
+impl Rule {
+    pub fn new(name: String, pattern: String, action: RuleAction) -> Self {
+        Rule { name, pattern, action, debounce_ms: 0, cooldown_ms: 0 }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Default)]
+/// The `RuleEngineStreamConfig` struct configures a `RuleEngineStream` with the rules it applies, in order.
+pub struct RuleEngineStreamConfig {
+    pub rules: Vec<Rule>,
+}
+
+impl RuleEngineStreamConfig {
+    pub fn new() -> Self {
+        RuleEngineStreamConfig { rules: vec![] }
+    }
+
+    /// The streams written to or injected into by the rules.
+    pub fn target_streams(&self) -> Vec<Uuid> {
+        let mut targets: Vec<Uuid> = Vec::new();
+        for rule in self.rules.iter() {
+            if let RuleAction::Inject{stream, ..} | RuleAction::Write{stream, ..} | RuleAction::Control{stream, ..} = &rule.action {
+                if !targets.contains(stream) {
+                    targets.push(*stream);
+                }
+            }
+        }
+        targets
+    }
+}
+
+#[derive(Debug, PartialEq)]
+/// A rule action with its arguments expanded from the captures of the matching message.
+enum FiredAction {
+    RunCommand{program: String, args: Vec<String>},
+    Inject{stream: Uuid, text: String},
+    Write{stream: Uuid, text: String},
+    Control{stream: Uuid, command: String},
+}
+
+struct RuleState {
+    rule: Rule,
+    regex: Regex,
+    pending: Option<(i64, FiredAction)>,
+    cooldown_until_ms: i64,
+}
+
+impl RuleState {
+    fn new(rule: Rule) -> Result<Self, String> {
+        let regex = Regex::new(&rule.pattern).map_err(|e| format!("Rule '{}': {e}", rule.name))?;
+        Ok(RuleState { rule, regex, pending: None, cooldown_until_ms: i64::MIN })
+    }
+
+    fn process(&mut self, msg: &Message, now_ms: i64) -> Option<FiredAction> {
+        if now_ms < self.cooldown_until_ms {
+            return None;
+        }
+        let captures = self.regex.captures(&msg.text)?;
+        let expand = |template: &str| {
+            let mut expanded = String::new();
+            captures.expand(template, &mut expanded);
+            expanded
+        };
+
+        let action = match &self.rule.action {
+            RuleAction::RunCommand{program, args} => FiredAction::RunCommand{program: program.clone(), args: args.iter().map(|arg| expand(arg)).collect()},
+            RuleAction::Inject{stream, template} => FiredAction::Inject{stream: *stream, text: expand(template)},
+            RuleAction::Write{stream, template} => FiredAction::Write{stream: *stream, text: expand(template)},
+            RuleAction::Control{stream, command} => FiredAction::Control{stream: *stream, command: expand(command)},
+        };
+
+        if self.rule.debounce_ms == 0 {
+            self.fire(action, now_ms)
+        } else {
+            self.pending = Some((now_ms.saturating_add(self.rule.debounce_ms as i64), action));
+            None
+        }
+    }
+
+    fn tick(&mut self, now_ms: i64) -> Option<FiredAction> {
+        match self.pending.take() {
+            Some((due_ms, action)) if now_ms >= due_ms => self.fire(action, now_ms),
+            pending => {
+                self.pending = pending;
+                None
+            }
+        }
+    }
+
+    fn fire(&mut self, action: FiredAction, now_ms: i64) -> Option<FiredAction> {
+        self.cooldown_until_ms = now_ms.saturating_add(self.rule.cooldown_ms as i64);
+        Some(action)
+    }
+}
+
+#[derive(Debug)]
+/// A stream applying rules to the messages routed to it. When the pattern of a rule matches, the rule's action is
+/// taken, e.g. writing a command back to the device that printed the message.
+///
+/// The messages received are passed on to the stream's outputs unchanged. Actions that fail are reported with a
+/// warning message on the stream's outputs.
+pub struct RuleEngineStream {
+    config: StreamConfig,
+    core: StreamCore,
+    new_message_generated_sender: Sender<Message>,
+    new_message_received_receiver: Option<Receiver<Message>>,
+    targets: Arc<Mutex<HashMap<Uuid, StreamTarget>>>, // Shared with the thread, so that targets can be relinked while running.
+    thread_handle: Option<JoinHandle<()>>,
+    thread_stop_requsted: Arc<AtomicBool>
+}
+
+impl Stream for RuleEngineStream {
+
+    fn start(&mut self) -> Result<(), String> {
+        let stream_name = self.config.name.clone();
+        let receiver: Receiver<Message> = self.new_message_received_receiver.take().ok_or("Receiver unavailable")?;
+        let sender: Sender<Message> = self.new_message_generated_sender.clone();
+        let targets = Arc::clone(&self.targets);
+        let stop_requested = Arc::clone(&self.thread_stop_requsted);
+        let mut rules: Vec<RuleState> = Vec::new();
+
+        if let StreamTypeConfig::RuleEngine {config} = &self.config.type_config {
+            for rule in config.rules.iter() {
+                rules.push(RuleState::new(rule.clone()).map_err(|e| format!("'{stream_name}' - {e}"))?);
+            }
+            let linked = targets.lock().expect("Targets lock poisoned");
+            if let Some(missing) = config.target_streams().into_iter().find(|uuid| !linked.contains_key(uuid)) {
+                return Err(format!("'{stream_name}' - Target stream {missing} is not linked"));
+            }
+        }
+        else{
+            return Err("Invalid type_config for a RuleEngineStream".to_string());
+        }
+
+        println!("'{}' - RuleEngineStream starting thread", stream_name);
+
+        self.thread_handle = Some(thread::spawn(move || loop {
+            while let Ok(msg) = receiver.try_recv() {
+                let now_ms = Utc::now().timestamp_millis();
+                for rule in rules.iter_mut() {
+                    if let Some(action) = rule.process(&msg, now_ms) {
+                        Self::take_action(&stream_name, &rule.rule.name, action, &targets.lock().expect("Targets lock poisoned"), &sender);
+                    }
+                }
+            }
+
+            let now_ms = Utc::now().timestamp_millis();
+            for rule in rules.iter_mut() {
+                if let Some(action) = rule.tick(now_ms) {
+                    Self::take_action(&stream_name, &rule.rule.name, action, &targets.lock().expect("Targets lock poisoned"), &sender);
+                }
+            }
+
+            thread::sleep(Duration::from_millis(INTERNAL_STREAM_TICK_MS));
+
+            if stop_requested.load(Ordering::Relaxed) {
+                break;
+            }
+        }));
+
+        self.core.start(&self.config)?;
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        println!("'{}' - RuleEngineStream stopping", self.config.name);
+        self.core.stop()?;
+        self.await_thread_stop()
+    }
+
+    fn await_thread_stop(&mut self) -> Result<(), String> {
+        self.thread_stop_requsted.store(true, Ordering::Relaxed);
+
+        if let Some(thread_handle) = self.thread_handle.take() {
+            thread_handle.join().expect("Failed to join thread");
+            Ok(())
+        } else {
+            Err("Thread handle not available".to_string())
+        }
+    }
+
+    fn get_config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    fn get_status(&self) -> &StreamCore {
+        &self.core
+    }
+
+    fn get_uuid(&self) -> &Uuid{
+        &self.config.uuid
+    }
+
+    fn add_output(&mut self, receiver: Sender<Message>) -> Result<(), String>{
+        self.core.add_external_output(receiver)
+    }
+
+    fn add_outputs(&mut self, senders: Vec<Sender<Message>>) -> Result<(), String>{
+        self.core.add_external_outputs(senders)
+    }
+
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
+
+    fn add_target_stream(&mut self, uuid: Uuid, target: StreamTarget) -> Result<(), String> {
+        self.targets.lock().expect("Targets lock poisoned").insert(uuid, target);
+        Ok(())
+    }
+}
+
+impl RuleEngineStream {
+    pub fn new(config: StreamConfig) -> Result<Self, &'static str> {
+        if let StreamTypeConfig::RuleEngine {..} = config.type_config {
+            let mut core = StreamCore::new();
+
+            Ok(Self{
+                config,
+                new_message_generated_sender: core.get_internal_input_sender_clone(),
+                new_message_received_receiver: Some(core.get_internal_output_receiver()),
+                core,
+                targets: Arc::new(Mutex::new(HashMap::new())),
+                thread_handle: None,
+                thread_stop_requsted: Arc::new(AtomicBool::new(false))
+            })
+        }
+        else{
+            Err("Invalid type_config for a RuleEngineStream")
+        }
+    }
+
+    fn take_action(stream_name: &str, rule_name: &str, action: FiredAction, targets: &HashMap<Uuid, StreamTarget>, sender: &Sender<Message>) {
+        let now_ms = Utc::now().timestamp_millis();
+        let result: Result<(), String> = match action {
+            FiredAction::RunCommand{program, args} => {
+                match Command::new(&program).args(&args).spawn() {
+                    Ok(mut child) => {
+                        // Wait for the command in the background, so that it does not hold up the rules.
+                        let sender = sender.clone();
+                        let stream_name = stream_name.to_string();
+                        let rule_name = rule_name.to_string();
+                        thread::spawn(move || match child.wait() {
+                            Ok(status) if status.success() => {},
+                            Ok(status) => Self::report_failure(&stream_name, &rule_name, format!("'{program}' exited with {status}"), &sender),
+                            Err(e) => Self::report_failure(&stream_name, &rule_name, format!("'{program}': {e}"), &sender),
+                        });
+                        Ok(())
+                    },
+                    Err(e) => Err(format!("Failed to run '{program}': {e}")),
+                }
+            },
+            FiredAction::Inject{stream, text} => {
+                targets.get(&stream)
+                    .ok_or(format!("Target stream {stream} is not linked"))
+                    .and_then(|target| target.inject_sender.send(Message::new(now_ms, stream_name.to_string(), text)).map_err(|e| e.to_string()))
+            },
+            FiredAction::Write{stream, text} => {
+                targets.get(&stream)
+                    .ok_or(format!("Target stream {stream} is not linked"))
+                    .and_then(|target| target.input_sender.send(Message::new(now_ms, stream_name.to_string(), text)).map_err(|e| e.to_string()))
+            },
+            FiredAction::Control{stream, command} => {
+                targets.get(&stream)
+                    .ok_or(format!("Target stream {stream} is not linked"))
+                    .and_then(|target| target.input_sender.send(Message::new_control(now_ms, stream_name.to_string(), command)).map_err(|e| e.to_string()))
+            },
+        };
+
+        if let Err(e) = result {
+            Self::report_failure(stream_name, rule_name, e, sender);
+        }
+    }
+
+    fn report_failure(stream_name: &str, rule_name: &str, error: String, sender: &Sender<Message>) {
+        let text = format!("Rule '{rule_name}' failed: {error}");
+        eprintln!("'{stream_name}' - {text}");
+        let _ = sender.send(Message::new_warning(Utc::now().timestamp_millis(), stream_name.to_string(), text));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageKind;
+    use std::sync::mpsc;
+
+    fn msg(text: &str) -> Message {
+        Message::new(0, String::from("Device"), String::from(text))
+    }
+
+    fn write_rule(target: Uuid) -> Rule {
+        Rule::new(String::from("Start test"), String::from(r"READY v(\d+)"), RuleAction::Write{stream: target, template: String::from("start_test $1")})
+    }
+
+    #[test]
+    fn test_captures_expanded() {
+        let target = Uuid::new_v4();
+        let mut rule = RuleState::new(write_rule(target)).unwrap();
+
+        assert_eq!(rule.process(&msg("booting"), 0), None);
+        assert_eq!(rule.process(&msg("READY v2"), 0), Some(FiredAction::Write{stream: target, text: String::from("start_test 2")}));
+
+        let mut command = RuleState::new(Rule::new(String::from("Dump"), String::from(r"crash at (?P<address>0x[0-9a-f]+)"),
+            RuleAction::RunCommand{program: String::from("addr2line"), args: vec![String::from("-e"), String::from("fw.elf"), String::from("${address}")]})).unwrap();
+        assert_eq!(command.process(&msg("crash at 0x0800beef"), 0),
+            Some(FiredAction::RunCommand{program: String::from("addr2line"), args: vec![String::from("-e"), String::from("fw.elf"), String::from("0x0800beef")]}));
+
+        let mut command = RuleState::new(Rule::new(String::from("Run"), String::from(r"run (\S+)"),
+            RuleAction::RunCommand{program: String::from("$1"), args: vec![]})).unwrap();
+        assert_eq!(command.process(&msg("run rm"), 0), Some(FiredAction::RunCommand{program: String::from("$1"), args: vec![]}));
+    }
+
+    #[test]
+    fn test_debounce_uses_last_match() {
+        let target = Uuid::new_v4();
+        let mut config = write_rule(target);
+        config.debounce_ms = 100;
+        let mut rule = RuleState::new(config).unwrap();
+
+        assert_eq!(rule.process(&msg("READY v1"), 0), None);
+        assert_eq!(rule.process(&msg("READY v2"), 50), None);
+        assert_eq!(rule.tick(149), None);
+        assert_eq!(rule.tick(150), Some(FiredAction::Write{stream: target, text: String::from("start_test 2")}));
+        assert_eq!(rule.tick(1000), None);
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let mut config = write_rule(Uuid::new_v4());
+        config.cooldown_ms = 1000;
+        let mut rule = RuleState::new(config).unwrap();
+
+        assert!(rule.process(&msg("READY v1"), 0).is_some());
+        assert!(rule.process(&msg("READY v1"), 999).is_none());
+        assert!(rule.process(&msg("READY v1"), 1000).is_some());
+    }
+
+    #[test]
+    fn test_write_inject_and_control_reach_target() {
+        let target_uuid = Uuid::new_v4();
+        let mut type_config = RuleEngineStreamConfig::new();
+        type_config.rules.push(write_rule(target_uuid));
+        type_config.rules.push(Rule::new(String::from("Annotate"), String::from("READY"), RuleAction::Inject{stream: target_uuid, template: String::from("test started")}));
+        type_config.rules.push(Rule::new(String::from("Reset"), String::from("PANIC"), RuleAction::Control{stream: target_uuid, command: String::from("dtr pulse 100")}));
+
+        let mut config = StreamConfig::default();
+        config.name = String::from("Rules");
+        config.type_config = StreamTypeConfig::RuleEngine { config: type_config };
+        let mut stream = RuleEngineStream::new(config).unwrap();
+        assert_eq!(stream.get_target_streams(), vec![target_uuid]);
+
+        let (input_sender, input_receiver) = mpsc::channel::<Message>();
+        let (inject_sender, inject_receiver) = mpsc::channel::<Message>();
+        stream.add_target_stream(target_uuid, StreamTarget { input_sender, inject_sender }).unwrap();
+        let sender: Sender<Message> = stream.get_status().get_external_input_sender_clone();
+
+        stream.start().unwrap();
+        sender.send(msg("READY v7")).unwrap();
+        let written = input_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        let injected = inject_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        sender.send(msg("PANIC")).unwrap();
+        let control = input_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        stream.stop().unwrap();
+
+        assert_eq!(written.text, "start_test 7");
+        assert_eq!(written.originator, "Rules");
+        assert_eq!(injected.text, "test started");
+        assert_eq!((control.kind, control.text.as_str()), (MessageKind::Control, "dtr pulse 100"));
+    }
+
+    #[test]
+    fn test_unlinked_target_fails_to_start() {
+        let mut type_config = RuleEngineStreamConfig::new();
+        type_config.rules.push(write_rule(Uuid::new_v4()));
+        let mut config = StreamConfig::default();
+        config.type_config = StreamTypeConfig::RuleEngine { config: type_config };
+
+        assert!(RuleEngineStream::new(config).unwrap().start().is_err());
+    }
+}
@@ -0,0 +1,470 @@
+use std::{collections::HashSet, io::{self, Read, Write}, net::{SocketAddr, TcpListener, TcpStream}};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::tools::telnet::{self, TelnetDecoder, TelnetEvent, DO, DONT, OPTION_BINARY, OPTION_COM_PORT, OPTION_SUPPRESS_GO_AHEAD, WILL, WONT};
+use super::serial_stream::{FlowControl, Parity, SerialStreamConfig};
+
+/// Clients further behind than this are disconnected, rather than holding up the others.
+const MAX_PENDING_BYTES: usize = 1024 * 1024;
+/// The signature sent to RFC 2217 clients asking for it.
+const SIGNATURE: &str = "log_flux_relay";
+
+// The RFC 2217 commands sent by clients. Servers answer with the command plus `SERVER_OFFSET`.
+const SIGNATURE_COMMAND: u8 = 0;
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const SET_LINESTATE_MASK: u8 = 10;
+const SET_MODEMSTATE_MASK: u8 = 11;
+const PURGE_DATA: u8 = 12;
+const SERVER_OFFSET: u8 = 100;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Default)]
+/// The `BridgeProtocol` enum selects how clients talk to a serial bridge.
+///
+/// - `Raw`: The bytes of the port, unchanged, e.g. for `nc` or `socat`.
+/// - `Rfc2217`: Telnet with the COM-PORT-OPTION of RFC 2217, letting the client change the line settings and control
+///   lines of the port, e.g. for pyserial's `rfc2217://` URLs.
+pub enum BridgeProtocol {
+    #[default]
+    Raw,
+    Rfc2217,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Default)]
+/// The `WriteAccess` enum selects the client whose input is written to the port. The input of other clients is
+/// discarded.
+///
+/// - `FirstClient`: The client connected the longest.
+/// - `LastClient`: The client connected last, taking over from the previous one.
+/// - `None`: No client, the port is only shared for reading.
+pub enum WriteAccess {
+    #[default]
+    FirstClient,
+    LastClient,
+    None,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `SerialBridgeConfig` struct configures the TCP server sharing a serial port. Every client receives the output
+/// of the port, while the stream keeps relaying it.
+///
+/// - `port`: The TCP port listened on.
+/// - `bind_address`: The address listened on, `127.0.0.1` by default. Clients are not authenticated and may write to
+///   the port, so only listen on other addresses in trusted networks.
+pub struct SerialBridgeConfig {
+    pub port: u16,
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    #[serde(default)]
+    pub protocol: BridgeProtocol,
+    #[serde(default)]
+    pub write_access: WriteAccess,
+}
+
+fn default_bind_address() -> String {
+    String::from("127.0.0.1")
+}
+
+impl SerialBridgeConfig {
+    pub fn new(port: u16) -> Self {
+        SerialBridgeConfig { port, bind_address: default_bind_address(), protocol: BridgeProtocol::Raw, write_access: WriteAccess::FirstClient }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A change to the serial port requested by the client with write access.
+pub(crate) enum PortRequest {
+    BaudRate(u32),
+    DataBits(u8),
+    Parity(Parity),
+    StopBits(u8),
+    FlowControl(FlowControl),
+    Break(bool),
+    Dtr(bool),
+    Rts(bool),
+    Purge{input: bool, output: bool},
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// What the serial stream has to do after servicing the bridge.
+pub(crate) enum BridgeEvent {
+    Marker(String),
+    Write(Vec<u8>),
+    Request(PortRequest),
+}
+
+struct BridgeClient {
+    stream: TcpStream,
+    address: SocketAddr,
+    decoder: Option<TelnetDecoder>,
+    negotiations_sent: HashSet<(u8, u8)>,
+    pending: Vec<u8>,
+    closed: Option<String>,
+}
+
+impl BridgeClient {
+    fn send(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+    }
+
+    /// Sends an option negotiation, unless it was already sent.
+    fn negotiate(&mut self, verb: u8, option: u8) {
+        if self.negotiations_sent.insert((verb, option)) {
+            self.send(&telnet::negotiation(verb, option));
+        }
+    }
+
+    fn flush(&mut self) {
+        while !self.pending.is_empty() && self.closed.is_none() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => self.closed = Some(String::from("connection closed")),
+                Ok(count) => { self.pending.drain(..count); },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => self.closed = Some(e.to_string()),
+            }
+        }
+        if self.pending.len() > MAX_PENDING_BYTES {
+            self.closed = Some(String::from("not reading fast enough"));
+        }
+    }
+}
+
+/// The `SerialBridge` struct is a TCP server sharing a serial port between clients. It is serviced by the thread of
+/// the serial stream, which owns the port.
+pub(crate) struct SerialBridge {
+    config: SerialBridgeConfig,
+    listener: TcpListener,
+    clients: Vec<BridgeClient>,
+    writer: Option<SocketAddr>,
+    dtr: bool,
+    rts: bool,
+    break_on: bool,
+}
+
+impl SerialBridge {
+    pub(crate) fn bind(config: &SerialBridgeConfig) -> Result<Self, String> {
+        let address = format!("{}:{}", config.bind_address, config.port);
+        let listener = TcpListener::bind(&address).map_err(|e| format!("Failed to listen on {address}: {e}"))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(SerialBridge { config: config.clone(), listener, clients: Vec::new(), writer: None, dtr: true, rts: true, break_on: false })
+    }
+
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    /// Sends the bytes read from the port to every client.
+    pub(crate) fn broadcast(&mut self, bytes: &[u8]) {
+        for client in self.clients.iter_mut() {
+            match client.decoder {
+                Some(_) => client.send(&telnet::escape(bytes)),
+                None => client.send(bytes),
+            }
+            client.flush();
+        }
+    }
+
+    /// Accepts new clients, reads the input of every client and drops the clients disconnected.
+    ///
+    /// # Arguments
+    /// * `settings` - The current settings of the port, reported to RFC 2217 clients.
+    ///
+    /// # Returns
+    /// The markers to send, the input of the client with write access and the changes it requested, in order.
+    pub(crate) fn service(&mut self, settings: &SerialStreamConfig) -> Vec<BridgeEvent> {
+        let mut events: Vec<BridgeEvent> = Vec::new();
+
+        while let Ok((stream, address)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_err() {
+                continue;
+            }
+            let mut client = BridgeClient { stream, address, decoder: None, negotiations_sent: HashSet::new(), pending: Vec::new(), closed: None };
+            if self.config.protocol == BridgeProtocol::Rfc2217 {
+                client.decoder = Some(TelnetDecoder::new());
+                for (verb, option) in [(WILL, OPTION_BINARY), (DO, OPTION_BINARY), (WILL, OPTION_SUPPRESS_GO_AHEAD), (DO, OPTION_SUPPRESS_GO_AHEAD), (DO, OPTION_COM_PORT)] {
+                    client.negotiate(verb, option);
+                }
+            }
+            self.clients.push(client);
+            events.push(BridgeEvent::Marker(format!("Bridge client {address} connected")));
+            if self.config.write_access == WriteAccess::LastClient {
+                self.writer = None;
+            }
+        }
+
+        let mut buf = [0u8; 4096];
+        for index in 0..self.clients.len() {
+            loop {
+                let client = &mut self.clients[index];
+                match client.stream.read(&mut buf) {
+                    Ok(0) => client.closed = Some(String::from("connection closed")),
+                    Ok(count) => {
+                        let is_writer = self.writer == Some(client.address);
+                        let telnet_events = match &mut client.decoder {
+                            Some(decoder) => decoder.decode(&buf[..count]),
+                            None => vec![TelnetEvent::Data(buf[..count].to_vec())],
+                        };
+                        // Data and requests are passed on in the order they were sent.
+                        for telnet_event in telnet_events {
+                            match telnet_event {
+                                TelnetEvent::Data(data) if is_writer => events.push(BridgeEvent::Write(data)),
+                                TelnetEvent::Data(_) => {},
+                                command => {
+                                    let requests = self.handle_telnet(index, command, is_writer, settings);
+                                    events.extend(requests.into_iter().map(BridgeEvent::Request));
+                                },
+                            }
+                        }
+                        continue;
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => client.closed = Some(e.to_string()),
+                }
+                break;
+            }
+            self.clients[index].flush();
+        }
+
+        for client in self.clients.iter().filter(|client| client.closed.is_some()) {
+            events.push(BridgeEvent::Marker(format!("Bridge client {} disconnected: {}", client.address, client.closed.as_deref().unwrap_or_default())));
+        }
+        self.clients.retain(|client| client.closed.is_none());
+
+        if !self.clients.iter().any(|client| Some(client.address) == self.writer) {
+            self.writer = match self.config.write_access {
+                WriteAccess::FirstClient => self.clients.first().map(|client| client.address),
+                WriteAccess::LastClient => self.clients.last().map(|client| client.address),
+                WriteAccess::None => None,
+            };
+            if let Some(writer) = self.writer {
+                events.push(BridgeEvent::Marker(format!("Bridge client {writer} has write access")));
+            }
+        }
+
+        events
+    }
+
+    /// Answers a telnet command of a client, returning the changes to the port it requested, if it has write access.
+    fn handle_telnet(&mut self, index: usize, event: TelnetEvent, is_writer: bool, settings: &SerialStreamConfig) -> Vec<PortRequest> {
+        let client = &mut self.clients[index];
+        match event {
+            TelnetEvent::Negotiation{verb: WILL, option} => {
+                let verb = if [OPTION_BINARY, OPTION_SUPPRESS_GO_AHEAD, OPTION_COM_PORT].contains(&option) { DO } else { DONT };
+                client.negotiate(verb, option);
+                vec![]
+            },
+            TelnetEvent::Negotiation{verb: DO, option} => {
+                let verb = if [OPTION_BINARY, OPTION_SUPPRESS_GO_AHEAD].contains(&option) { WILL } else { WONT };
+                client.negotiate(verb, option);
+                vec![]
+            },
+            TelnetEvent::Negotiation{..} | TelnetEvent::Data(_) => vec![],
+            TelnetEvent::Subnegotiation(bytes) => {
+                let [OPTION_COM_PORT, command, ref value @ ..] = bytes[..] else {
+                    return vec![];
+                };
+                let (reply, request) = self.com_port_command(command, value, is_writer, settings);
+                let client = &mut self.clients[index];
+                if let Some(reply) = reply {
+                    let mut payload = vec![command + SERVER_OFFSET];
+                    payload.extend(reply);
+                    client.send(&telnet::subnegotiation(OPTION_COM_PORT, &payload));
+                }
+                request.into_iter().collect()
+            },
+        }
+    }
+
+    /// Handles an RFC 2217 command. Requests to change the port are only granted to the client with write access,
+    /// other clients are answered with the current settings. Values of 0 ask for the current setting.
+    ///
+    /// # Returns
+    /// The value to answer the client with, if any, and the change to the port requested.
+    fn com_port_command(&mut self, command: u8, value: &[u8], is_writer: bool, settings: &SerialStreamConfig) -> (Option<Vec<u8>>, Option<PortRequest>) {
+        let byte = value.first().copied().unwrap_or(0);
+        let granted = |request: Option<PortRequest>| request.filter(|_| is_writer);
+
+        match command {
+            SIGNATURE_COMMAND if value.is_empty() => (Some(SIGNATURE.as_bytes().to_vec()), None),
+            SET_BAUDRATE => {
+                let baud_rate = value.try_into().map(u32::from_be_bytes).unwrap_or(0);
+                let request = granted((baud_rate != 0).then_some(PortRequest::BaudRate(baud_rate)));
+                let baud_rate = if request.is_some() { baud_rate } else { settings.baud_rate };
+                (Some(baud_rate.to_be_bytes().to_vec()), request)
+            },
+            SET_DATASIZE => {
+                let request = granted((5..=8).contains(&byte).then_some(PortRequest::DataBits(byte)));
+                (Some(vec![if request.is_some() { byte } else { settings.data_bits }]), request)
+            },
+            SET_PARITY => {
+                let parity = match byte {
+                    1 => Some(Parity::None),
+                    2 => Some(Parity::Odd),
+                    3 => Some(Parity::Even),
+                    _ => None,
+                };
+                let request = granted(parity.map(PortRequest::Parity));
+                let current = match parity.filter(|_| request.is_some()).unwrap_or(settings.parity) {
+                    Parity::None => 1,
+                    Parity::Odd => 2,
+                    Parity::Even => 3,
+                };
+                (Some(vec![current]), request)
+            },
+            SET_STOPSIZE => {
+                let request = granted((1..=2).contains(&byte).then_some(PortRequest::StopBits(byte)));
+                (Some(vec![if request.is_some() { byte } else { settings.stop_bits }]), request)
+            },
+            SET_CONTROL => self.control_command(byte, is_writer, settings),
+            SET_LINESTATE_MASK | SET_MODEMSTATE_MASK => (Some(vec![byte]), None),
+            PURGE_DATA => {
+                let request = granted((1..=3).contains(&byte).then_some(PortRequest::Purge{input: byte != 2, output: byte != 1}));
+                (Some(vec![byte]), request)
+            },
+            _ => (None, None),
+        }
+    }
+
+    /// Handles the values of the SET-CONTROL command, which sets the flow control, the break and the control lines.
+    fn control_command(&mut self, value: u8, is_writer: bool, settings: &SerialStreamConfig) -> (Option<Vec<u8>>, Option<PortRequest>) {
+        let flow_control = |flow_control: FlowControl| match flow_control {
+            FlowControl::None => 1,
+            FlowControl::XonXoff => 2,
+            FlowControl::RtsCts => 3,
+        };
+        let state = |active: bool, on: u8| if active { on } else { on + 1 };
+
+        let request = match value {
+            1 => Some(PortRequest::FlowControl(FlowControl::None)),
+            2 => Some(PortRequest::FlowControl(FlowControl::XonXoff)),
+            3 => Some(PortRequest::FlowControl(FlowControl::RtsCts)),
+            5 | 6 => Some(PortRequest::Break(value == 5)),
+            8 | 9 => Some(PortRequest::Dtr(value == 8)),
+            11 | 12 => Some(PortRequest::Rts(value == 11)),
+            _ => None,
+        }.filter(|_| is_writer);
+
+        match request {
+            Some(PortRequest::Break(on)) => self.break_on = on,
+            Some(PortRequest::Dtr(active)) => self.dtr = active,
+            Some(PortRequest::Rts(active)) => self.rts = active,
+            _ => {},
+        }
+        let reply = match value {
+            0..=3 => match request {
+                Some(PortRequest::FlowControl(requested)) => flow_control(requested),
+                _ => flow_control(settings.flow_control),
+            },
+            4..=6 => state(self.break_on, 5),
+            7..=9 => state(self.dtr, 8),
+            10..=12 => state(self.rts, 11),
+            // Inbound flow control follows the outbound flow control.
+            _ => value,
+        };
+        (Some(vec![reply]), request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::telnet::{IAC, SB, SE};
+    use std::time::{Duration, Instant};
+
+    fn bridge(protocol: BridgeProtocol, write_access: WriteAccess) -> SerialBridge {
+        SerialBridge::bind(&SerialBridgeConfig { bind_address: String::from("127.0.0.1"), protocol, write_access, ..SerialBridgeConfig::new(0) }).unwrap()
+    }
+
+    fn connect(bridge: &SerialBridge) -> TcpStream {
+        let stream = TcpStream::connect(bridge.local_addr().unwrap()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    }
+
+    /// Services the bridge until it returns the expected number of events.
+    fn service_until(bridge: &mut SerialBridge, count: usize) -> Vec<BridgeEvent> {
+        let settings = SerialStreamConfig::new();
+        let started = Instant::now();
+        let mut events: Vec<BridgeEvent> = Vec::new();
+        while events.len() < count && started.elapsed() < Duration::from_secs(5) {
+            events.extend(bridge.service(&settings));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        events
+    }
+
+    fn read_exact(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len];
+        stream.read_exact(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_write_access_follows_policy() {
+        let mut bridge = bridge(BridgeProtocol::Raw, WriteAccess::FirstClient);
+        let mut first = connect(&bridge);
+        let first_address = first.local_addr().unwrap();
+        assert_eq!(service_until(&mut bridge, 2), vec![
+            BridgeEvent::Marker(format!("Bridge client {first_address} connected")),
+            BridgeEvent::Marker(format!("Bridge client {first_address} has write access")),
+        ]);
+        let mut second = connect(&bridge);
+        assert_eq!(service_until(&mut bridge, 1).len(), 1);
+
+        second.write_all(b"ignored").unwrap();
+        first.write_all(b"reset\r").unwrap();
+        assert_eq!(service_until(&mut bridge, 1), vec![BridgeEvent::Write(b"reset\r".to_vec())]);
+
+        bridge.broadcast(b"boot\n");
+        assert_eq!(read_exact(&mut first, 5), b"boot\n");
+        assert_eq!(read_exact(&mut second, 5), b"boot\n");
+
+        drop(first);
+        let events = service_until(&mut bridge, 2);
+        assert!(matches!(&events[0], BridgeEvent::Marker(text) if text.starts_with(&format!("Bridge client {first_address} disconnected"))));
+        assert_eq!(events[1], BridgeEvent::Marker(format!("Bridge client {} has write access", second.local_addr().unwrap())));
+    }
+
+    #[test]
+    fn test_rfc2217_commands() {
+        let mut bridge = bridge(BridgeProtocol::Rfc2217, WriteAccess::FirstClient);
+        let mut client = connect(&bridge);
+        service_until(&mut bridge, 2);
+        assert_eq!(read_exact(&mut client, 15), vec![IAC, WILL, 0, IAC, DO, 0, IAC, WILL, 3, IAC, DO, 3, IAC, DO, OPTION_COM_PORT]);
+
+        // Write at the current baud rate, set it to 250000, write again, then pulse DTR off.
+        let mut request = vec![b'x'];
+        request.extend(telnet::subnegotiation(OPTION_COM_PORT, &[SET_BAUDRATE, 0x00, 0x03, 0xd0, 0x90]));
+        request.extend([b'a', IAC, IAC]);
+        request.extend(telnet::subnegotiation(OPTION_COM_PORT, &[SET_CONTROL, 9]));
+        client.write_all(&request).unwrap();
+        assert_eq!(service_until(&mut bridge, 4), vec![
+            BridgeEvent::Write(vec![b'x']),
+            BridgeEvent::Request(PortRequest::BaudRate(250000)),
+            BridgeEvent::Write(vec![b'a', IAC]),
+            BridgeEvent::Request(PortRequest::Dtr(false)),
+        ]);
+        assert_eq!(read_exact(&mut client, 10), vec![IAC, SB, OPTION_COM_PORT, 101, 0x00, 0x03, 0xd0, 0x90, IAC, SE]);
+        assert_eq!(read_exact(&mut client, 6), vec![IAC, SB, OPTION_COM_PORT, 105, 9, IAC]);
+    }
+
+    #[test]
+    fn test_readers_get_current_settings() {
+        let mut bridge = bridge(BridgeProtocol::Rfc2217, WriteAccess::None);
+        let mut client = connect(&bridge);
+        service_until(&mut bridge, 1);
+        read_exact(&mut client, 15);
+
+        client.write_all(&telnet::subnegotiation(OPTION_COM_PORT, &[SET_DATASIZE, 7])).unwrap();
+        for _ in 0..20 {
+            assert!(bridge.service(&SerialStreamConfig::new()).is_empty());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(read_exact(&mut client, 6), vec![IAC, SB, OPTION_COM_PORT, 102, 8, IAC]);
+    }
+}
@@ -1,31 +1,234 @@
-use std::{io::{self, Read}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc}, thread::{self, JoinHandle}, time::Duration};
+use std::{fmt, io::{self, Read, Write}, net::SocketAddr, str::FromStr, sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc}, thread::{self, JoinHandle}, time::{Duration, Instant}};
 use chrono::Utc;
 use mio::{Events, Interest, Poll, Token};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 extern crate mio;
 extern crate mio_serial;
 use mio_serial::SerialPortBuilderExt;
+use super::serial_bridge::{BridgeEvent, PortRequest, SerialBridge, SerialBridgeConfig};
 use super::{Stream, StreamConfig, StreamTypeConfig, Message, StreamCore};
+use crate::message::MessageKind;
 use crate::{stream::INTERNAL_STREAM_TICK_MS, tools::stream_tools::stream_tools::process_raw_log_entry};
 use std::str;
 const SERIAL_TOKEN: Token = Token(0);
+/// How long a write may wait for the serial port to accept data before it fails.
+const WRITE_TIMEOUT_MS: u64 = 1000;
+/// The delay before retrying to open a missing port, doubled after each failure up to `RECONNECT_MAX_DELAY_MS`.
+const RECONNECT_MIN_DELAY_MS: u64 = 100;
+const RECONNECT_MAX_DELAY_MS: u64 = 5000;
+/// The durations of pulses and breaks given without one.
+const DEFAULT_PULSE_MS: u64 = 100;
+const DEFAULT_BREAK_MS: u64 = 250;
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The `SerialControl` enum is a command for the modem control lines of a serial port. Commands are sent to a
+/// `SerialStream` as control messages, whose text is the command, e.g. `dtr off`, `rts pulse 50` or `break`.
+///
+/// - `Dtr`, `Rts` (`dtr on`, `rts off`): Sets the line active or inactive.
+/// - `PulseDtr`, `PulseRts` (`dtr pulse [ms]`): Sets the line active for a number of milliseconds, then inactive, e.g.
+///   to reset a board.
+/// - `Break` (`break [ms]`): Sends a break for a number of milliseconds.
+///
+/// Pulses and breaks hold up the stream for their duration.
+pub enum SerialControl {
+    Dtr(bool),
+    Rts(bool),
+    PulseDtr(u64),
+    PulseRts(u64),
+    Break(u64),
+}
+
+impl SerialControl {
+    /// Applies the command to the port.
+    pub fn apply(&self, port: &mut dyn mio_serial::SerialPort) -> mio_serial::Result<()> {
+        match *self {
+            SerialControl::Dtr(active) => port.write_data_terminal_ready(active),
+            SerialControl::Rts(active) => port.write_request_to_send(active),
+            SerialControl::PulseDtr(duration_ms) => {
+                port.write_data_terminal_ready(true)?;
+                thread::sleep(Duration::from_millis(duration_ms));
+                port.write_data_terminal_ready(false)
+            }
+            SerialControl::PulseRts(duration_ms) => {
+                port.write_request_to_send(true)?;
+                thread::sleep(Duration::from_millis(duration_ms));
+                port.write_request_to_send(false)
+            }
+            SerialControl::Break(duration_ms) => {
+                port.set_break()?;
+                thread::sleep(Duration::from_millis(duration_ms));
+                port.clear_break()
+            }
+        }
+    }
+}
+
+impl fmt::Display for SerialControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = |active: bool| if active { "on" } else { "off" };
+        match self {
+            SerialControl::Dtr(active) => write!(f, "dtr {}", state(*active)),
+            SerialControl::Rts(active) => write!(f, "rts {}", state(*active)),
+            SerialControl::PulseDtr(duration_ms) => write!(f, "dtr pulse {}", duration_ms),
+            SerialControl::PulseRts(duration_ms) => write!(f, "rts pulse {}", duration_ms),
+            SerialControl::Break(duration_ms) => write!(f, "break {}", duration_ms),
+        }
+    }
+}
+
+impl FromStr for SerialControl {
+    type Err = String;
+
+    fn from_str(command: &str) -> Result<Self, Self::Err> {
+        let words: Vec<String> = command.split_whitespace().map(str::to_lowercase).collect();
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let duration = |duration: Option<&str>, default_ms: u64| match duration {
+            Some(duration) => duration.parse::<u64>().map_err(|_| format!("Invalid duration '{}' in serial control '{}'", duration, command)),
+            None => Ok(default_ms),
+        };
+
+        match words.as_slice() {
+            ["dtr", "on"] => Ok(SerialControl::Dtr(true)),
+            ["dtr", "off"] => Ok(SerialControl::Dtr(false)),
+            ["rts", "on"] => Ok(SerialControl::Rts(true)),
+            ["rts", "off"] => Ok(SerialControl::Rts(false)),
+            ["dtr", "pulse", rest @ ..] if rest.len() <= 1 => Ok(SerialControl::PulseDtr(duration(rest.first().copied(), DEFAULT_PULSE_MS)?)),
+            ["rts", "pulse", rest @ ..] if rest.len() <= 1 => Ok(SerialControl::PulseRts(duration(rest.first().copied(), DEFAULT_PULSE_MS)?)),
+            ["break", rest @ ..] if rest.len() <= 1 => Ok(SerialControl::Break(duration(rest.first().copied(), DEFAULT_BREAK_MS)?)),
+            _ => Err(format!("Unknown serial control '{}', expected 'dtr|rts on|off', 'dtr|rts pulse [ms]' or 'break [ms]'", command)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The state of the modem status lines of a serial port.
+struct ModemStatus {
+    cts: bool,
+    dsr: bool,
+    cd: bool,
+}
+
+impl ModemStatus {
+    /// Reads the lines of the port. Ports without modem lines, such as pseudo-terminals, have no status.
+    fn read(port: &mut dyn mio_serial::SerialPort) -> Option<Self> {
+        Some(ModemStatus {
+            cts: port.read_clear_to_send().ok()?,
+            dsr: port.read_data_set_ready().ok()?,
+            cd: port.read_carrier_detect().ok()?,
+        })
+    }
+
+    /// Describes each line that changed from the previous status, e.g. `CTS on`.
+    fn changes_from(&self, previous: &ModemStatus) -> Vec<String> {
+        let state = |active: bool| if active { "on" } else { "off" };
+        [("CTS", previous.cts, self.cts), ("DSR", previous.dsr, self.dsr), ("CD", previous.cd, self.cd)]
+            .into_iter()
+            .filter(|(_, was, is)| was != is)
+            .map(|(line, _, is)| format!("{} {}", line, state(is)))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Default)]
+/// The `Parity` enum selects the parity bit sent after the data bits of each character.
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Default)]
+/// The `FlowControl` enum selects how the port and the device pause each other when they cannot keep up.
+///
+/// - `None`: No flow control.
+/// - `XonXoff`: Software flow control, with XON/XOFF characters in the data.
+/// - `RtsCts`: Hardware flow control, with the RTS and CTS lines.
 pub enum FlowControl {
-    None(),
+    #[default]
+    None,
     XonXoff,
-    Etc,
+    RtsCts,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `UsbDeviceId` struct identifies a USB-serial adapter, whichever path it gets when plugged in.
+///
+/// - `vid`, `pid`: The USB vendor and product IDs, e.g. 1027 (0x0403) and 24577 (0x6001) for an FTDI FT232R.
+/// - `serial_number`: The serial number of the adapter, to tell apart adapters of the same model.
+pub struct UsbDeviceId {
+    pub vid: u16,
+    pub pid: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+}
+
+impl UsbDeviceId {
+    /// Returns true if the adapter with the given IDs and serial number is this device.
+    pub fn matches(&self, vid: u16, pid: u16, serial_number: Option<&str>) -> bool {
+        self.vid == vid && self.pid == pid && (self.serial_number.is_none() || self.serial_number.as_deref() == serial_number)
+    }
+
+    /// Returns the path of the first connected serial port of this device.
+    pub fn find_port(&self) -> Result<String, String> {
+        let ports = mio_serial::available_ports().map_err(|e| format!("Failed to list serial ports: {}", e))?;
+        ports.into_iter()
+            .find(|port| matches!(&port.port_type, mio_serial::SerialPortType::UsbPort(info) if self.matches(info.vid, info.pid, info.serial_number.as_deref())))
+            .map(|port| port.port_name)
+            .ok_or(format!("USB device {} is not connected", self))
+    }
+}
+
+impl fmt::Display for UsbDeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(serial_number) = &self.serial_number {
+            write!(f, " ({})", serial_number)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `SerialStreamConfig` struct describes a serial port and its line settings.
+///
+/// - `baud_rate`: Any rate the port supports, including non-standard rates, e.g. 250000 or 1500000.
+/// - `data_bits`: 5 to 8.
+/// - `stop_bits`: 1 or 2. Two stop bits are not supported with 5 data bits, which UARTs send as 1.5 stop bits.
+/// - `usb_device`: The USB-serial adapter to open, found by its IDs rather than by `port_path`.
+/// - `tcp_bridge`: A TCP server sharing the port with other tools, see `SerialBridgeConfig`. Changes of the line
+///   settings made by RFC 2217 clients last until the stream is restarted.
+///
+/// The stream waits for a missing port and reopens it after a disconnection, sending a marker message each time the
+/// port is connected or disconnected. Control messages sent to the stream are applied to the modem control lines, see
+/// `SerialControl`, and changes of the CTS, DSR and CD lines are sent as marker messages.
 pub struct SerialStreamConfig {
-    // TODO - These may not be correct
     pub baud_rate: u32,
     pub port_path: String,
-    pub start_bits: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usb_device: Option<UsbDeviceId>,
+    #[serde(default = "default_data_bits")]
+    pub data_bits: u8,
+    #[serde(default)]
+    pub parity: Parity,
     pub stop_bits: u8,
     pub flow_control: FlowControl,
+    #[serde(default = "default_line_terminator")]
+    pub line_terminator: String, // Written after the text of each message sent to the port.
+    #[serde(default)]
+    pub char_delay_ms: u64, // Pause between the characters written, for devices without flow control that drop input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_bridge: Option<SerialBridgeConfig>,
+}
+
+fn default_data_bits() -> u8 {
+    8
+}
+
+pub(crate) fn default_line_terminator() -> String {
+    String::from("\r\n")
 }
 
 impl SerialStreamConfig {
@@ -33,11 +236,93 @@ impl SerialStreamConfig {
         SerialStreamConfig {
             baud_rate: 9600,
             port_path: String::from(""),
-            start_bits: 1,
+            usb_device: None,
+            data_bits: default_data_bits(),
+            parity: Parity::None,
             stop_bits: 1,
-            flow_control: FlowControl::None(),
+            flow_control: FlowControl::None,
+            line_terminator: default_line_terminator(),
+            char_delay_ms: 0,
+            tcp_bridge: None,
         }
     }
+
+    /// Creates a builder opening the port with the line settings of the configuration.
+    ///
+    /// # Returns
+    /// The builder, or an error message describing the first setting, or combination of settings, that is not
+    /// supported.
+    pub fn port_builder(&self) -> Result<mio_serial::SerialPortBuilder, String> {
+        let (data_bits, parity, stop_bits, flow_control) = self.port_settings()?;
+        Ok(mio_serial::new(&self.port_path, self.baud_rate)
+            .data_bits(data_bits)
+            .parity(parity)
+            .stop_bits(stop_bits)
+            .flow_control(flow_control))
+    }
+
+    /// Applies the line settings of the configuration to a port already open.
+    pub fn apply_line_settings(&self, port: &mut dyn mio_serial::SerialPort) -> Result<(), String> {
+        let (data_bits, parity, stop_bits, flow_control) = self.port_settings()?;
+        port.set_baud_rate(self.baud_rate)
+            .and_then(|_| port.set_data_bits(data_bits))
+            .and_then(|_| port.set_parity(parity))
+            .and_then(|_| port.set_stop_bits(stop_bits))
+            .and_then(|_| port.set_flow_control(flow_control))
+            .map_err(|e| e.to_string())
+    }
+
+    fn port_settings(&self) -> Result<(mio_serial::DataBits, mio_serial::Parity, mio_serial::StopBits, mio_serial::FlowControl), String> {
+        if self.baud_rate == 0 {
+            return Err(String::from("The baud rate must be greater than zero"));
+        }
+        let data_bits = match self.data_bits {
+            5 => mio_serial::DataBits::Five,
+            6 => mio_serial::DataBits::Six,
+            7 => mio_serial::DataBits::Seven,
+            8 => mio_serial::DataBits::Eight,
+            data_bits => return Err(format!("{data_bits} data bits are not supported, use 5 to 8")),
+        };
+        let stop_bits = match (self.stop_bits, self.data_bits) {
+            (1, _) => mio_serial::StopBits::One,
+            (2, 5) => return Err(String::from("2 stop bits are not supported with 5 data bits")),
+            (2, _) => mio_serial::StopBits::Two,
+            (stop_bits, _) => return Err(format!("{stop_bits} stop bits are not supported, use 1 or 2")),
+        };
+        let parity = match self.parity {
+            Parity::None => mio_serial::Parity::None,
+            Parity::Odd => mio_serial::Parity::Odd,
+            Parity::Even => mio_serial::Parity::Even,
+        };
+        let flow_control = match self.flow_control {
+            FlowControl::None => mio_serial::FlowControl::None,
+            FlowControl::XonXoff => mio_serial::FlowControl::Software,
+            FlowControl::RtsCts => mio_serial::FlowControl::Hardware,
+        };
+        Ok((data_bits, parity, stop_bits, flow_control))
+    }
+
+    /// Describes the port opened, the USB device if one is given, otherwise the path of the port.
+    pub fn port_description(&self) -> String {
+        match &self.usb_device {
+            Some(usb_device) => format!("USB device {}", usb_device),
+            None => self.port_path.clone(),
+        }
+    }
+
+    /// Describes the line settings in the usual notation, e.g. `115200 8N1` or `9600 7E2 XonXoff`.
+    pub fn line_settings(&self) -> String {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        let flow_control = match self.flow_control {
+            FlowControl::None => String::new(),
+            flow_control => format!(" {flow_control:?}"),
+        };
+        format!("{} {}{}{}{}", self.baud_rate, self.data_bits, parity, self.stop_bits, flow_control)
+    }
 }
 
 #[derive(Debug)]
@@ -47,26 +332,35 @@ pub struct SerialStream {
     new_message_generated_sender: Sender<Message>,
     new_message_received_receiver: Option<Receiver<Message>>,
     thread_handle: Option<JoinHandle<()>>,
-    thread_stop_requsted: Arc<AtomicBool>
+    thread_stop_requsted: Arc<AtomicBool>,
+    bridge_address: Option<SocketAddr>,
 }
 
 impl SerialStream {
     pub fn new(config: StreamConfig) -> Result<Self, &'static str> {
         if let StreamTypeConfig::Serial {..} = config.type_config {
             let mut core = StreamCore::new();
+            // Messages sent to the device are not passed on, the device's own output is.
+            core.set_echo_received(false);
             Ok(Self{
                 config:config,
                 new_message_generated_sender: core.get_internal_input_sender_clone(),
                 new_message_received_receiver: Some(core.get_internal_output_receiver()),
                 core: core,
                 thread_handle: None,
-                thread_stop_requsted: Arc::new(AtomicBool::new(false))
+                thread_stop_requsted: Arc::new(AtomicBool::new(false)),
+                bridge_address: None,
             })
         }
         else{
             Err("Invalid type_config for a SerialStream")
         }
     }
+
+    /// The address the TCP bridge listens on, once the stream is started.
+    pub fn bridge_address(&self) -> Option<SocketAddr> {
+        self.bridge_address
+    }
 }
 
 
@@ -75,8 +369,10 @@ impl Stream for SerialStream {
         let stream_name = self.config.name.clone();
         let receiver: Receiver<Message> = self.new_message_received_receiver.take().expect("Receiver unavailable");
         let sender: Sender<Message> = self.new_message_generated_sender.clone();
-        let path:String;
-        let baud_rate: u32;
+        let mut serial_config: SerialStreamConfig;
+        let mut bridge: Option<SerialBridge> = None;
+        let line_terminator: String;
+        let char_delay: Duration;
         let mut buf = [0u8; 10240];
         let mut last_partial_line: String = String::new();
         let mut events = Events::with_capacity(1);
@@ -90,24 +386,26 @@ impl Stream for SerialStream {
         println!("'{}' - SerialStream starting thread", stream_name);
 
         if let StreamTypeConfig::Serial {config} = &self.config.type_config {
-            path = config.port_path.clone();
-            baud_rate = config.baud_rate;
+            config.port_builder().map_err(|e| format!("'{}' - {}: {}", stream_name, config.port_description(), e))?;
+            serial_config = config.clone();
+            if let Some(bridge_config) = &config.tcp_bridge {
+                let bound = SerialBridge::bind(bridge_config).map_err(|e| format!("'{}' - {}", stream_name, e))?;
+                self.bridge_address = bound.local_addr();
+                bridge = Some(bound);
+            }
+            line_terminator = config.line_terminator.clone();
+            char_delay = Duration::from_millis(config.char_delay_ms);
         }
         else{
             return Err("Invalid type_config for a SerialStream".to_string());
         }
 
-        // Create the serial port
-        println!("Opening {} at {},8N1", path, baud_rate);
-        let mut rx = match mio_serial::new(path, baud_rate).open_native_async() {
-            Ok(rx) => rx,
-            Err(e) => panic!("failed to open serial port; err={:?}", e),
-        };
-
-        poll.registry()
-            .register(&mut rx, SERIAL_TOKEN, Interest::READABLE)
-            .unwrap();
-
+        // The port is opened by the thread, which waits for it to appear and reopens it after a disconnection.
+        let mut port: Option<(mio_serial::SerialStream, String)> = None;
+        let mut next_attempt = Instant::now();
+        let mut reconnect_delay = Duration::from_millis(RECONNECT_MIN_DELAY_MS);
+        let mut last_open_error: Option<String> = None;
+        let mut modem_status: Option<ModemStatus> = None;
 
         self.thread_handle = Some(thread::spawn(move || loop {
             
@@ -116,10 +414,83 @@ impl Stream for SerialStream {
                 break;
             }
 
-            // New message received from core.
-            while let Ok(_msg) = receiver.try_recv() {
-                todo!("write message to serial port");
+            if port.is_none() && Instant::now() >= next_attempt {
+                match open_port(&serial_config, &poll) {
+                    Ok((mut opened, path)) => {
+                        modem_status = ModemStatus::read(&mut opened);
+                        let text = format!("Connected to {} at {}", path, serial_config.line_settings());
+                        println!("'{}' - {}", stream_name, text);
+                        let _ = sender.send(Message::new_marker(Utc::now().timestamp_millis(), stream_name.clone(), text));
+                        port = Some((opened, path));
+                        reconnect_delay = Duration::from_millis(RECONNECT_MIN_DELAY_MS);
+                        last_open_error = None;
+                    }
+                    Err(e) => {
+                        if last_open_error.as_ref() != Some(&e) {
+                            eprintln!("'{}' - Waiting for serial port, {}", stream_name, e);
+                        }
+                        last_open_error = Some(e);
+                        next_attempt = Instant::now() + reconnect_delay;
+                        reconnect_delay = (reconnect_delay * 2).min(Duration::from_millis(RECONNECT_MAX_DELAY_MS));
+                    }
+                }
+            }
+
+            // New message received from core, data is written to the port and control commands are applied to it.
+            while let Ok(msg) = receiver.try_recv() {
+                let result = match (&mut port, msg.kind) {
+                    (Some((rx, _)), MessageKind::Data) => write_paced(rx, format!("{}{}", msg.text, line_terminator).as_bytes(), char_delay).map_err(|e| e.to_string()),
+                    (Some((rx, _)), MessageKind::Control) => msg.text.parse::<SerialControl>().and_then(|control| control.apply(rx).map_err(|e| e.to_string())),
+                    (None, MessageKind::Data | MessageKind::Control) => Err(String::from("not connected")),
+                    _ => continue,
+                };
+                if let Err(e) = result {
+                    let action = if msg.kind == MessageKind::Control { "apply" } else { "write" };
+                    let text = format!("Failed to {} '{}' to serial port: {}", action, msg.text, e);
+                    eprintln!("'{}' - {}", stream_name, text);
+                    let _ = sender.send(Message::new_warning(Utc::now().timestamp_millis(), stream_name.clone(), text));
+                }
+            }
+
+            // Bridge clients are served while the port is disconnected too, their input being dropped.
+            if let Some(bridge) = &mut bridge {
+                for event in bridge.service(&serial_config) {
+                    let (action, text, result) = match event {
+                        BridgeEvent::Marker(text) => {
+                            println!("'{}' - {}", stream_name, text);
+                            let _ = sender.send(Message::new_marker(Utc::now().timestamp_millis(), stream_name.clone(), text));
+                            continue;
+                        }
+                        BridgeEvent::Write(bytes) => ("write", String::from_utf8_lossy(&bytes).to_string(), match &mut port {
+                            Some((rx, _)) => write_paced(rx, &bytes, char_delay).map_err(|e| e.to_string()),
+                            None => Err(String::from("not connected")),
+                        }),
+                        BridgeEvent::Request(request) => ("apply", format!("{request:?}"), match &mut port {
+                            Some((rx, _)) => apply_port_request(&mut serial_config, rx, request),
+                            None => Err(String::from("not connected")),
+                        }),
+                    };
+                    if let Err(e) = result {
+                        let text = format!("Failed to {} bridge client '{}' to serial port: {}", action, text, e);
+                        eprintln!("'{}' - {}", stream_name, text);
+                        let _ = sender.send(Message::new_warning(Utc::now().timestamp_millis(), stream_name.clone(), text));
+                    }
+                }
+            }
+
+            let Some((rx, path)) = &mut port else {
+                thread::sleep(Duration::from_millis(INTERNAL_STREAM_TICK_MS));
+                continue;
+            };
+
+            // Changes of the modem status lines are reported as markers.
+            let new_modem_status = ModemStatus::read(rx);
+            if let (Some(previous), Some(current)) = (&modem_status, &new_modem_status) {
+                for text in current.changes_from(previous) {
+                    let _ = sender.send(Message::new_marker(Utc::now().timestamp_millis(), stream_name.clone(), text));
+                }
             }
+            modem_status = new_modem_status;
             
             match poll.poll(&mut events, Some(Duration::from_millis(INTERNAL_STREAM_TICK_MS))) {
                 Ok(poll) => poll,
@@ -127,11 +498,20 @@ impl Stream for SerialStream {
             };
 
             // Process each event.
+            let mut disconnect_error: Option<String> = None;
             for event in events.iter() {
                 match event.token() {
                     SERIAL_TOKEN => loop {
                         match rx.read(&mut buf) {
+                            Ok(0) => {
+                                // The device hung up.
+                                disconnect_error = Some(String::from("end of file"));
+                                break;
+                            }
                             Ok(count) => {
+                                if let Some(bridge) = &mut bridge {
+                                    bridge.broadcast(&buf[..count]);
+                                }
                                 let raw_string = String::from_utf8_lossy(&buf[..count]);
                                 
                                 let (complete_lines,partial_line) = process_raw_log_entry(raw_string.to_string(), last_partial_line);
@@ -139,7 +519,10 @@ impl Stream for SerialStream {
 
                                 for line in complete_lines {
                                     let new_msg: Message = Message::new(Utc::now().timestamp_millis(), stream_name.clone(), line);
-                                    sender.send(new_msg).unwrap();
+                                    if sender.send(new_msg).is_err() {
+                                        // The core has stopped, so has the stream.
+                                        return;
+                                    }
                                 }
                             }
                             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
@@ -147,23 +530,35 @@ impl Stream for SerialStream {
                                 break;
                             }
                             Err(e) => {
-                                print!("SerialStream stopping due to read error: {}", e);
+                                disconnect_error = Some(e.to_string());
                                 break;
                             }
                         }
                     },
                     _ => {
                         // This should never happen as we only registered our
-                        // `UdpSocket` using the `UDP_SOCKET` token, but if it ever
+                        // serial port using the `SERIAL_TOKEN` token, but if it ever
                         // does we'll log it.
                         print!("SerialStream - Got event for unexpected token: {:?}, stopping", event);
                         break;
                     }
                 }
             }
+
+            if let Some(e) = disconnect_error {
+                let _ = poll.registry().deregister(rx);
+                if !last_partial_line.is_empty() {
+                    let _ = sender.send(Message::new(Utc::now().timestamp_millis(), stream_name.clone(), std::mem::take(&mut last_partial_line)));
+                }
+                let text = format!("Disconnected from {}: {}", path, e);
+                println!("'{}' - {}", stream_name, text);
+                let _ = sender.send(Message::new_marker(Utc::now().timestamp_millis(), stream_name.clone(), text));
+                port = None;
+                next_attempt = Instant::now() + reconnect_delay;
+            }
         }));
 
-        self.core.start()?;
+        self.core.start(&self.config)?;
 
         Ok(())
     }
@@ -204,4 +599,347 @@ impl Stream for SerialStream {
     fn add_outputs(&mut self, senders: Vec<Sender<Message>>) -> Result<(), String>{
         self.core.add_external_outputs(senders)
     }
+
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
+}
+
+/// Opens the serial port of the configuration and registers it with the poll instance.
+///
+/// # Returns
+/// The port and its path, or an error message naming the port.
+fn open_port(config: &SerialStreamConfig, poll: &Poll) -> Result<(mio_serial::SerialStream, String), String> {
+    let path = match &config.usb_device {
+        Some(usb_device) => usb_device.find_port()?,
+        None => config.port_path.clone(),
+    };
+    let mut port = config.port_builder()?.path(&path).open_native_async().map_err(|e| format!("{}: {}", path, e))?;
+
+    // Non-standard rates the port cannot generate are rejected, or silently rounded by some drivers.
+    match mio_serial::SerialPort::baud_rate(&port) {
+        Ok(baud_rate) if baud_rate != config.baud_rate => {
+            return Err(format!("{}: Baud rate {} is not supported by the port, it runs at {}", path, config.baud_rate, baud_rate));
+        }
+        _ => {}
+    }
+
+    poll.registry().register(&mut port, SERIAL_TOKEN, Interest::READABLE).map_err(|e| format!("{}: {}", path, e))?;
+    Ok((port, path))
+}
+
+/// Applies a change requested by the bridge client with write access. Line settings are kept in `serial_config`, so
+/// that the port is reopened with them after a disconnection.
+fn apply_port_request(serial_config: &mut SerialStreamConfig, port: &mut mio_serial::SerialStream, request: PortRequest) -> Result<(), String> {
+    use mio_serial::SerialPort;
+    let mut settings = serial_config.clone();
+    match request {
+        PortRequest::BaudRate(baud_rate) => settings.baud_rate = baud_rate,
+        PortRequest::DataBits(data_bits) => settings.data_bits = data_bits,
+        PortRequest::Parity(parity) => settings.parity = parity,
+        PortRequest::StopBits(stop_bits) => settings.stop_bits = stop_bits,
+        PortRequest::FlowControl(flow_control) => settings.flow_control = flow_control,
+        PortRequest::Break(on) => return if on { port.set_break() } else { port.clear_break() }.map_err(|e| e.to_string()),
+        PortRequest::Dtr(active) => return port.write_data_terminal_ready(active).map_err(|e| e.to_string()),
+        PortRequest::Rts(active) => return port.write_request_to_send(active).map_err(|e| e.to_string()),
+        PortRequest::Purge{input, output} => {
+            let buffer = match (input, output) {
+                (true, true) => mio_serial::ClearBuffer::All,
+                (true, false) => mio_serial::ClearBuffer::Input,
+                _ => mio_serial::ClearBuffer::Output,
+            };
+            return port.clear(buffer).map_err(|e| e.to_string());
+        }
+    }
+    settings.apply_line_settings(port)?;
+    *serial_config = settings;
+    Ok(())
+}
+
+/// Writes all the bytes to a non-blocking writer. When `char_delay` is not zero, each byte is flushed then followed
+/// by a pause of `char_delay`.
+///
+/// Writes the port cannot accept yet are retried until `WRITE_TIMEOUT_MS` elapses.
+fn write_paced<W: Write>(writer: &mut W, bytes: &[u8], char_delay: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + Duration::from_millis(WRITE_TIMEOUT_MS);
+    let chunk_size = if char_delay.is_zero() { bytes.len().max(1) } else { 1 };
+
+    for chunk in bytes.chunks(chunk_size) {
+        let mut remaining = chunk;
+        while !remaining.is_empty() {
+            match writer.write(remaining) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "serial port closed")),
+                Ok(count) => remaining = &remaining[count..],
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted => {
+                    if Instant::now() >= deadline {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "serial port not accepting data"));
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if !char_delay.is_zero() {
+            writer.flush()?;
+            thread::sleep(char_delay);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::serial_bridge::BridgeProtocol;
+    use crate::tools::telnet;
+    use mio_serial::SerialPort;
+    use std::{net::TcpStream, sync::mpsc};
+
+    fn serial_config(port_path: String) -> StreamConfig {
+        let mut serial_config = SerialStreamConfig::new();
+        serial_config.port_path = port_path;
+        StreamConfig { name: String::from("UART"), type_config: StreamTypeConfig::Serial { config: serial_config }, ..StreamConfig::default() }
+    }
+
+    fn read_until(port: &mut mio_serial::SerialStream, expected_len: usize) -> String {
+        let started = Instant::now();
+        let mut received: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 256];
+        while received.len() < expected_len && started.elapsed() < Duration::from_secs(5) {
+            match port.read(&mut buf) {
+                Ok(count) => received.extend_from_slice(&buf[..count]),
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        String::from_utf8_lossy(&received).to_string()
+    }
+
+    #[test]
+    fn test_reads_and_writes_lines() {
+        let (mut device, port) = mio_serial::SerialStream::pair().unwrap();
+        let mut stream = SerialStream::new(serial_config(port.name().unwrap())).unwrap();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        stream.add_output(sender).unwrap();
+        stream.start().unwrap();
+
+        let connected = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(connected.kind, MessageKind::Marker);
+        device.write_all(b"boot ok\n").unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap().text, "boot ok");
+
+        let input = stream.get_status().get_external_input_sender_clone();
+        input.send(Message::new_marker(0, String::from("Relay"), String::from("not written"))).unwrap();
+        input.send(Message::new(0, String::from("Host"), String::from("reset"))).unwrap();
+        assert_eq!(read_until(&mut device, 7), "reset\r\n");
+
+        stream.stop().unwrap();
+    }
+
+    /// Starts a stream sharing the port over a bridge on a free local port.
+    fn start_bridged(port_path: String, protocol: BridgeProtocol) -> (SerialStream, mpsc::Receiver<Message>) {
+        let mut config = serial_config(port_path);
+        if let StreamTypeConfig::Serial { config } = &mut config.type_config {
+            config.tcp_bridge = Some(SerialBridgeConfig { bind_address: String::from("127.0.0.1"), protocol, ..SerialBridgeConfig::new(0) });
+        }
+        let mut stream = SerialStream::new(config).unwrap();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        stream.add_output(sender).unwrap();
+        stream.start().unwrap();
+        (stream, receiver)
+    }
+
+    fn connect(stream: &SerialStream, receiver: &mpsc::Receiver<Message>, expected_marker: &str) -> TcpStream {
+        let client = TcpStream::connect(stream.bridge_address().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        std::iter::from_fn(|| receiver.recv_timeout(Duration::from_secs(5)).ok()).find(|msg| msg.text.ends_with(expected_marker)).unwrap();
+        client
+    }
+
+    #[test]
+    fn test_bridge_shares_port() {
+        let (mut device, port) = mio_serial::SerialStream::pair().unwrap();
+        let (mut stream, receiver) = start_bridged(port.name().unwrap(), BridgeProtocol::Raw);
+        let mut writer = connect(&stream, &receiver, "has write access");
+        let mut reader = connect(&stream, &receiver, "connected");
+
+        device.write_all(b"boot ok\n").unwrap();
+        for client in [&mut writer, &mut reader] {
+            let mut received = [0u8; 8];
+            client.read_exact(&mut received).unwrap();
+            assert_eq!(&received, b"boot ok\n");
+        }
+        assert_eq!(std::iter::from_fn(|| receiver.recv_timeout(Duration::from_secs(5)).ok()).find(|msg| msg.kind == MessageKind::Data).unwrap().text, "boot ok");
+
+        // Only the input of the client with write access reaches the device.
+        reader.write_all(b"ignored").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        writer.write_all(b"reset\r").unwrap();
+        assert_eq!(read_until(&mut device, 6), "reset\r");
+
+        stream.stop().unwrap();
+    }
+
+    #[test]
+    fn test_bridge_rfc2217_changes_baud_rate() {
+        let (_device, port) = mio_serial::SerialStream::pair().unwrap();
+        let (mut stream, receiver) = start_bridged(port.name().unwrap(), BridgeProtocol::Rfc2217);
+        let mut client = connect(&stream, &receiver, "has write access");
+
+        client.write_all(&telnet::subnegotiation(telnet::OPTION_COM_PORT, &[1, 0x00, 0x03, 0xd0, 0x90])).unwrap();
+        // The negotiations offered on connection, then the reply with the new baud rate.
+        let mut received = [0u8; 25];
+        client.read_exact(&mut received).unwrap();
+        assert_eq!(&received[15..], &[telnet::IAC, telnet::SB, telnet::OPTION_COM_PORT, 101, 0x00, 0x03, 0xd0, 0x90, telnet::IAC, telnet::SE]);
+        stream.stop().unwrap();
+
+        assert_eq!(port.baud_rate().unwrap(), 250000);
+    }
+
+    #[test]
+    fn test_waits_for_port_and_reconnects() {
+        let directory = tempfile::tempdir().unwrap();
+        let link = directory.path().join("ttyUSB0");
+        let mut stream = SerialStream::new(serial_config(link.to_str().unwrap().to_string())).unwrap();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        stream.add_output(sender).unwrap();
+        stream.start().unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+
+        for _ in 0..2 {
+            let (mut device, port) = mio_serial::SerialStream::pair().unwrap();
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(port.name().unwrap(), &link).unwrap();
+
+            let connected = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!((connected.kind, connected.text), (MessageKind::Marker, format!("Connected to {} at 9600 8N1", link.display())));
+            device.write_all(b"partial").unwrap();
+            assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+            drop((device, port));
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap().text, "partial");
+            let disconnected = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(disconnected.kind, MessageKind::Marker);
+            assert!(disconnected.text.starts_with(&format!("Disconnected from {}", link.display())), "{}", disconnected.text);
+        }
+
+        stream.stop().unwrap();
+    }
+
+    #[test]
+    fn test_usb_device_matches() {
+        let usb_device = UsbDeviceId { vid: 0x0403, pid: 0x6001, serial_number: Some(String::from("A1")) };
+        assert!(usb_device.matches(0x0403, 0x6001, Some("A1")));
+        assert!(!usb_device.matches(0x0403, 0x6001, Some("B2")));
+        assert!(!usb_device.matches(0x0403, 0x6015, Some("A1")));
+        assert!(UsbDeviceId { serial_number: None, ..usb_device.clone() }.matches(0x0403, 0x6001, None));
+        assert_eq!(usb_device.to_string(), "0403:6001 (A1)");
+    }
+
+    #[test]
+    fn test_line_settings_applied() {
+        let (_device, port) = mio_serial::SerialStream::pair().unwrap();
+        let mut config = serial_config(port.name().unwrap());
+        if let StreamTypeConfig::Serial { config } = &mut config.type_config {
+            config.baud_rate = 250000;
+            config.data_bits = 7;
+            config.parity = Parity::Even;
+            config.stop_bits = 2;
+            assert_eq!(config.line_settings(), "250000 7E2");
+        }
+        let mut stream = SerialStream::new(config).unwrap();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        stream.add_output(sender).unwrap();
+        stream.start().unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap().kind, MessageKind::Marker);
+        stream.stop().unwrap();
+
+        // Linux pseudo-terminals always use 8 data bits without parity.
+        assert_eq!(port.baud_rate().unwrap(), 250000);
+        assert_eq!(port.stop_bits().unwrap(), mio_serial::StopBits::Two);
+    }
+
+    #[test]
+    fn test_unsupported_line_settings_rejected() {
+        let unsupported = |data_bits: u8, stop_bits: u8| {
+            SerialStreamConfig { data_bits, stop_bits, ..SerialStreamConfig::new() }.port_builder().err().unwrap()
+        };
+        assert_eq!(unsupported(9, 1), "9 data bits are not supported, use 5 to 8");
+        assert_eq!(unsupported(8, 3), "3 stop bits are not supported, use 1 or 2");
+        assert_eq!(unsupported(5, 2), "2 stop bits are not supported with 5 data bits");
+
+        let mut config = serial_config(String::from("/dev/does-not-exist"));
+        if let StreamTypeConfig::Serial { config } = &mut config.type_config {
+            config.baud_rate = 0;
+        }
+        let error = SerialStream::new(config).unwrap().start().err().unwrap();
+        assert_eq!(error, "'UART' - /dev/does-not-exist: The baud rate must be greater than zero");
+    }
+
+    #[test]
+    fn test_serial_control_commands() {
+        assert_eq!("DTR on".parse::<SerialControl>(), Ok(SerialControl::Dtr(true)));
+        assert_eq!("rts  off".parse::<SerialControl>(), Ok(SerialControl::Rts(false)));
+        assert_eq!("dtr pulse".parse::<SerialControl>(), Ok(SerialControl::PulseDtr(DEFAULT_PULSE_MS)));
+        assert_eq!("rts pulse 20".parse::<SerialControl>(), Ok(SerialControl::PulseRts(20)));
+        assert_eq!("break".parse::<SerialControl>(), Ok(SerialControl::Break(DEFAULT_BREAK_MS)));
+        assert!("break soon".parse::<SerialControl>().unwrap_err().starts_with("Invalid duration 'soon'"));
+        assert!("dtr toggle".parse::<SerialControl>().unwrap_err().starts_with("Unknown serial control 'dtr toggle'"));
+
+        for control in [SerialControl::Dtr(false), SerialControl::PulseRts(50), SerialControl::Break(10)] {
+            assert_eq!(control.to_string().parse::<SerialControl>(), Ok(control));
+        }
+    }
+
+    #[test]
+    fn test_modem_status_changes() {
+        let previous = ModemStatus { cts: false, dsr: true, cd: false };
+        assert!(previous.changes_from(&previous).is_empty());
+        assert_eq!(ModemStatus { cts: true, dsr: false, cd: false }.changes_from(&previous), vec!["CTS on", "DSR off"]);
+    }
+
+    #[test]
+    fn test_failed_control_reported() {
+        let (_device, port) = mio_serial::SerialStream::pair().unwrap();
+        let mut stream = SerialStream::new(serial_config(port.name().unwrap())).unwrap();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        stream.add_output(sender).unwrap();
+        stream.start().unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap().kind, MessageKind::Marker);
+
+        let input = stream.get_status().get_external_input_sender_clone();
+        input.send(Message::new_control(0, String::from("Host"), String::from("reset now"))).unwrap();
+        let warning = std::iter::from_fn(|| receiver.recv_timeout(Duration::from_secs(5)).ok()).find(|msg| msg.kind == MessageKind::Warning).unwrap();
+        stream.stop().unwrap();
+
+        assert!(warning.text.starts_with("Failed to apply 'reset now' to serial port: Unknown serial control"), "{}", warning.text);
+    }
+
+    struct SlowWriter {
+        written: Vec<u8>,
+        would_block: bool,
+    }
+
+    impl Write for SlowWriter {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.would_block = !self.would_block;
+            if self.would_block {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            self.written.push(bytes[0]);
+            Ok(1)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_paced_retries_and_paces() {
+        let mut writer = SlowWriter { written: Vec::new(), would_block: false };
+        let started = Instant::now();
+        write_paced(&mut writer, b"abc\r\n", Duration::from_millis(10)).unwrap();
+        assert_eq!(writer.written, b"abc\r\n");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
 }
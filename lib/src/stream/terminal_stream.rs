@@ -1,11 +1,12 @@
 use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc}, thread::{self, JoinHandle}, time::Duration};
 use chrono::{Utc, Local, TimeZone};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use crate::stream::INTERNAL_STREAM_TICK_MS;
 use super::{Stream, StreamConfig, StreamTypeConfig, Message, StreamCore};
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub struct TerminalStreamConfig{
     pub inter_message_generation_period_ms: u64,
     pub generates_messages: bool,
@@ -89,7 +90,7 @@ impl Stream for TerminalStream {
             }
         }));
 
-        self.core.start()?;
+        self.core.start(&self.config)?;
 
         Ok(())
     }
@@ -131,6 +132,10 @@ impl Stream for TerminalStream {
         self.core.add_external_outputs(senders)
     }
 
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
+
 }
 
 impl TerminalStream {
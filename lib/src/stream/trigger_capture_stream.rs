@@ -0,0 +1,395 @@
+use std::{collections::VecDeque, fs::File, io::Write, sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::stream::INTERNAL_STREAM_TICK_MS;
+use super::file_stream::{FileFormat, FileStream};
+use super::{Stream, StreamConfig, StreamTypeConfig, Message, StreamCore};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `TriggerCaptureStreamConfig` struct configures a `TriggerCaptureStream`.
+///
+/// - `file_path`: The capture files are named like the files of a `FileStream`, tagged with the capture number,
+///   e.g. `2024-01-02_030405_capture1_log.txt`.
+/// - `format`: The format in which the captured messages are written.
+/// - `trigger_pattern`: A regular expression starting a capture when it matches the text of a message.
+/// - `pre_trigger_ms`: How long before the trigger the captured messages start.
+/// - `post_trigger_ms`: How long after the trigger the capture continues.
+/// - `rearm`: Wait for the next trigger once a capture has completed. Otherwise only a single capture is made.
+/// - `holdoff_ms`: How long to ignore the trigger after a capture has completed.
+/// - `max_captures`: Stop capturing after this many captures, if set.
+pub struct TriggerCaptureStreamConfig {
+    pub file_path: String,
+    #[serde(default)]
+    pub format: FileFormat,
+    pub trigger_pattern: String,
+    pub pre_trigger_ms: u64,
+    pub post_trigger_ms: u64,
+    pub rearm: bool,
+    pub holdoff_ms: u64,
+    pub max_captures: Option<u32>,
+}
+
+impl TriggerCaptureStreamConfig {
+    pub fn new(file_path: String, trigger_pattern: String) -> Self {
+        TriggerCaptureStreamConfig {
+            file_path,
+            format: FileFormat::Text,
+            trigger_pattern,
+            pre_trigger_ms: 5000,
+            post_trigger_ms: 5000,
+            rearm: true,
+            holdoff_ms: 0,
+            max_captures: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+/// The file operations requested by the capture state machine.
+enum CaptureAction {
+    Open{capture: u32},
+    Write(Message),
+    Close{capture: u32},
+}
+
+#[derive(Debug, PartialEq)]
+enum CaptureState {
+    Armed,
+    Capturing{until_ms: i64},
+    Holdoff{until_ms: i64},
+    Done,
+}
+
+/// The capture state machine, driven by the message timestamps so that replayed captures trigger alike.
+struct TriggerCapture {
+    config: TriggerCaptureStreamConfig,
+    trigger: Regex,
+    pre_trigger_buffer: VecDeque<Message>,
+    state: CaptureState,
+    captures: u32,
+}
+
+impl TriggerCapture {
+    fn new(config: TriggerCaptureStreamConfig) -> Result<Self, String> {
+        let trigger = Regex::new(&config.trigger_pattern).map_err(|e| e.to_string())?;
+        let state = if config.max_captures == Some(0) { CaptureState::Done } else { CaptureState::Armed };
+        Ok(TriggerCapture { config, trigger, pre_trigger_buffer: VecDeque::new(), state, captures: 0 })
+    }
+
+    fn process(&mut self, msg: Message) -> Vec<CaptureAction> {
+        let now_ms = msg.timestamp_ms;
+        let mut actions = self.tick(now_ms);
+
+        match self.state {
+            CaptureState::Armed if self.trigger.is_match(&msg.text) => {
+                self.captures += 1;
+                actions.push(CaptureAction::Open{capture: self.captures});
+                actions.extend(self.pre_trigger_buffer.drain(..).map(CaptureAction::Write));
+                actions.push(CaptureAction::Write(msg));
+                self.state = CaptureState::Capturing{until_ms: now_ms.saturating_add(self.config.post_trigger_ms as i64)};
+                return actions;
+            },
+            CaptureState::Capturing{..} => actions.push(CaptureAction::Write(msg.clone())),
+            CaptureState::Done => return actions,
+            _ => {},
+        }
+
+        // Messages seen while capturing may still fall within the pre-trigger window of the next capture.
+        self.pre_trigger_buffer.push_back(msg);
+        let oldest_ms = now_ms.saturating_sub(self.config.pre_trigger_ms as i64);
+        while self.pre_trigger_buffer.front().is_some_and(|msg| msg.timestamp_ms < oldest_ms) {
+            self.pre_trigger_buffer.pop_front();
+        }
+        actions
+    }
+
+    fn tick(&mut self, now_ms: i64) -> Vec<CaptureAction> {
+        let mut actions: Vec<CaptureAction> = Vec::new();
+
+        if let CaptureState::Capturing{until_ms} = self.state {
+            if now_ms > until_ms {
+                actions.push(CaptureAction::Close{capture: self.captures});
+                self.state = self.after_capture(until_ms);
+            }
+        }
+        if let CaptureState::Holdoff{until_ms} = self.state {
+            if now_ms >= until_ms {
+                self.state = CaptureState::Armed;
+            }
+        }
+        actions
+    }
+
+    /// Completes a capture in progress, e.g. when the stream is stopped.
+    fn finish(&mut self) -> Vec<CaptureAction> {
+        match self.state {
+            CaptureState::Capturing{..} => {
+                self.state = CaptureState::Done;
+                vec![CaptureAction::Close{capture: self.captures}]
+            },
+            _ => vec![],
+        }
+    }
+
+    fn after_capture(&self, completed_ms: i64) -> CaptureState {
+        if !self.config.rearm || self.config.max_captures.is_some_and(|max_captures| self.captures >= max_captures) {
+            CaptureState::Done
+        } else {
+            CaptureState::Holdoff{until_ms: completed_ms.saturating_add(self.config.holdoff_ms as i64)}
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A sink stream keeping a rolling window of the recent messages, and writing it to a new file whenever a
+/// message matches the trigger pattern, followed by the messages received during the post-trigger window.
+pub struct TriggerCaptureStream {
+    config: StreamConfig,
+    core: StreamCore,
+    new_message_received_receiver: Option<Receiver<Message>>,
+    thread_handle: Option<JoinHandle<()>>,
+    thread_stop_requsted: Arc<AtomicBool>
+}
+
+impl Stream for TriggerCaptureStream {
+
+    fn start(&mut self) -> Result<(), String> {
+        let stream_name = self.config.name.clone();
+        let receiver: Receiver<Message> = self.new_message_received_receiver.take().ok_or("Receiver unavailable")?;
+        let stop_requested = Arc::clone(&self.thread_stop_requsted);
+        let mut capture: TriggerCapture;
+
+        if let StreamTypeConfig::TriggerCapture {config} = &self.config.type_config {
+            capture = TriggerCapture::new(config.clone()).map_err(|e| format!("'{stream_name}' - Invalid trigger pattern: {e}"))?;
+        }
+        else{
+            return Err("Invalid type_config for a TriggerCaptureStream".to_string());
+        }
+
+        println!("'{}' - TriggerCaptureStream starting thread", stream_name);
+
+        self.thread_handle = Some(thread::spawn(move || {
+            let mut file: Option<File> = None;
+            // The message clock, advanced by the time elapsed since the last message was received.
+            let mut last_message: Option<(i64, Instant)> = None;
+
+            loop {
+                while let Ok(msg) = receiver.try_recv() {
+                    last_message = Some((msg.timestamp_ms, Instant::now()));
+                    let actions = capture.process(msg);
+                    Self::apply_actions(&stream_name, &capture.config, &mut file, actions);
+                }
+
+                if let Some((timestamp_ms, received)) = last_message {
+                    let actions = capture.tick(timestamp_ms + received.elapsed().as_millis() as i64);
+                    Self::apply_actions(&stream_name, &capture.config, &mut file, actions);
+                }
+
+                thread::sleep(Duration::from_millis(INTERNAL_STREAM_TICK_MS));
+
+                if stop_requested.load(Ordering::Relaxed) {
+                    let actions = capture.finish();
+                    Self::apply_actions(&stream_name, &capture.config, &mut file, actions);
+                    break;
+                }
+            }
+        }));
+
+        self.core.start(&self.config)?;
+
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), String> {
+        println!("'{}' - TriggerCaptureStream stopping", self.config.name);
+        self.core.stop()?;
+        self.await_thread_stop()
+    }
+
+    fn await_thread_stop(&mut self) -> Result<(), String> {
+        self.thread_stop_requsted.store(true, Ordering::Relaxed);
+
+        if let Some(thread_handle) = self.thread_handle.take() {
+            thread_handle.join().expect("Failed to join thread");
+            Ok(())
+        } else {
+            Err("Thread handle not available".to_string())
+        }
+    }
+
+    fn get_config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    fn get_status(&self) -> &StreamCore {
+        &self.core
+    }
+
+    fn get_uuid(&self) -> &Uuid{
+        &self.config.uuid
+    }
+
+    fn add_output(&mut self, receiver: Sender<Message>) -> Result<(), String>{
+        self.core.add_external_output(receiver)
+    }
+
+    fn add_outputs(&mut self, senders: Vec<Sender<Message>>) -> Result<(), String>{
+        self.core.add_external_outputs(senders)
+    }
+
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
+}
+
+impl TriggerCaptureStream {
+    pub fn new(config: StreamConfig) -> Result<Self, &'static str> {
+        if let StreamTypeConfig::TriggerCapture {..} = config.type_config {
+            let mut core = StreamCore::new();
+
+            Ok(Self{
+                config,
+                new_message_received_receiver: Some(core.get_internal_output_receiver()),
+                core,
+                thread_handle: None,
+                thread_stop_requsted: Arc::new(AtomicBool::new(false))
+            })
+        }
+        else{
+            Err("Invalid type_config for a TriggerCaptureStream")
+        }
+    }
+
+    /// Performs the file operations requested by the capture state machine. A capture whose file cannot be
+    /// created or written is abandoned, the following captures are still attempted.
+    fn apply_actions(stream_name: &str, config: &TriggerCaptureStreamConfig, file: &mut Option<File>, actions: Vec<CaptureAction>) {
+        for action in actions {
+            match action {
+                CaptureAction::Open{capture} => {
+                    println!("'{stream_name}' - Triggered capture {capture}");
+                    *file = FileStream::create_log_file(stream_name, &config.file_path, Some(&format!("capture{capture}")), &config.format)
+                        .map_err(|e| eprintln!("'{stream_name}' - Failed to create capture file: {e}"))
+                        .ok();
+                },
+                CaptureAction::Write(msg) => {
+                    if let Some(open_file) = file {
+                        if let Err(e) = writeln!(open_file, "{}", FileStream::format_message(&msg, &config.format)) {
+                            eprintln!("'{stream_name}' - Failed to write capture file: {e}");
+                            *file = None;
+                        }
+                    }
+                },
+                CaptureAction::Close{capture} => {
+                    if file.take().is_some() {
+                        println!("'{stream_name}' - Capture {capture} complete");
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(timestamp_ms: i64, text: &str) -> Message {
+        Message::new(timestamp_ms, String::from("Device"), String::from(text))
+    }
+
+    fn capture_config() -> TriggerCaptureStreamConfig {
+        let mut config = TriggerCaptureStreamConfig::new(String::from("capture.txt"), String::from("PANIC"));
+        config.pre_trigger_ms = 100;
+        config.post_trigger_ms = 100;
+        config
+    }
+
+    fn written_texts(actions: &[CaptureAction]) -> Vec<String> {
+        actions.iter().filter_map(|action| match action {
+            CaptureAction::Write(msg) => Some(msg.text.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_pre_and_post_trigger_windows() {
+        let mut capture = TriggerCapture::new(capture_config()).unwrap();
+
+        assert!(capture.process(msg(0, "too old")).is_empty());
+        assert!(capture.process(msg(150, "before")).is_empty());
+        let actions = capture.process(msg(200, "PANIC"));
+        assert_eq!(actions[0], CaptureAction::Open{capture: 1});
+        assert_eq!(written_texts(&actions), vec!["before", "PANIC"]);
+
+        assert_eq!(written_texts(&capture.process(msg(300, "after"))), vec!["after"]);
+        let actions = capture.process(msg(301, "too late"));
+        assert_eq!(actions, vec![CaptureAction::Close{capture: 1}]);
+    }
+
+    #[test]
+    fn test_post_trigger_window_closed_by_tick() {
+        let mut capture = TriggerCapture::new(capture_config()).unwrap();
+
+        capture.process(msg(0, "PANIC"));
+        assert!(capture.tick(100).is_empty());
+        assert_eq!(capture.tick(101), vec![CaptureAction::Close{capture: 1}]);
+    }
+
+    #[test]
+    fn test_holdoff_and_max_captures() {
+        let mut config = capture_config();
+        config.holdoff_ms = 500;
+        config.max_captures = Some(2);
+        let mut capture = TriggerCapture::new(config).unwrap();
+
+        assert!(capture.process(msg(0, "PANIC")).contains(&CaptureAction::Open{capture: 1}));
+        capture.tick(101);
+        assert!(written_texts(&capture.process(msg(200, "PANIC"))).is_empty());
+        assert!(capture.process(msg(601, "PANIC")).contains(&CaptureAction::Open{capture: 2}));
+        capture.tick(702);
+        assert!(capture.process(msg(5000, "PANIC")).is_empty());
+    }
+
+    #[test]
+    fn test_single_shot() {
+        let mut config = capture_config();
+        config.rearm = false;
+        let mut capture = TriggerCapture::new(config).unwrap();
+
+        capture.process(msg(0, "PANIC"));
+        capture.tick(101);
+        assert!(capture.process(msg(200, "PANIC")).is_empty());
+    }
+
+    #[test]
+    fn test_capture_written_to_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut type_config = capture_config();
+        type_config.file_path = format!("{}/capture.txt", directory.path().display());
+        type_config.post_trigger_ms = 60000;
+
+        let mut config = StreamConfig::default();
+        config.name = String::from("Capture");
+        config.type_config = StreamTypeConfig::TriggerCapture { config: type_config };
+        let mut stream = TriggerCaptureStream::new(config).unwrap();
+        let sender: Sender<Message> = stream.get_status().get_external_input_sender_clone();
+
+        stream.start().unwrap();
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        sender.send(msg(now_ms, "before")).unwrap();
+        sender.send(msg(now_ms + 1, "PANIC")).unwrap();
+        sender.send(msg(now_ms + 2, "after")).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        stream.stop().unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(directory.path()).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_str().unwrap().ends_with("_capture1_capture.txt"));
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].ends_with("'before'") && lines[2].ends_with("'PANIC'") && lines[3].ends_with("'after'"));
+    }
+}
@@ -1,18 +1,20 @@
 use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, Sender}, Arc}, thread::{self, JoinHandle}, time::Duration};
 use chrono::Utc;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use crate::stream::INTERNAL_STREAM_TICK_MS;
 use super::{Stream, StreamConfig, StreamTypeConfig, Message, StreamCore};
+use std::io::ErrorKind;
 use std::net::UdpSocket;
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub enum UdpDirection{
     UdpOutput,
     UdpInput
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub struct UdpStreamConfig{
     pub direction: UdpDirection,
     pub output_ip_address: String,
@@ -81,6 +83,8 @@ impl Stream for UdpStream {
                 Ok(in_socket) => Some(in_socket),
                 Err(e) => panic!("failed to open port; err={:?}", e)
             };
+            // Wake up regularly, so that a stop request is noticed while no messages arrive.
+            in_socket.as_ref().unwrap().set_read_timeout(Some(Duration::from_millis(INTERNAL_STREAM_TICK_MS))).map_err(|e| e.to_string())?;
         }
 
         self.thread_handle = Some(thread::Builder::new().name(stream_name.clone()).spawn(move || loop {
@@ -91,13 +95,10 @@ impl Stream for UdpStream {
                     Ok((size, _)) => {
                         let received_message = String::from_utf8_lossy(&buf[..size]);
                         let timestamp = Utc::now().timestamp_millis();
-                        let message = Message {
-                            originator: stream_name.clone(),
-                            text: received_message.to_string(),
-                            timestamp_ms: timestamp
-                        };
+                        let message = Message::new(timestamp, stream_name.clone(), received_message.to_string());
                         sender.send(message).expect(&format!("{stream_name} - Failed to send message"));
                     },
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {},
                     Err(e) => {
                         eprintln!("Failed to receive message: {}", e);
                         break;
@@ -136,7 +137,7 @@ impl Stream for UdpStream {
             }
         }).map_err(|e| e.to_string())?);
 
-        self.core.start()?;
+        self.core.start(&self.config)?;
 
         Ok(())
     }
@@ -178,6 +179,10 @@ impl Stream for UdpStream {
         self.core.add_external_outputs(senders)
     }
 
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
+
 }
 
 impl UdpStream {
@@ -1,5 +1,6 @@
 use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::Sender, Arc}, thread::{self, JoinHandle}, time::Duration};
 use chrono::Utc;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 extern crate mio;
@@ -8,7 +9,7 @@ use super::{Stream, StreamConfig, StreamTypeConfig, Message, StreamCore};
 use crate::{stream::INTERNAL_STREAM_TICK_MS, tools::waveforms_i2c::waveforms_i2c::WaveformsI2cControl};
 use std::str;
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
 pub struct WaveformsI2cStreamConfig {
     pub scl_pin: u8,
     pub sda_pin: u8,
@@ -95,7 +96,7 @@ impl Stream for WaveformsI2cStream {
             }
         }));
 
-        self.core.start()?;
+        self.core.start(&self.config)?;
 
         Ok(())
     }
@@ -136,4 +137,8 @@ impl Stream for WaveformsI2cStream {
     fn add_outputs(&mut self, senders: Vec<Sender<Message>>) -> Result<(), String>{
         self.core.add_external_outputs(senders)
     }
+
+    fn set_output_streams(&mut self, output_streams: Vec<Uuid>) {
+        self.config.output_streams = output_streams;
+    }
 }
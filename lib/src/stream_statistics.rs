@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+/// Counters describing the traffic handled by a single stream.
+///
+/// - `messages_received`: Messages received from other streams.
+/// - `messages_generated`: Messages generated by the stream itself.
+/// - `messages_suppressed`: Messages dropped or collapsed by the stream's stages.
+/// - `late_messages`: Messages that arrived at a reorder buffer after later messages had already been released.
+/// - `device_reboots`: Device reboots detected from the device's uptime going backwards.
+/// - `lost_messages`: Messages missing from a device's sequence counter.
+/// - `duplicate_messages`: Messages repeating the previous value of a device's sequence counter.
+pub struct StreamStatistics {
+    pub messages_received: u64,
+    pub messages_generated: u64,
+    pub messages_suppressed: u64,
+    pub late_messages: u64,
+    pub device_reboots: u64,
+    pub lost_messages: u64,
+    pub duplicate_messages: u64,
+}
+
+impl StreamStatistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,140 @@
+use crate::config_manager::stream_uuid_from_id;
+use crate::stream::{StreamConfig, StreamTypeConfig};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+/// The placeholder replaced by the path of the device.
+const DEVICE_PLACEHOLDER: &str = "{device}";
+/// The placeholder replaced by the file name of the device.
+const DEVICE_NAME_PLACEHOLDER: &str = "{device_name}";
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `StreamTemplate` struct describes a stream created for each device matching a glob pattern, e.g. a serial
+/// stream for each USB-serial adapter attached.
+///
+/// - `device_glob`: The pattern matching the paths of the devices, e.g. `/dev/ttyUSB*` or `/dev/serial/by-id/*`.
+/// - `stream`: The configuration of the streams. In every text of the configuration, `{device}` is replaced by the
+///   path of the device and `{device_name}` by its file name, e.g. `logs/{device_name}.txt`. Serial streams without a
+///   `port_path` open the device.
+///
+/// Each stream's `id` is the `id` of the template's stream followed by the device name, and its UUID is derived from
+/// its `id`. Names without `{device_name}` are followed by the device name.
+pub struct StreamTemplate {
+    pub device_glob: String,
+    pub stream: StreamConfig,
+}
+
+impl StreamTemplate {
+    pub fn new(device_glob: String, stream: StreamConfig) -> Self {
+        StreamTemplate { device_glob, stream }
+    }
+
+    /// Returns the paths of the devices currently matching the glob pattern, in alphabetical order.
+    pub fn matching_devices(&self) -> Result<Vec<String>, String> {
+        let paths = glob::glob(&self.device_glob).map_err(|e| format!("Invalid device glob '{}': {e}", self.device_glob))?;
+        Ok(paths.filter_map(Result::ok).map(|path| path.to_string_lossy().to_string()).collect())
+    }
+
+    /// Creates the configuration of the stream for a device.
+    pub fn instantiate(&self, device: &str) -> Result<StreamConfig, String> {
+        let template_id = self.stream.id.as_deref().ok_or(format!("Template '{}' has no id", self.stream.name))?;
+        let device_name = Path::new(device).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(device.to_string());
+
+        let mut value = serde_json::to_value(&self.stream).map_err(|e| e.to_string())?;
+        replace_placeholders(&mut value, device, &device_name);
+        let mut config: StreamConfig = serde_json::from_value(value).map_err(|e| e.to_string())?;
+
+        let id = format!("{template_id}-{device_name}");
+        config.uuid = stream_uuid_from_id(&id);
+        config.id = Some(id);
+        if !self.stream.name.contains(DEVICE_NAME_PLACEHOLDER) {
+            config.name = format!("{} {device_name}", config.name);
+        }
+        if let StreamTypeConfig::Serial { config: serial_config } = &mut config.type_config {
+            if serial_config.port_path.is_empty() {
+                serial_config.port_path = device.to_string();
+            }
+        }
+        Ok(config)
+    }
+
+    /// Creates the configuration of a stream for each device currently matching the glob pattern.
+    pub fn expand(&self) -> Result<Vec<StreamConfig>, String> {
+        self.matching_devices()?.iter().map(|device| self.instantiate(device)).collect()
+    }
+}
+
+/// Expands each template, returning the configurations of all the streams created, in the order of the templates.
+pub fn expand_templates(templates: &[StreamTemplate]) -> Result<Vec<StreamConfig>, String> {
+    let mut stream_configs: Vec<StreamConfig> = Vec::new();
+    for template in templates {
+        stream_configs.extend(template.expand()?);
+    }
+    Ok(stream_configs)
+}
+
+fn replace_placeholders(value: &mut Value, device: &str, device_name: &str) {
+    match value {
+        Value::String(text) => *text = text.replace(DEVICE_PLACEHOLDER, device).replace(DEVICE_NAME_PLACEHOLDER, device_name),
+        Value::Array(array) => array.iter_mut().for_each(|value| replace_placeholders(value, device, device_name)),
+        Value::Object(object) => object.values_mut().for_each(|value| replace_placeholders(value, device, device_name)),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::file_stream::FileStreamConfig;
+    use crate::stream::serial_stream::SerialStreamConfig;
+
+    fn serial_template(device_glob: String) -> StreamTemplate {
+        StreamTemplate::new(device_glob, StreamConfig {
+            id: Some(String::from("uart")),
+            name: String::from("UART"),
+            type_config: StreamTypeConfig::Serial { config: SerialStreamConfig::new() },
+            ..StreamConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_expands_over_matching_devices() {
+        let directory = tempfile::tempdir().unwrap();
+        for file_name in ["ttyUSB1", "ttyUSB0", "ttyACM0"] {
+            std::fs::write(directory.path().join(file_name), "").unwrap();
+        }
+        let template = serial_template(format!("{}/ttyUSB*", directory.path().display()));
+
+        let streams = template.expand().unwrap();
+        let ids: Vec<&str> = streams.iter().map(|stream| stream.id.as_deref().unwrap()).collect();
+        assert_eq!(ids, vec!["uart-ttyUSB0", "uart-ttyUSB1"]);
+        assert_eq!(streams[0].name, "UART ttyUSB0");
+        assert_eq!(streams[0].uuid, stream_uuid_from_id("uart-ttyUSB0"));
+        let StreamTypeConfig::Serial { config } = &streams[1].type_config else { panic!("not a serial stream") };
+        assert_eq!(config.port_path, format!("{}/ttyUSB1", directory.path().display()));
+    }
+
+    #[test]
+    fn test_placeholders_replaced() {
+        let template = StreamTemplate::new(String::new(), StreamConfig {
+            id: Some(String::from("log")),
+            name: String::from("Log of {device_name}"),
+            type_config: StreamTypeConfig::File { config: FileStreamConfig::new(String::from("logs/{device_name}.txt")) },
+            ..StreamConfig::default()
+        });
+
+        let stream = template.instantiate("/dev/serial/by-id/usb-FTDI_A1").unwrap();
+        assert_eq!(stream.name, "Log of usb-FTDI_A1");
+        assert_eq!(stream.type_config, StreamTypeConfig::File { config: FileStreamConfig::new(String::from("logs/usb-FTDI_A1.txt")) });
+    }
+
+    #[test]
+    fn test_template_needs_id_and_valid_glob() {
+        let mut template = serial_template(String::from("/dev/[ttyUSB"));
+        assert!(template.expand().is_err());
+        template.stream.id = None;
+        assert!(template.instantiate("/dev/ttyUSB0").is_err());
+    }
+}
@@ -1,15 +1,36 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use crate::stream_template::StreamTemplate;
 
+/// The version of the configuration schema written by this version of the library. Configurations of earlier
+/// versions are migrated when they are loaded, see `config_migration`.
+pub const CONFIG_VERSION: u32 = 3;
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+/// The `StreamsConfig` struct describes the streams run by a `StreamsEngine` and how they are linked.
+///
+/// - `version`: The version of the configuration schema. Configurations written before the version was recorded are
+///   version 1.
+/// - `stream_configs`: The configuration of each stream.
+/// - `templates`: Templates of streams created for each device matching a glob pattern.
 pub struct StreamsConfig {
-    pub stream_configs: Vec<crate::stream::StreamConfig>
+    #[serde(default = "unversioned")]
+    pub version: u32,
+    pub stream_configs: Vec<crate::stream::StreamConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<StreamTemplate>,
+}
+
+fn unversioned() -> u32 {
+    1
 }
 
 impl StreamsConfig{
     pub fn new() -> Self {
         StreamsConfig {
-            stream_configs: Vec::new()
+            version: CONFIG_VERSION,
+            stream_configs: Vec::new(),
+            templates: Vec::new(),
         }
     }
-}
\ No newline at end of file
+}
@@ -1,19 +1,70 @@
-use std::sync::mpsc::Sender;
+use std::collections::HashSet;
+use chrono::Utc;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
 use uuid::Uuid;
 use std::string::String;
 
 use crate::message::Message;
+use crate::stream_statistics::StreamStatistics;
+use crate::streams_config::StreamsConfig;
+use crate::stream_template::{expand_templates, StreamTemplate};
 use crate::stream::file_stream::FileStream;
-use crate::stream::serial_stream::SerialStream;
+use crate::stream::pty_stream::PtyStream;
+use crate::stream::replay_stream::ReplayStream;
+use crate::stream::ring_buffer_stream::{RingBufferHandle, RingBufferStream};
+use crate::stream::rule_engine_stream::RuleEngineStream;
+use crate::stream::serial_stream::{SerialControl, SerialStream};
+use crate::stream::trigger_capture_stream::TriggerCaptureStream;
 use crate::stream::udp_stream::UdpStream;
 use crate::stream::waveforms_i2c_stream::WaveformsI2cStream;
-use crate::stream::{terminal_stream::TerminalStream, Stream, StreamConfig, StreamTypeConfig};
+use crate::stream::{terminal_stream::TerminalStream, Stream, StreamConfig, StreamTarget, StreamTypeConfig};
 
 
 /// The `StreamsEngine` struct manages a collection of `Stream` instances.
 /// It provides methods to add new streams and ensure their UUIDs are unique.
 pub struct StreamsEngine{
-    streams: Vec<Box<dyn Stream>>
+    streams: Vec<Box<dyn Stream>>,
+    started: bool,
+    templates: Vec<StreamTemplate>,
+    template_instances: Vec<Uuid>, // The streams created from the templates.
+    failed: Vec<StreamConfig>, // The streams that failed to start, kept so that the streams linked to them stay valid.
+}
+
+#[derive(Debug, Default, PartialEq)]
+/// The `ConfigChanges` struct lists the names of the streams affected when a new configuration is applied to a
+/// `StreamsEngine`.
+///
+/// - `added`: Streams created and, if the engine is running, started.
+/// - `removed`: Streams stopped and removed.
+/// - `restarted`: Streams whose configuration changed, replaced by a new stream with the new configuration.
+/// - `relinked`: Streams left running whose output or target streams were linked again.
+pub struct ConfigChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub restarted: Vec<String>,
+    pub relinked: Vec<String>,
+}
+
+impl ConfigChanges {
+    /// Returns `true` if applying the configuration left every stream untouched.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.restarted.is_empty() && self.relinked.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigChanges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "No changes");
+        }
+        let sections = [("Added", &self.added), ("Removed", &self.removed), ("Restarted", &self.restarted), ("Relinked", &self.relinked)];
+        let descriptions: Vec<String> = sections.iter()
+            .filter(|(_, names)| !names.is_empty())
+            .map(|(label, names)| format!("{label}: {}", names.join(", ")))
+            .collect();
+        write!(f, "{}", descriptions.join("; "))
+    }
 }
 
 /// Manages a collection of `Stream` instances and provides methods to add new streams and ensure their UUIDs are unique.
@@ -25,14 +76,16 @@ pub struct StreamsEngine{
 /// The `link_streams` method is responsible for connecting the output streams of each stream to the corresponding input streams, by gathering the UUIDs and senders for each stream's outputs and then adding the collected senders to the corresponding streams.
 ///
 /// The `initialise` method is used to validate the configuration of the streams and then link them together. The `start` method is used to start all the streams managed by the engine.
+///
+/// The `apply_config` method replaces the configuration of the streams, e.g. when the configuration file is reloaded, only stopping and starting the streams whose configuration changed.
 impl StreamsEngine {
     pub fn new() -> Self {
-        StreamsEngine { streams: Vec::new()}
+        StreamsEngine { streams: Vec::new(), started: false, templates: Vec::new(), template_instances: Vec::new(), failed: Vec::new() }
     }
 
     /// Adds a new stream to the `StreamsEngine` based on the provided `StreamConfig`.
     ///
-    /// This method handles the creation of the appropriate stream type (Terminal, Serial, File, or UDP) and adds it to the internal `streams` vector.
+    /// This method handles the creation of the appropriate stream type (Terminal, Serial, File, UDP, Waveforms I2C, RingBuffer or Replay) and adds it to the internal `streams` vector.
     ///
     /// # Arguments
     /// * `config_to_add` - The `StreamConfig` containing the configuration for the new stream to be added.
@@ -41,35 +94,64 @@ impl StreamsEngine {
     /// * `Result<(), String>` - Returns `Ok(())` if the stream was successfully added, or an error message as a `String` if the stream type is invalid.
     pub fn add_stream(&mut self, config_to_add: StreamConfig) -> Result<(), String> {
         println!("Adding stream: {}", config_to_add.name);
+        let stream = Self::create_stream(config_to_add)?;
+        self.streams.push(stream);
+        Ok(())
+    }
 
-        match config_to_add.type_config {
-            StreamTypeConfig::Terminal { .. } => {
-                let stream = TerminalStream::new(config_to_add)?;
-                self.streams.push(Box::new(stream));
-            },
-            StreamTypeConfig::Serial { .. } => {
-                let stream: SerialStream = SerialStream::new(config_to_add)?;
-                self.streams.push(Box::new(stream));
-            },
-            StreamTypeConfig::File { .. } =>  {
-                let stream: FileStream = FileStream::new(config_to_add)?;
-                self.streams.push(Box::new(stream));
-            },
-            StreamTypeConfig::Udp { .. } =>  {
-                let stream: UdpStream = UdpStream::new(config_to_add)?;
-                self.streams.push(Box::new(stream));
-            },
-            StreamTypeConfig::WaveformsI2c { .. } => {
-                let stream: WaveformsI2cStream = WaveformsI2cStream::new(config_to_add)?;
-                self.streams.push(Box::new(stream));
-            },
+    /// Creates a stream of the type given by the `StreamConfig`.
+    fn create_stream(config: StreamConfig) -> Result<Box<dyn Stream>, String> {
+        let stream: Box<dyn Stream> = match config.type_config {
+            StreamTypeConfig::Terminal { .. } => Box::new(TerminalStream::new(config)?),
+            StreamTypeConfig::Serial { .. } => Box::new(SerialStream::new(config)?),
+            StreamTypeConfig::File { .. } => Box::new(FileStream::new(config)?),
+            StreamTypeConfig::Udp { .. } => Box::new(UdpStream::new(config)?),
+            StreamTypeConfig::WaveformsI2c { .. } => Box::new(WaveformsI2cStream::new(config)?),
+            StreamTypeConfig::RingBuffer { .. } => Box::new(RingBufferStream::new(config)?),
+            StreamTypeConfig::Replay { .. } => Box::new(ReplayStream::new(config)?),
+            StreamTypeConfig::TriggerCapture { .. } => Box::new(TriggerCaptureStream::new(config)?),
+            StreamTypeConfig::RuleEngine { .. } => Box::new(RuleEngineStream::new(config)?),
+            StreamTypeConfig::Pty { .. } => Box::new(PtyStream::new(config)?),
             _ => {
-                return Err(format!("Invalid stream type: {}", config_to_add.type_config));
+                return Err(format!("Invalid stream type: {}", config.type_config));
             }
+        };
+        Ok(stream)
+    }
+
+    /// Adds a stream to the `StreamsEngine` for each of the stream configurations of a `StreamsConfig`, and for
+    /// each device matching its templates.
+    ///
+    /// # Returns
+    /// * `Result<(), String>` - Returns `Ok(())` if all the streams were successfully added, or the error of the first stream that could not be added.
+    pub fn add_streams(&mut self, config: StreamsConfig) -> Result<(), String> {
+        let instances: Vec<StreamConfig> = expand_templates(&config.templates)?;
+        for stream_config in config.stream_configs {
+            self.add_stream(stream_config)?;
+        }
+        for instance in instances {
+            self.template_instances.push(instance.uuid);
+            self.add_stream(instance)?;
         }
+        self.templates.extend(config.templates);
         Ok(())
     }
 
+    /// Returns the configuration of all the streams in the `StreamsEngine`, in the order they were added, and the
+    /// templates the engine expands. The streams created from the templates are left out.
+    pub fn get_config(&self) -> StreamsConfig {
+        StreamsConfig {
+            stream_configs: self.streams.iter()
+                .map(|stream| stream.get_config())
+                .chain(self.failed.iter())
+                .filter(|config| !self.template_instances.contains(&config.uuid))
+                .cloned()
+                .collect(),
+            templates: self.templates.clone(),
+            ..StreamsConfig::new()
+        }
+    }
+
     /// Checks if all the UUIDs in the provided vector are unique.
     ///
     /// This function takes a vector of references to `Uuid` objects and checks if all the UUIDs are unique. It does this by first cloning the input vector, sorting it in place, and then checking for any consecutive duplicate UUIDs.
@@ -110,23 +192,22 @@ impl StreamsEngine {
 
     /// Checks if the `StreamsEngine` is in a valid state.
     ///
+    /// If the configurations of all the streams are valid, as checked by `validate_configs()`, the function returns `Ok(())`. Otherwise, it returns an error message.
+    fn is_valid(&self) -> Result<(), String> {
+        let configs: Vec<&StreamConfig> = self.streams.iter().map(|stream| stream.get_config()).collect();
+        Self::validate_configs(&configs)
+    }
+
+    /// Checks if a set of stream configurations can be run together.
+    ///
     /// This function checks the following conditions:
     /// - The UUIDs of the individual streams must be unique.
     /// - The output stream UUIDs of each stream must match the UUIDs of the streams.
-    ///
-    /// If both conditions are met, the function returns `Ok(())`. Otherwise, it returns an error message.
-    fn is_valid(&self) -> Result<(), String> {
-        let mut stream_uuids: Vec<&Uuid> = Vec::new();
-        let mut output_stream_uuids: Vec<&Uuid> = Vec::new();
-
-        for stream in self.streams.iter() {
-            let config: &StreamConfig = stream.get_config();
-
-            stream_uuids.push(&config.uuid);
-            for output_stream_uuid in config.output_streams.iter(){
-                output_stream_uuids.push(output_stream_uuid);
-            }
-        }
+    /// - The target stream UUIDs of each stream must match the UUIDs of the streams.
+    fn validate_configs(configs: &[&StreamConfig]) -> Result<(), String> {
+        let stream_uuids: Vec<&Uuid> = configs.iter().map(|config| &config.uuid).collect();
+        let output_stream_uuids: Vec<&Uuid> = configs.iter().flat_map(|config| config.output_streams.iter()).collect();
+        let target_stream_uuids: Vec<Uuid> = configs.iter().flat_map(|config| config.target_streams()).collect();
 
         // The uuids of the individual streams must be unique.
         if !Self::are_all_uuids_unique(&stream_uuids) {
@@ -138,62 +219,55 @@ impl StreamsEngine {
             return Err("The output stream uuids of each stream must match the uuids of the streams.".to_string());
         }
 
+        // The target stream uuids of each stream must match the uuids of the streams.
+        if !Self::all_elements_in_other(&target_stream_uuids.iter().collect(), &stream_uuids){
+            return Err("The target stream uuids of each stream must match the uuids of the streams.".to_string());
+        }
+
         Ok(())
     }
 
-    /// Adds the collected output senders to the corresponding streams in the `StreamsEngine`.
+    /// Links a stream in the `StreamsEngine` to its output streams and to the streams it targets directly.
     ///
-    /// This function takes a vector of tuples, where each tuple contains a `Uuid` and a vector of `Sender<Message>` objects.
-    /// It iterates through the streams in the `StreamsEngine` and, for each stream, it checks if the stream's UUID matches the UUID in the tuple.
-    /// If a match is found, the function adds the corresponding vector of `Sender<Message>` objects to the stream.
-    ///
-    /// # Arguments
-    /// * `uuids_with_output_senders` - A vector of tuples, where each tuple contains a `Uuid` and a vector of `Sender<Message>` objects.
-    fn add_outputs_to_streams(&mut self, uuids_with_output_senders: Vec<(Uuid, Vec<Sender<Message>>)>) -> Result<(), String> {
-        for stream in self.streams.iter_mut() {
-            for (uuid, senders) in uuids_with_output_senders.iter() {
-                if stream.get_uuid() == uuid {
-                    stream.add_outputs(senders.clone())?;
-                }
+    /// The output senders replace any the stream was linked to before, so a running stream can be relinked
+    /// after the streams it outputs to have been restarted or its output streams have changed.
+    fn link_stream(&mut self, uuid: &Uuid) -> Result<(), String> {
+        // Gather the senders (no mutable borrow yet)
+        let Some(stream) = self.streams.iter().find(|stream| stream.get_uuid() == uuid) else {
+            return Ok(());
+        };
+        let senders: Vec<Sender<Message>> = stream.get_config().output_streams.iter()
+            .filter_map(|output_uuid| self.get_input_sender(output_uuid))
+            .collect();
+        stream.get_status().set_linked_outputs(senders);
+
+        let mut targets: Vec<(Uuid, StreamTarget)> = Vec::new();
+        for target_uuid in stream.get_target_streams() {
+            if let Some(target_stream) = self.streams.iter().find(|target_stream| *target_stream.get_uuid() == target_uuid) {
+                let target = StreamTarget {
+                    input_sender: target_stream.get_status().get_external_input_sender_clone(),
+                    inject_sender: target_stream.get_status().get_internal_input_sender_clone(),
+                };
+                targets.push((target_uuid, target));
             }
         }
+
+        // Mutate the stream (now we do the mutable borrow)
+        if let Some(stream) = self.streams.iter_mut().find(|stream| stream.get_uuid() == uuid) {
+            for (target_uuid, target) in targets {
+                stream.add_target_stream(target_uuid, target)?;
+            }
+        }
+
         Ok(())
     }
-    
-    /// Links the streams in the `StreamsEngine` by gathering the UUIDs and senders for each stream's outputs,
-    /// and then adding the collected senders to the corresponding streams.
-    ///
-    /// This function is divided into two phases:
-    /// 1. Gather the UUIDs and senders (no mutable borrow yet)
-    /// 2. Mutate the streams (now we do the mutable borrow)
-    ///
-    /// The first phase iterates through the streams, collects the output senders for each stream, and stores
-    /// the UUID and associated senders in a `Vec`. The second phase then calls `add_outputs_to_streams` to
-    /// add the collected senders to the corresponding streams.
+
+    /// Links every stream in the `StreamsEngine` to its output streams and to the streams it targets directly.
     fn link_streams(&mut self) -> Result<(), String> {
-        // Phase 1: Gather the UUIDs and senders (no mutable borrow yet)
-        let mut uuids_with_output_senders: Vec<(Uuid, Vec<Sender<Message>>)> = Vec::new();
-    
-        for stream in self.streams.iter() {
-            let config: &StreamConfig = stream.get_config();
-            let mut senders: Vec<Sender<Message>> = Vec::new();
-            
-            // Gather the output senders for each stream
-            for output_uuid in &config.output_streams {
-                for stream_inner in self.streams.iter() {
-                    if stream_inner.get_uuid() == output_uuid {
-                        senders.push(stream_inner.get_status().get_external_input_sender_clone());
-                    }
-                }
-            }
-    
-            // Store the collected UUID and associated senders
-            uuids_with_output_senders.push((stream.get_uuid().clone(), senders));
+        let uuids: Vec<Uuid> = self.streams.iter().map(|stream| *stream.get_uuid()).collect();
+        for uuid in uuids.iter() {
+            self.link_stream(uuid)?;
         }
-    
-        // Phase 2: Mutate the streams (now we do the mutable borrow)
-        self.add_outputs_to_streams(uuids_with_output_senders)?;
-
         Ok(())
     }
 
@@ -216,12 +290,242 @@ impl StreamsEngine {
     /// # Returns
     /// A `Result` with an empty `()` value on success, or a `String` error message on failure.
     pub fn start(&mut self) -> Result<(), String> {
+        self.started = true;
         for stream in self.streams.iter_mut() {
             stream.start()?
         }
         Ok(())
     }
 
+    /// Applies a new configuration to the streams of the `StreamsEngine`, e.g. after the configuration file changed.
+    ///
+    /// The templates of the configuration are expanded over the devices currently present. The streams are matched by UUID:
+    /// - Streams only in the new configuration are added and, if the engine is running, started.
+    /// - Streams missing from the new configuration are stopped and removed.
+    /// - Streams whose configuration changed, other than their output streams, are replaced by a new stream.
+    /// - Streams whose output streams changed, or whose output or target streams were added or replaced, are
+    ///   relinked while they keep running.
+    ///
+    /// The other streams are left untouched. Replaced streams lose the subscriptions made with `subscribe()`.
+    ///
+    /// # Returns
+    /// The streams affected, or an error message if the new configuration is invalid, in which case the running
+    /// streams are left unchanged. Streams that fail to stop or start are reported in the error message once the
+    /// rest of the configuration has been applied. The streams that failed to start are not run but are kept in the
+    /// configuration, see `get_config()`, so applying a configuration again retries them.
+    pub fn apply_config(&mut self, new_config: StreamsConfig) -> Result<ConfigChanges, String> {
+        self.apply_expanded_config(new_config, true)
+    }
+
+    /// Expands the templates again, adding the streams of devices that appeared and removing those of devices that
+    /// disappeared, as `apply_config` does. The other streams are left untouched, including the streams that failed to
+    /// start, which are only retried by `apply_config`.
+    pub fn refresh_templates(&mut self) -> Result<ConfigChanges, String> {
+        if self.templates.is_empty() {
+            return Ok(ConfigChanges::default());
+        }
+        self.apply_expanded_config(self.get_config(), false)
+    }
+
+    fn apply_expanded_config(&mut self, new_config: StreamsConfig, retry_failed: bool) -> Result<ConfigChanges, String> {
+        let instances: Vec<StreamConfig> = expand_templates(&new_config.templates)?;
+        let instance_uuids: Vec<Uuid> = instances.iter().map(|instance| instance.uuid).collect();
+        let mut stream_configs: Vec<StreamConfig> = new_config.stream_configs;
+        stream_configs.extend(instances);
+
+        self.apply_stream_configs(stream_configs, new_config.templates, instance_uuids, retry_failed)
+    }
+
+    /// Replaces the streams of the `StreamsEngine` with streams of the given configurations, and its templates with
+    /// the templates they were expanded from, see `apply_config`. Unless `retry_failed` is set, the streams that failed
+    /// to start are left failed while their configuration is unchanged.
+    fn apply_stream_configs(&mut self, stream_configs: Vec<StreamConfig>, templates: Vec<StreamTemplate>, template_instances: Vec<Uuid>, retry_failed: bool) -> Result<ConfigChanges, String> {
+        Self::validate_configs(&stream_configs.iter().collect::<Vec<&StreamConfig>>())?;
+
+        // Create the new streams first, so an invalid stream leaves the running streams unchanged.
+        let mut new_streams: Vec<(StreamConfig, Option<Box<dyn Stream>>)> = Vec::new();
+        let mut still_failed: Vec<StreamConfig> = Vec::new();
+        for config in stream_configs {
+            if !retry_failed && self.failed.contains(&config) {
+                still_failed.push(config);
+                continue;
+            }
+            let unchanged = self.streams.iter()
+                .find(|stream| *stream.get_uuid() == config.uuid)
+                .is_some_and(|stream| !Self::needs_restart(stream.get_config(), &config));
+            let stream = if unchanged { None } else { Some(Self::create_stream(config.clone())?) };
+            new_streams.push((config, stream));
+        }
+
+        self.templates = templates;
+        self.template_instances = template_instances;
+        self.failed = still_failed;
+        let mut changes = ConfigChanges::default();
+        let mut old_streams: Vec<Box<dyn Stream>> = std::mem::take(&mut self.streams);
+        let mut to_link: HashSet<Uuid> = HashSet::new();
+        let mut to_start: Vec<Uuid> = Vec::new();
+
+        // Failures are collected rather than returned, every stream is put in place whatever happens to the others.
+        let mut errors: Vec<String> = Vec::new();
+        let mut report = |name: &str, action: &str, result: Result<(), String>| {
+            if let Err(e) = result {
+                errors.push(format!("Failed to {action} '{name}': {e}"));
+            }
+        };
+
+        // Stop the streams that were removed.
+        let new_uuids: HashSet<Uuid> = new_streams.iter().map(|(config, _)| config.uuid).collect();
+        for mut stream in old_streams.extract_if(.., |stream| !new_uuids.contains(stream.get_uuid())) {
+            if self.started {
+                let result = stream.stop();
+                report(&stream.get_config().name, "stop", result);
+            }
+            changes.removed.push(stream.get_config().name.clone());
+        }
+
+        for (config, new_stream) in new_streams {
+            let old_index = old_streams.iter().position(|stream| *stream.get_uuid() == config.uuid);
+            match (old_index, new_stream) {
+                (Some(index), None) => {
+                    let mut stream = old_streams.remove(index);
+                    if stream.get_config().output_streams != config.output_streams {
+                        stream.set_output_streams(config.output_streams);
+                        to_link.insert(config.uuid);
+                    }
+                    self.streams.push(stream);
+                },
+                (Some(index), Some(new_stream)) => {
+                    let mut stream = old_streams.remove(index);
+                    if self.started {
+                        report(&config.name, "stop", stream.stop());
+                    }
+                    changes.restarted.push(config.name);
+                    to_link.insert(config.uuid);
+                    to_start.push(config.uuid);
+                    self.streams.push(new_stream);
+                },
+                (None, Some(new_stream)) => {
+                    changes.added.push(config.name);
+                    to_link.insert(config.uuid);
+                    to_start.push(config.uuid);
+                    self.streams.push(new_stream);
+                },
+                (None, None) => unreachable!("Streams missing from the engine are always created"),
+            }
+        }
+
+        // Streams sending to a new stream need the senders of the new stream.
+        for stream in self.streams.iter() {
+            let config = stream.get_config();
+            if config.output_streams.iter().chain(config.target_streams().iter()).any(|uuid| to_start.contains(uuid)) {
+                to_link.insert(config.uuid);
+            }
+        }
+
+        let uuids: Vec<Uuid> = self.streams.iter().map(|stream| *stream.get_uuid()).collect();
+        for uuid in uuids.iter().filter(|uuid| to_link.contains(uuid)) {
+            let result = self.link_stream(uuid);
+            let name = self.streams.iter().find(|stream| stream.get_uuid() == uuid).map(|stream| stream.get_config().name.clone()).unwrap_or_default();
+            report(&name, "link", result);
+            if !to_start.contains(uuid) {
+                changes.relinked.push(name);
+            }
+        }
+
+        if self.started {
+            let mut failed: HashSet<Uuid> = HashSet::new();
+            for stream in self.streams.iter_mut().filter(|stream| to_start.contains(stream.get_uuid())) {
+                if let Err(e) = stream.start() {
+                    report(&stream.get_config().name, "start", Err(e));
+                    failed.insert(*stream.get_uuid());
+                }
+            }
+            for stream in self.streams.extract_if(.., |stream| failed.contains(stream.get_uuid())) {
+                self.failed.push(stream.get_config().clone());
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(changes)
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    /// Returns `true` if a stream must be replaced to apply the new configuration. Changes to the output streams
+    /// only need the stream to be relinked.
+    fn needs_restart(current: &StreamConfig, new: &StreamConfig) -> bool {
+        let mut current = current.clone();
+        current.output_streams = new.output_streams.clone();
+        current != *new
+    }
+
+    /// Returns a snapshot of the statistics of the stream with the given UUID.
+    ///
+    /// # Returns
+    /// `Some(StreamStatistics)` if the engine contains the stream, otherwise `None`.
+    pub fn get_statistics(&self, uuid: &Uuid) -> Option<StreamStatistics> {
+        self.streams.iter()
+            .find(|stream| stream.get_uuid() == uuid)
+            .map(|stream| stream.get_status().get_statistics())
+    }
+
+    /// Returns a handle onto the messages held by the ring buffer stream with the given UUID.
+    ///
+    /// # Returns
+    /// `Some(RingBufferHandle)` if the engine contains the stream and it is a ring buffer stream, otherwise `None`.
+    pub fn get_ring_buffer(&self, uuid: &Uuid) -> Option<RingBufferHandle> {
+        self.streams.iter()
+            .find(|stream| stream.get_uuid() == uuid)
+            .and_then(|stream| stream.get_ring_buffer())
+    }
+
+    /// Returns the UUID of the stream with the given ID or, failing that, of the first stream with the given name.
+    pub fn find_stream(&self, id_or_name: &str) -> Option<Uuid> {
+        self.streams.iter()
+            .find(|stream| stream.get_config().id.as_deref() == Some(id_or_name))
+            .or_else(|| self.streams.iter().find(|stream| stream.get_config().name == id_or_name))
+            .map(|stream| *stream.get_uuid())
+    }
+
+    /// Subscribes to the messages output by the stream with the given UUID.
+    ///
+    /// Subscriptions must be made before the engine is started.
+    ///
+    /// # Returns
+    /// A `Receiver` for the stream's output messages, or an error message if the engine does not contain the stream.
+    pub fn subscribe(&mut self, uuid: &Uuid) -> Result<Receiver<Message>, String> {
+        let stream = self.streams.iter_mut()
+            .find(|stream| stream.get_uuid() == uuid)
+            .ok_or(format!("Unknown stream: {uuid}"))?;
+        let (sender, receiver) = mpsc::channel::<Message>();
+        stream.add_output(sender)?;
+        Ok(receiver)
+    }
+
+    /// Returns a sender delivering messages to the stream with the given UUID, as if they were routed to it from
+    /// another stream. Bidirectional streams write the messages to their device.
+    pub fn get_input_sender(&self, uuid: &Uuid) -> Option<Sender<Message>> {
+        self.streams.iter()
+            .find(|stream| stream.get_uuid() == uuid)
+            .map(|stream| stream.get_status().get_external_input_sender_clone())
+    }
+
+    /// Sends a control command to the serial stream with the given UUID, e.g. to reset the board attached to it.
+    ///
+    /// # Returns
+    /// An error message if the engine does not contain the stream or it is not a serial stream.
+    pub fn send_serial_control(&self, uuid: &Uuid, control: SerialControl) -> Result<(), String> {
+        let stream = self.streams.iter()
+            .find(|stream| stream.get_uuid() == uuid)
+            .ok_or(format!("Unknown stream: {uuid}"))?;
+        if !matches!(stream.get_config().type_config, StreamTypeConfig::Serial{..}) {
+            return Err(format!("'{}' is not a serial stream", stream.get_config().name));
+        }
+        let msg = Message::new_control(Utc::now().timestamp_millis(), String::from("Engine"), control.to_string());
+        stream.get_status().get_external_input_sender_clone().send(msg).map_err(|e| e.to_string())
+    }
+
     /// Stops all the streams in the `StreamsEngine`.
     ///
     /// This function iterates through all the streams in the `StreamsEngine` and calls the `stop()` method on each one.
@@ -229,6 +533,7 @@ impl StreamsEngine {
     /// # Returns
     /// A `Result` with an empty `()` value on success, or a `String` error message on failure.
     pub fn stop(&mut self) -> Result<(), String> {
+        self.started = false;
         for stream in self.streams.iter_mut() {
             stream.stop()?
         }
@@ -240,6 +545,8 @@ impl StreamsEngine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{thread, time::{Duration, Instant}};
+    use crate::stream::ring_buffer_stream::RingBufferStreamConfig;
 
     #[test]
     fn test_new_streams_engine() {
@@ -247,6 +554,269 @@ mod tests {
         assert!(engine.streams.is_empty());
     }
 
+    #[test]
+    fn test_config_round_trip() {
+        let file_config = StreamConfig {
+            name: String::from("File"),
+            type_config: StreamTypeConfig::File { config: crate::stream::file_stream::FileStreamConfig::new(String::from("log.txt")) },
+            ..StreamConfig::default()
+        };
+        let mut terminal_config = StreamConfig {
+            name: String::from("Terminal"),
+            type_config: StreamTypeConfig::Terminal { config: crate::stream::terminal_stream::TerminalStreamConfig::new() },
+            ..StreamConfig::default()
+        };
+        terminal_config.add_output_stream(file_config.uuid);
+
+        let mut config = StreamsConfig::new();
+        config.stream_configs = vec![terminal_config, file_config];
+
+        let mut engine = StreamsEngine::new();
+        engine.add_streams(config.clone()).unwrap();
+        engine.initialise().unwrap();
+        assert_eq!(engine.get_config(), config);
+    }
+
+
+    fn ring_buffer_config(name: &str, capacity: usize) -> StreamConfig {
+        StreamConfig {
+            name: String::from(name),
+            type_config: StreamTypeConfig::RingBuffer { config: RingBufferStreamConfig { capacity, ..RingBufferStreamConfig::new() } },
+            ..StreamConfig::default()
+        }
+    }
+
+    /// Waits for the ring buffer to hold the expected number of messages.
+    fn wait_for_len(handle: &RingBufferHandle, len: usize) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if handle.len() == len {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_apply_config_only_touches_changed_streams() {
+        let mut relay = ring_buffer_config("Relay", 100);
+        let sink_a = ring_buffer_config("Sink A", 100);
+        let sink_b = ring_buffer_config("Sink B", 100);
+        relay.add_output_stream(sink_a.uuid);
+
+        let mut engine = StreamsEngine::new();
+        engine.add_streams(StreamsConfig { stream_configs: vec![relay.clone(), sink_a.clone()], ..StreamsConfig::new() }).unwrap();
+        engine.initialise().unwrap();
+        engine.start().unwrap();
+
+        let relay_input = engine.get_input_sender(&relay.uuid).unwrap();
+        let relay_buffer = engine.get_ring_buffer(&relay.uuid).unwrap();
+        relay_input.send(Message::new(0, String::from("Test"), String::from("first"))).unwrap();
+        assert!(wait_for_len(&engine.get_ring_buffer(&sink_a.uuid).unwrap(), 1));
+
+        // Replace Sink A by Sink B as the output of the relay.
+        relay.output_streams = vec![sink_b.uuid];
+        let changes = engine.apply_config(StreamsConfig { stream_configs: vec![relay.clone(), sink_b.clone()], ..StreamsConfig::new() }).unwrap();
+        assert_eq!(changes, ConfigChanges {
+            added: vec![String::from("Sink B")],
+            removed: vec![String::from("Sink A")],
+            restarted: vec![],
+            relinked: vec![String::from("Relay")],
+        });
+        relay_input.send(Message::new(1, String::from("Test"), String::from("second"))).unwrap();
+        assert!(wait_for_len(&engine.get_ring_buffer(&sink_b.uuid).unwrap(), 1));
+
+        // Changing Sink B restarts it, and the relay is relinked to the new stream.
+        let sink_b = StreamConfig { type_config: ring_buffer_config("Sink B", 50).type_config, ..sink_b };
+        let changes = engine.apply_config(StreamsConfig { stream_configs: vec![relay.clone(), sink_b.clone()], ..StreamsConfig::new() }).unwrap();
+        assert_eq!(changes.restarted, vec![String::from("Sink B")]);
+        assert_eq!(changes.relinked, vec![String::from("Relay")]);
+        relay_input.send(Message::new(2, String::from("Test"), String::from("third"))).unwrap();
+        assert!(wait_for_len(&engine.get_ring_buffer(&sink_b.uuid).unwrap(), 1));
+
+        // The relay kept running throughout.
+        assert!(wait_for_len(&relay_buffer, 3));
+        assert_eq!(engine.get_config().stream_configs, vec![relay, sink_b]);
+        engine.stop().unwrap();
+    }
+
+    #[test]
+    fn test_apply_invalid_config_keeps_streams() {
+        let relay = ring_buffer_config("Relay", 100);
+        let config = StreamsConfig { stream_configs: vec![relay.clone()], ..StreamsConfig::new() };
+        let mut engine = StreamsEngine::new();
+        engine.add_streams(config.clone()).unwrap();
+        engine.initialise().unwrap();
+
+        let mut invalid = relay.clone();
+        invalid.add_output_stream(Uuid::new_v4());
+        assert!(engine.apply_config(StreamsConfig { stream_configs: vec![invalid], ..StreamsConfig::new() }).is_err());
+        assert_eq!(engine.get_config(), config);
+
+        let changes = engine.apply_config(config).unwrap();
+        assert!(changes.is_empty());
+        assert_eq!(changes.to_string(), "No changes");
+    }
+
+    #[test]
+    fn test_apply_config_with_failing_stream_keeps_others() {
+        let relay = ring_buffer_config("Relay", 100);
+        let mut sink = ring_buffer_config("Sink", 100);
+        let mut engine = StreamsEngine::new();
+        engine.add_streams(StreamsConfig { stream_configs: vec![relay.clone(), sink.clone()], ..StreamsConfig::new() }).unwrap();
+        engine.initialise().unwrap();
+        engine.start().unwrap();
+
+        // The new stream cannot listen on a port already in use, the changed sink and the relay are still applied.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut failing = ring_buffer_config("Failing", 100);
+        if let StreamTypeConfig::RingBuffer { config } = &mut failing.type_config {
+            config.query_port = Some(listener.local_addr().unwrap().port());
+        }
+        sink.type_config = ring_buffer_config("Sink", 50).type_config;
+        let error = engine.apply_config(StreamsConfig { stream_configs: vec![relay.clone(), sink.clone(), failing.clone()], ..StreamsConfig::new() }).unwrap_err();
+        assert!(error.starts_with("Failed to start 'Failing'"), "{error}");
+        assert_eq!(engine.get_config().stream_configs, vec![relay.clone(), sink.clone(), failing.clone()]);
+
+        engine.get_input_sender(&relay.uuid).unwrap().send(Message::new(0, String::from("Test"), String::from("still running"))).unwrap();
+        assert!(wait_for_len(&engine.get_ring_buffer(&relay.uuid).unwrap(), 1));
+
+        // Once the port is free, applying the configuration again starts the stream.
+        drop(listener);
+        let changes = engine.apply_config(StreamsConfig { stream_configs: vec![relay, sink, failing], ..StreamsConfig::new() }).unwrap();
+        assert_eq!(changes.added, vec![String::from("Failing")]);
+        engine.stop().unwrap();
+    }
+
+    #[test]
+    fn test_failed_stream_keeps_links_valid() {
+        let directory = tempfile::tempdir().unwrap();
+        let template = StreamTemplate::new(format!("{}/ttyUSB*", directory.path().display()), StreamConfig { id: Some(String::from("device")), ..ring_buffer_config("Device", 10) });
+        let mut relay = ring_buffer_config("Relay", 100);
+        let mut engine = StreamsEngine::new();
+        engine.add_streams(StreamsConfig { stream_configs: vec![relay.clone()], templates: vec![template.clone()], ..StreamsConfig::new() }).unwrap();
+        engine.initialise().unwrap();
+        engine.start().unwrap();
+
+        // The relay outputs to a stream that fails to start, refreshing the templates must not find the link dangling.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut failing = ring_buffer_config("Failing", 100);
+        if let StreamTypeConfig::RingBuffer { config } = &mut failing.type_config {
+            config.query_port = Some(listener.local_addr().unwrap().port());
+        }
+        relay.add_output_stream(failing.uuid);
+        assert!(engine.apply_config(StreamsConfig { stream_configs: vec![relay, failing], templates: vec![template], ..StreamsConfig::new() }).is_err());
+
+        assert!(engine.refresh_templates().unwrap().is_empty());
+        std::fs::write(directory.path().join("ttyUSB0"), "").unwrap();
+        assert_eq!(engine.refresh_templates().unwrap().added, vec![String::from("Device ttyUSB0")]);
+        engine.stop().unwrap();
+    }
+
+    /// Reads from a non-blocking port until the expected number of bytes has arrived, or five seconds have elapsed.
+    fn read_port(port: &mut mio_serial::SerialStream, expected_len: usize) -> Vec<u8> {
+        use std::io::Read;
+        let started = Instant::now();
+        let mut received: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 256];
+        while received.len() < expected_len && started.elapsed() < Duration::from_secs(5) {
+            match port.read(&mut buf) {
+                Ok(count) => received.extend_from_slice(&buf[..count]),
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn test_serial_and_pty_linked_both_ways() {
+        use std::io::Write;
+        use mio_serial::{SerialPort, SerialPortBuilderExt};
+        use crate::stream::{pty_stream::PtyStreamConfig, serial_stream::SerialStreamConfig};
+
+        let directory = tempfile::tempdir().unwrap();
+        let link_path = directory.path().join("ttyRELAY").to_str().unwrap().to_string();
+        let (mut device, port) = mio_serial::SerialStream::pair().unwrap();
+        let mut serial = StreamConfig {
+            name: String::from("UART"),
+            type_config: StreamTypeConfig::Serial { config: SerialStreamConfig { port_path: port.name().unwrap(), ..SerialStreamConfig::new() } },
+            ..StreamConfig::default()
+        };
+        let mut pty = StreamConfig {
+            name: String::from("Virtual UART"),
+            type_config: StreamTypeConfig::Pty { config: PtyStreamConfig { link_path: Some(link_path.clone()), ..PtyStreamConfig::new() } },
+            ..StreamConfig::default()
+        };
+        serial.add_output_stream(pty.uuid);
+        pty.add_output_stream(serial.uuid);
+
+        let mut engine = StreamsEngine::new();
+        engine.add_streams(StreamsConfig { stream_configs: vec![serial.clone(), pty.clone()], ..StreamsConfig::new() }).unwrap();
+        engine.initialise().unwrap();
+        engine.start().unwrap();
+        let mut tool = mio_serial::new(&link_path, 115200).open_native_async().unwrap();
+
+        device.write_all(b"boot ok\n").unwrap();
+        assert_eq!(read_port(&mut tool, 9), b"boot ok\r\n");
+        tool.write_all(b"flash\n").unwrap();
+        assert_eq!(read_port(&mut device, 7), b"flash\r\n");
+
+        // Each message crosses once, neither stream sends back what it received.
+        let received = |engine: &StreamsEngine| [&serial, &pty].map(|config| engine.get_statistics(&config.uuid).unwrap().messages_received);
+        let counts = received(&engine);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(received(&engine), counts);
+        assert!(counts.iter().all(|count| *count <= 2), "{counts:?}");
+        engine.stop().unwrap();
+    }
+
+    #[test]
+    fn test_serial_control_needs_serial_stream() {
+        let ring_buffer = ring_buffer_config("Scrollback", 10);
+        let serial = StreamConfig {
+            name: String::from("UART"),
+            type_config: StreamTypeConfig::Serial { config: crate::stream::serial_stream::SerialStreamConfig::new() },
+            ..StreamConfig::default()
+        };
+        let mut engine = StreamsEngine::new();
+        engine.add_streams(StreamsConfig { stream_configs: vec![ring_buffer.clone(), serial.clone()], ..StreamsConfig::new() }).unwrap();
+
+        assert!(engine.send_serial_control(&serial.uuid, SerialControl::PulseDtr(100)).is_ok());
+        assert_eq!(engine.send_serial_control(&ring_buffer.uuid, SerialControl::Break(250)), Err(String::from("'Scrollback' is not a serial stream")));
+        assert!(engine.send_serial_control(&Uuid::new_v4(), SerialControl::Dtr(true)).is_err());
+    }
+
+    #[test]
+    fn test_templates_follow_devices() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("ttyUSB0"), "").unwrap();
+        let template = StreamTemplate::new(format!("{}/ttyUSB*", directory.path().display()), StreamConfig {
+            id: Some(String::from("uart")),
+            name: String::from("UART"),
+            type_config: StreamTypeConfig::Serial { config: crate::stream::serial_stream::SerialStreamConfig::new() },
+            ..StreamConfig::default()
+        });
+        let config = StreamsConfig { templates: vec![template], ..StreamsConfig::new() };
+
+        let mut engine = StreamsEngine::new();
+        engine.add_streams(config.clone()).unwrap();
+        engine.initialise().unwrap();
+        assert!(engine.find_stream("uart-ttyUSB0").is_some());
+        assert_eq!(engine.get_config(), config);
+
+        std::fs::write(directory.path().join("ttyUSB1"), "").unwrap();
+        let changes = engine.refresh_templates().unwrap();
+        assert_eq!(changes.added, vec![String::from("UART ttyUSB1")]);
+        assert!(changes.removed.is_empty() && changes.restarted.is_empty());
+
+        std::fs::remove_file(directory.path().join("ttyUSB0")).unwrap();
+        let changes = engine.refresh_templates().unwrap();
+        assert_eq!(changes.removed, vec![String::from("UART ttyUSB0")]);
+        assert!(engine.find_stream("uart-ttyUSB0").is_none());
+        assert!(engine.refresh_templates().unwrap().is_empty());
+        assert_eq!(engine.get_config(), config);
+    }
 
     #[test]
     fn test_are_all_uuids_unique() {
@@ -1,2 +1,4 @@
+pub mod serial_ports;
 pub mod stream_tools;
+pub mod telnet;
 pub mod waveforms_i2c;
\ No newline at end of file
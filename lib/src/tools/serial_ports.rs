@@ -0,0 +1,165 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::config_manager::stream_uuid_from_id;
+use crate::stream::{StreamConfig, StreamTypeConfig};
+use crate::stream::serial_stream::{SerialStreamConfig, UsbDeviceId};
+
+/// The directory where udev links each serial port under a name that stays the same across replugs.
+const BY_ID_DIRECTORY: &str = "/dev/serial/by-id";
+
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+/// The `SerialPortInfo` struct describes a serial port found on the system.
+///
+/// - `port_path`: The path of the port, e.g. `/dev/ttyUSB0`.
+/// - `by_id_path`: The stable link to the port in `/dev/serial/by-id`, if any.
+/// - `vid`, `pid`, `manufacturer`, `product`, `serial_number`: The USB metadata of USB-serial adapters.
+pub struct SerialPortInfo {
+    pub port_path: String,
+    pub by_id_path: Option<String>,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl SerialPortInfo {
+    /// Returns true if the port is selected by `name`, which is its path, its by-id path or its serial number.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.port_path == name || self.by_id_path.as_deref() == Some(name) || self.serial_number.as_deref() == Some(name)
+    }
+
+    /// Returns the ID of the USB device, if the port is a USB-serial adapter.
+    pub fn usb_device(&self) -> Option<UsbDeviceId> {
+        Some(UsbDeviceId { vid: self.vid?, pid: self.pid?, serial_number: self.serial_number.clone() })
+    }
+
+    /// Creates the configuration of a serial stream for the port.
+    ///
+    /// The stream opens the port by its USB IDs when the adapter has a serial number, otherwise by its by-id path
+    /// or its path. Its `id` is the file name of the port.
+    pub fn stream_config(&self) -> StreamConfig {
+        let mut serial_config = SerialStreamConfig::new();
+        serial_config.port_path = self.by_id_path.clone().unwrap_or(self.port_path.clone());
+        serial_config.usb_device = self.usb_device().filter(|usb_device| usb_device.serial_number.is_some());
+
+        let id = file_name(&self.port_path);
+        StreamConfig {
+            uuid: stream_uuid_from_id(&id),
+            name: self.product.clone().unwrap_or(id.clone()),
+            id: Some(id),
+            type_config: StreamTypeConfig::Serial { config: serial_config },
+            ..StreamConfig::default()
+        }
+    }
+}
+
+/// Lists the serial ports of the system, in the order of their paths.
+pub fn list_serial_ports() -> Result<Vec<SerialPortInfo>, String> {
+    let ports = mio_serial::available_ports().map_err(|e| format!("Failed to list serial ports: {e}"))?;
+    let mut infos: Vec<SerialPortInfo> = ports.into_iter().map(|port| {
+        let mut info = SerialPortInfo {
+            by_id_path: find_link_to(&port.port_name, Path::new(BY_ID_DIRECTORY)),
+            port_path: port.port_name,
+            ..SerialPortInfo::default()
+        };
+        if let mio_serial::SerialPortType::UsbPort(usb) = port.port_type {
+            info.vid = Some(usb.vid);
+            info.pid = Some(usb.pid);
+            info.manufacturer = usb.manufacturer;
+            info.product = usb.product;
+            info.serial_number = usb.serial_number;
+        }
+        info
+    }).collect();
+    infos.sort_by(|a, b| a.port_path.cmp(&b.port_path));
+    Ok(infos)
+}
+
+/// Formats the ports as a table with a header line, a line per port and a column per field.
+pub fn format_table(ports: &[SerialPortInfo]) -> String {
+    let text = |value: &Option<String>| value.clone().unwrap_or(String::from("-"));
+    let mut rows: Vec<[String; 6]> = vec![["PORT", "VID:PID", "MANUFACTURER", "PRODUCT", "SERIAL", "BY-ID"].map(String::from)];
+    for port in ports {
+        let ids = match (port.vid, port.pid) {
+            (Some(vid), Some(pid)) => format!("{vid:04x}:{pid:04x}"),
+            _ => String::from("-"),
+        };
+        rows.push([port.port_path.clone(), ids, text(&port.manufacturer), text(&port.product), text(&port.serial_number), text(&port.by_id_path)]);
+    }
+
+    let mut widths = [0usize; 6];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    rows.iter().map(|row| {
+        let cells: Vec<String> = row.iter().zip(widths.iter()).map(|(cell, width)| format!("{cell:<width$}")).collect();
+        cells.join("  ").trim_end().to_string() + "\n"
+    }).collect()
+}
+
+/// Returns the path of the first link in the directory resolving to the port, if any.
+fn find_link_to(port_path: &str, directory: &Path) -> Option<String> {
+    let port = fs::canonicalize(port_path).ok()?;
+    let mut links: Vec<PathBuf> = fs::read_dir(directory).ok()?.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect();
+    links.sort();
+    links.into_iter().find(|link| fs::canonicalize(link).ok().as_ref() == Some(&port)).map(|link| link.to_string_lossy().to_string())
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftdi_port() -> SerialPortInfo {
+        SerialPortInfo {
+            port_path: String::from("/dev/ttyUSB0"),
+            by_id_path: Some(String::from("/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A1-if00-port0")),
+            vid: Some(0x0403),
+            pid: Some(0x6001),
+            manufacturer: Some(String::from("FTDI")),
+            product: Some(String::from("FT232R USB UART")),
+            serial_number: Some(String::from("A1")),
+        }
+    }
+
+    #[test]
+    fn test_format_table() {
+        let builtin = SerialPortInfo { port_path: String::from("/dev/ttyS0"), ..SerialPortInfo::default() };
+        assert_eq!(format_table(&[ftdi_port(), builtin]), concat!(
+            "PORT          VID:PID    MANUFACTURER  PRODUCT          SERIAL  BY-ID\n",
+            "/dev/ttyUSB0  0403:6001  FTDI          FT232R USB UART  A1      /dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A1-if00-port0\n",
+            "/dev/ttyS0    -          -             -                -       -\n",
+        ));
+    }
+
+    #[test]
+    fn test_stream_config_snippet() {
+        let config = ftdi_port().stream_config();
+        assert_eq!(config.id.as_deref(), Some("ttyUSB0"));
+        assert_eq!(config.name, "FT232R USB UART");
+        let StreamTypeConfig::Serial { config } = config.type_config else { panic!("not a serial stream") };
+        assert_eq!(config.port_path, "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A1-if00-port0");
+        assert_eq!(config.usb_device, Some(UsbDeviceId { vid: 0x0403, pid: 0x6001, serial_number: Some(String::from("A1")) }));
+        assert!(ftdi_port().is_named("A1"));
+    }
+
+    #[test]
+    fn test_find_link_to_port() {
+        let directory = tempfile::tempdir().unwrap();
+        let port = directory.path().join("ttyUSB0");
+        fs::write(&port, "").unwrap();
+        let links = directory.path().join("by-id");
+        fs::create_dir(&links).unwrap();
+        std::os::unix::fs::symlink(&port, links.join("usb-FTDI_A1")).unwrap();
+
+        assert_eq!(find_link_to(port.to_str().unwrap(), &links), Some(links.join("usb-FTDI_A1").to_string_lossy().to_string()));
+        assert_eq!(find_link_to(port.to_str().unwrap(), &directory.path().join("missing")), None);
+    }
+}
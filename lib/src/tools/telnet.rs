@@ -0,0 +1,161 @@
+/// Interpret As Command, introducing every telnet command. A data byte of this value is sent twice.
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+/// Subnegotiation begin and end.
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+
+pub const OPTION_BINARY: u8 = 0;
+pub const OPTION_SUPPRESS_GO_AHEAD: u8 = 3;
+/// The COM-PORT-OPTION of RFC 2217.
+pub const OPTION_COM_PORT: u8 = 44;
+
+#[derive(Clone, Debug, PartialEq)]
+/// The `TelnetEvent` enum is data or a command received from the peer.
+///
+/// - `Data`: Data bytes, with doubled `IAC` bytes unescaped.
+/// - `Negotiation`: An option negotiation, e.g. `WILL` `OPTION_BINARY`.
+/// - `Subnegotiation`: The unescaped bytes between `IAC SB` and `IAC SE`, starting with the option.
+pub enum TelnetEvent {
+    Data(Vec<u8>),
+    Negotiation{verb: u8, option: u8},
+    Subnegotiation(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+enum DecoderState {
+    #[default]
+    Data,
+    Iac,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+#[derive(Clone, Debug, Default)]
+/// The `TelnetDecoder` struct splits the bytes received from a telnet peer into data and commands, keeping their
+/// order. Commands may be split across reads.
+pub struct TelnetDecoder {
+    state: DecoderState,
+    subnegotiation: Vec<u8>,
+}
+
+impl TelnetDecoder {
+    pub fn new() -> Self {
+        TelnetDecoder::default()
+    }
+
+    /// Decodes bytes received from the peer.
+    ///
+    /// # Returns
+    /// The data and the commands received, in the order they were received. Consecutive data bytes are returned
+    /// together.
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<TelnetEvent> {
+        let mut data: Vec<u8> = Vec::new();
+        let mut events: Vec<TelnetEvent> = Vec::new();
+        // The data received so far goes before a command.
+        let mut push_command = |data: &mut Vec<u8>, event: TelnetEvent| {
+            if !data.is_empty() {
+                events.push(TelnetEvent::Data(std::mem::take(data)));
+            }
+            events.push(event);
+        };
+
+        for &byte in bytes {
+            self.state = match (&self.state, byte) {
+                (DecoderState::Data, IAC) => DecoderState::Iac,
+                (DecoderState::Data, _) => {
+                    data.push(byte);
+                    DecoderState::Data
+                },
+                (DecoderState::Iac, IAC) => {
+                    data.push(IAC);
+                    DecoderState::Data
+                },
+                (DecoderState::Iac, WILL | WONT | DO | DONT) => DecoderState::Negotiation(byte),
+                (DecoderState::Iac, SB) => {
+                    self.subnegotiation.clear();
+                    DecoderState::Subnegotiation
+                },
+                // Other commands, such as NOP or Go Ahead, carry no meaning for a byte stream.
+                (DecoderState::Iac, _) => DecoderState::Data,
+                (DecoderState::Negotiation(verb), _) => {
+                    push_command(&mut data, TelnetEvent::Negotiation{verb: *verb, option: byte});
+                    DecoderState::Data
+                },
+                (DecoderState::Subnegotiation, IAC) => DecoderState::SubnegotiationIac,
+                (DecoderState::Subnegotiation, _) => {
+                    self.subnegotiation.push(byte);
+                    DecoderState::Subnegotiation
+                },
+                (DecoderState::SubnegotiationIac, SE) => {
+                    push_command(&mut data, TelnetEvent::Subnegotiation(std::mem::take(&mut self.subnegotiation)));
+                    DecoderState::Data
+                },
+                (DecoderState::SubnegotiationIac, _) => {
+                    self.subnegotiation.push(byte);
+                    DecoderState::Subnegotiation
+                },
+            };
+        }
+
+        if !data.is_empty() {
+            events.push(TelnetEvent::Data(data));
+        }
+        events
+    }
+}
+
+/// Escapes data for a telnet peer by doubling the `IAC` bytes.
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped: Vec<u8> = Vec::with_capacity(data.len());
+    for &byte in data {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
+
+/// Encodes an option negotiation, e.g. `IAC DO OPTION_COM_PORT`.
+pub fn negotiation(verb: u8, option: u8) -> [u8; 3] {
+    [IAC, verb, option]
+}
+
+/// Encodes a subnegotiation of an option, escaping its payload.
+pub fn subnegotiation(option: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![IAC, SB, option];
+    bytes.extend(escape(payload));
+    bytes.extend([IAC, SE]);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_and_commands_split() {
+        let mut decoder = TelnetDecoder::new();
+        let events = decoder.decode(&[b'a', IAC, IAC, b'b', IAC, WILL, OPTION_COM_PORT, b'c', IAC, SB, OPTION_COM_PORT, 5]);
+        assert_eq!(events, vec![
+            TelnetEvent::Data(vec![b'a', IAC, b'b']),
+            TelnetEvent::Negotiation{verb: WILL, option: OPTION_COM_PORT},
+            TelnetEvent::Data(vec![b'c']),
+        ]);
+
+        // The subnegotiation continues in the next read.
+        let events = decoder.decode(&[IAC, IAC, 8, IAC, SE, b'd']);
+        assert_eq!(events, vec![TelnetEvent::Subnegotiation(vec![OPTION_COM_PORT, 5, IAC, 8]), TelnetEvent::Data(vec![b'd'])]);
+    }
+
+    #[test]
+    fn test_encoding_escapes_iac() {
+        assert_eq!(escape(&[1, IAC, 2]), vec![1, IAC, IAC, 2]);
+        assert_eq!(subnegotiation(OPTION_COM_PORT, &[101, IAC]), vec![IAC, SB, OPTION_COM_PORT, 101, IAC, IAC, IAC, SE]);
+    }
+}
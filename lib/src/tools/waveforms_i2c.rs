@@ -3,6 +3,9 @@ pub mod waveforms_i2c{
     use libloading::{Library, Symbol};
     use std::os::raw::{c_char, c_double, c_int, c_uchar};
 
+    /// The Waveforms SDK library loaded at runtime.
+    const WAVEFORMS_LIBRARY: &str = "dwf.dll";
+
     type FDwfDeviceOpenFn = unsafe extern "C" fn(i32, *mut i32) -> i32;
     type FDwfGetLastErrorMsgFn = unsafe extern "C" fn(*mut c_char);
     type FDwfDigitalI2cRateSetFn = unsafe extern "C" fn(i32, c_double) -> i32;
@@ -44,9 +47,14 @@ pub mod waveforms_i2c{
             unsafe { self.lib.get(b"FDwfDeviceCloseAll\0").expect("could not find function FDwfDeviceCloseAll in lib") }
         }
 
+        /// Checks that the Waveforms SDK library can be loaded, without opening a device.
+        pub fn check_library() -> Result<(), String> {
+            unsafe { Library::new(WAVEFORMS_LIBRARY) }.map(|_| ()).map_err(|e| format!("{WAVEFORMS_LIBRARY}: {e}"))
+        }
+
         pub fn new(baud_rate: u32, scl_pin: u8, sda_pin: u8) -> Result<WaveformsI2cControl, String> {
             WaveformsI2cControl::initialise(WaveformsI2cControl {
-                lib: unsafe { Library::new("dwf.dll").map_err(|e| e.to_string()) }?,
+                lib: unsafe { Library::new(WAVEFORMS_LIBRARY).map_err(|e| e.to_string()) }?,
                 hdwf: 0,
             }, baud_rate, scl_pin, sda_pin)
         }
@@ -1,142 +1,255 @@
-use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, thread, time::Duration};
+use std::{fs, path::PathBuf, process, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread, time::{Duration, SystemTime}};
 use ctrlc;
 
 use lib::{
-    stream::{
-        file_stream::FileStreamConfig, serial_stream::{FlowControl, SerialStreamConfig}, terminal_stream::{self, TerminalStream, TerminalStreamConfig}, udp_stream::{UdpDirection, UdpStreamConfig}, waveforms_i2c_stream::WaveformsI2cStreamConfig, StreamConfig, StreamTypeConfig
-    }, 
-    streams_engine::StreamsEngine
+    config_manager,
+    config_validator,
+    script::{Script, ScriptRunner},
+    streams_engine::StreamsEngine,
+    tools::serial_ports
 };
 
+const USAGE: &str = "Usage: service --config <file> [--check] [--save-config <file>] [--script <file> [--junit <file>]]\n       service --convert <input file> <output file>\n       service --schema <file>\n       service --list-ports [--json] [--stream-config <port>]";
 
-    fn create_streams_and_configure_engine(engine: &mut StreamsEngine) -> Result<(), String> {
-        let mut dummy_stream_config_gen: TerminalStreamConfig = TerminalStreamConfig::new();
-    dummy_stream_config_gen.generates_messages = true;
-    dummy_stream_config_gen.inter_message_generation_period_ms = 10;
-    dummy_stream_config_gen.print_to_standard_out = false;
-
-    let mut generator_stream_config: StreamConfig = StreamConfig::default();
-    generator_stream_config.name = String::from("Generator Stream");
-    generator_stream_config.type_config = StreamTypeConfig::Terminal { config:dummy_stream_config_gen };
-
-    // let dummy_stream_config_con: TerminalStreamConfig = TerminalStreamConfig::new();
-    // let mut consumer_stream_config_a: StreamConfig = StreamConfig::default();
-    // consumer_stream_config_a.name = String::from("Consumer Stream A");
-    // consumer_stream_config_a.direction = Direction::Input;
-    // consumer_stream_config_a.type_config = StreamTypeConfig::Terminal { config:dummy_stream_config_con };
-    // consumer_stream_config_a.input_filter = String::from("tick");
-
-    // let mut dummy_stream_config_con: TerminalStreamConfig = TerminalStreamConfig::new();
-    // dummy_stream_config_con.print_to_standard_out = false;
-    // let mut consumer_stream_config_b: StreamConfig = StreamConfig::default();
-    // consumer_stream_config_b.name = String::from("Consumer Stream B");
-    // consumer_stream_config_b.direction = Direction::Input;
-    // consumer_stream_config_b.type_config = StreamTypeConfig::Terminal { config:dummy_stream_config_con };
-    // consumer_stream_config_b.input_filter = String::from("tock");
-
-    // generator_stream_config.output_streams.push(consumer_stream_config_a.uuid.clone());
-    // generator_stream_config.output_streams.push(consumer_stream_config_b.uuid.clone());
-
-    // let mut serial_stream_1_config: SerialStreamConfig = SerialStreamConfig::new();
-    // serial_stream_1_config.baud_rate = 115200;
-    // serial_stream_1_config.port_path = String::from("COM4");
-    // serial_stream_1_config.start_bits = 1;
-    // serial_stream_1_config.stop_bits = 1;
-    // serial_stream_1_config.flow_control = FlowControl::None();
-
-    // let mut source_stream_1_config: StreamConfig = StreamConfig::default();
-    // source_stream_1_config.name = String::from("Serial Stream COM4");
-    // source_stream_1_config.type_config = StreamTypeConfig::Serial { config:serial_stream_1_config };
-
-    // let mut waveforms_i2c_config: WaveformsI2cStreamConfig = WaveformsI2cStreamConfig::new();
-    // waveforms_i2c_config.baud_rate = 4000000;
-    // waveforms_i2c_config.scl_pin = 0;
-    // waveforms_i2c_config.sda_pin = 1;
-
-    // let mut waveforms_source_stream_config: StreamConfig = StreamConfig::default();
-    // waveforms_source_stream_config.name = String::from("Waveforms I2C");
-    // waveforms_source_stream_config.type_config = StreamTypeConfig::WaveformsI2c { config:waveforms_i2c_config };
-
-    // let mut serial_stream_2_config: SerialStreamConfig = SerialStreamConfig::new();
-    // serial_stream_2_config.baud_rate = 115200;
-    // serial_stream_2_config.port_path = String::from("COM6");
-    // serial_stream_2_config.start_bits = 1;
-    // serial_stream_2_config.stop_bits = 1;
-    // serial_stream_2_config.flow_control = FlowControl::None();
-
-    // let mut source_stream_2_config: StreamConfig = StreamConfig::default();
-    // source_stream_2_config.name = String::from("Serial Stream COM6");
-    // source_stream_2_config.direction = Direction::BiDirectional;
-    // source_stream_2_config.type_config = StreamTypeConfig::Serial { config:serial_stream_2_config };
-    // //source_stream_2_config.add_output_stream(output_dummy_stream_config.uuid.clone());
-
-    // let dummy_stream_config_con: TerminalStreamConfig = TerminalStreamConfig::new();
-    // let mut output_dummy_stream_config: StreamConfig = StreamConfig::default();
-    // output_dummy_stream_config.name = String::from("Terminal Stream A");
-    // output_dummy_stream_config.type_config = StreamTypeConfig::Terminal { config:dummy_stream_config_con };
-
-    // let file_stream_config: FileStreamConfig = FileStreamConfig::new(String::from("simple_text.txt"));
-    // let mut output_file_stream_config: StreamConfig = StreamConfig::default();
-    // output_file_stream_config.name = String::from("File Writer A");
-    // output_file_stream_config.type_config = StreamTypeConfig::File { config:file_stream_config };
-
-    // // UDP stream for sending data to external ULogViewer
-    // let mut udp_stream_config: UdpStreamConfig = UdpStreamConfig::new();
-    // udp_stream_config.output_ip_address = String::from("localhost");
-    // udp_stream_config.output_port = 65000;
-    // udp_stream_config.output_enabled = true;
-    // let mut output_udp_stream_config: StreamConfig = StreamConfig::default();
-    // output_udp_stream_config.name = String::from("UDP Writer A");
-    // output_udp_stream_config.type_config = StreamTypeConfig::Udp { config: udp_stream_config };
-
-    // // Connect up the streams
-    // generator_stream_config.add_output_stream(output_dummy_stream_config.uuid.clone());
-    // //waveforms_source_stream_config.add_output_stream(output_dummy_stream_config.uuid.clone());
-    // output_dummy_stream_config.add_output_stream(output_file_stream_config.uuid.clone());
-    // output_dummy_stream_config.add_output_stream(output_udp_stream_config.uuid.clone());
-
-    let mut terminal_stream_config: TerminalStreamConfig = TerminalStreamConfig::new();
-    terminal_stream_config.print_to_standard_out = false;
-    terminal_stream_config.generates_messages = true;
-    terminal_stream_config.inter_message_generation_period_ms = 1000;
-    let mut output_dummy_stream: StreamConfig = StreamConfig::default();
-    output_dummy_stream.name = String::from("Terminal Stream A - Generates Messages");
-    output_dummy_stream.type_config = StreamTypeConfig::Terminal { config:terminal_stream_config };
-
-    let mut output_udp_stream_config: UdpStreamConfig = UdpStreamConfig::new();
-    output_udp_stream_config.direction = UdpDirection::UdpOutput;
-    output_udp_stream_config.output_ip_address = String::from("127.0.0.1");
-    output_udp_stream_config.output_port = 65001;
-    let mut output_udp_stream: StreamConfig = StreamConfig::default();
-    output_udp_stream.name = String::from("UDP Output Stream");
-    output_udp_stream.type_config = StreamTypeConfig::Udp { config: output_udp_stream_config };
-    output_dummy_stream.add_output_stream(output_udp_stream.uuid.clone());
-
-    let mut input_udp_stream_config: UdpStreamConfig = UdpStreamConfig::new();
-    input_udp_stream_config.direction = UdpDirection::UdpInput;
-    input_udp_stream_config.input_port = 65001;
-    let mut input_udp_stream: StreamConfig = StreamConfig::default();
-    input_udp_stream.name = String::from("UDP Input Stream");
-    input_udp_stream.type_config = StreamTypeConfig::Udp { config: input_udp_stream_config };
-    
-    let mut terminal_stream_config: TerminalStreamConfig = TerminalStreamConfig::new();
-    terminal_stream_config.print_to_standard_out = true;
-    let mut printing_terminal_stream: StreamConfig = StreamConfig::default();
-    printing_terminal_stream.name = String::from("Terminal Stream B - Prints Messages");
-    printing_terminal_stream.type_config = StreamTypeConfig::Terminal { config:terminal_stream_config };
-    input_udp_stream.add_output_stream(printing_terminal_stream.uuid.clone());
-
-    engine.add_stream(output_dummy_stream)?;
-    engine.add_stream(output_udp_stream)?;
-    engine.add_stream(input_udp_stream)?;
-    engine.add_stream(printing_terminal_stream)?;
-    
-    Ok(())
+/// The command line options of the service.
+///
+/// - `--config <file>`: The `StreamsConfig` file describing the streams and how they are linked, in JSON, TOML or
+///   YAML format depending on its extension. The file is reloaded when it, or a file it includes, changes, or on
+///   SIGHUP.
+/// - `--check`: Checks the configuration without starting the streams, reports every problem found, then exits with a
+///   non-zero code if there were any.
+/// - `--save-config <file>`: Saves the configuration of the running engine, in the format of the file's extension.
+/// - `--convert <input file> <output file>`: Converts a configuration file to the format of the output file, then exits.
+/// - `--schema <file>`: Writes the JSON Schema of configuration files, then exits.
+/// - `--list-ports`: Lists the serial ports with their USB metadata as a table, then exits.
+/// - `--json`: Lists the serial ports as JSON instead.
+/// - `--stream-config <port>`: Prints the configuration of a serial stream for the port, given by its path, by-id path
+///   or serial number, then exits.
+/// - `--script <file>`: Runs the script against the streams, then exits with a non-zero code if a step failed.
+/// - `--junit <file>`: Writes the results of the script as a JUnit XML report.
+struct Options {
+    config_path: String,
+    check: bool,
+    save_config_path: Option<String>,
+    convert_paths: Option<(String, String)>,
+    schema_path: Option<String>,
+    list_ports: bool,
+    ports_json: bool,
+    stream_config_port: Option<String>,
+    script_path: Option<String>,
+    junit_path: Option<String>,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut config_path: Option<String> = None;
+    let mut options = Options { config_path: String::new(), check: false, save_config_path: None, convert_paths: None, schema_path: None, list_ports: false, ports_json: false, stream_config_port: None, script_path: None, junit_path: None };
+    let mut args = args.skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config_path = Some(args.next().ok_or("--config requires a file")?),
+            "--check" => options.check = true,
+            "--save-config" => options.save_config_path = Some(args.next().ok_or("--save-config requires a file")?),
+            "--convert" => {
+                let input_path = args.next().ok_or("--convert requires an input and an output file")?;
+                let output_path = args.next().ok_or("--convert requires an input and an output file")?;
+                options.convert_paths = Some((input_path, output_path));
+            },
+            "--schema" => options.schema_path = Some(args.next().ok_or("--schema requires a file")?),
+            "--list-ports" => options.list_ports = true,
+            "--json" => options.ports_json = true,
+            "--stream-config" => options.stream_config_port = Some(args.next().ok_or("--stream-config requires a port")?),
+            "--script" => options.script_path = Some(args.next().ok_or("--script requires a file")?),
+            "--junit" => options.junit_path = Some(args.next().ok_or("--junit requires a file")?),
+            _ => return Err(format!("Unknown argument: {arg}")),
+        }
+    }
+
+    if options.junit_path.is_some() && options.script_path.is_none() {
+        return Err(String::from("--junit requires --script"));
+    }
+    if (options.ports_json || options.stream_config_port.is_some()) && !options.list_ports {
+        return Err(String::from("--json and --stream-config require --list-ports"));
+    }
+    if options.convert_paths.is_some() || options.schema_path.is_some() || options.list_ports {
+        return Ok(options);
+    }
+    options.config_path = config_path.ok_or("--config is required")?;
+    Ok(options)
+}
+
+/// Creates the streams described by the configuration file, returning the files the configuration was read from.
+fn create_streams_and_configure_engine(engine: &mut StreamsEngine, config_path: &str) -> Result<Vec<PathBuf>, String> {
+    let loaded = config_manager::load_config(config_path.to_string()).map_err(|e| format!("{config_path}: {e}"))?;
+    print_warnings(&loaded.warnings);
+    engine.add_streams(loaded.config)?;
+    Ok(loaded.files)
+}
+
+/// Checks the configuration file without starting the streams, returning the exit code of the service.
+fn check_config(config_path: &str) -> i32 {
+    let loaded = match config_manager::load_config(config_path.to_string()) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("{config_path}: {e}");
+            return 1;
+        }
+    };
+
+    // Migration warnings are reported with the problems, but only the problems make the check fail.
+    print_warnings(&loaded.warnings);
+    let problems = config_validator::validate_config(&loaded.config);
+    for problem in problems.iter() {
+        println!("{problem}");
+    }
+    if problems.is_empty() {
+        println!("{config_path}: configuration is valid");
+        0
+    } else {
+        println!("{config_path}: {} problem(s) found", problems.len());
+        1
+    }
+}
+
+/// Reloads the configuration file and applies it to the running engine. If the file is invalid, the running
+/// configuration is kept.
+///
+/// # Returns
+/// The files the configuration was read from, if it could be read.
+fn reload_config(engine: &mut StreamsEngine, config_path: &str) -> Option<Vec<PathBuf>> {
+    let loaded = match config_manager::load_config(config_path.to_string()) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("Failed to reload configuration, keeping the running configuration: {config_path}: {e}");
+            return None;
+        }
+    };
+    print_warnings(&loaded.warnings);
+    match engine.apply_config(loaded.config) {
+        Ok(changes) => println!("Configuration reloaded: {}", changes),
+        Err(e) => println!("Failed to reload configuration, keeping the running configuration: {}", e),
+    }
+    Some(loaded.files)
+}
+
+/// Prints the warnings of the migration of configuration files of earlier versions.
+fn print_warnings(warnings: &[String]) {
+    for warning in warnings {
+        println!("warning: {warning}");
     }
+}
+
+/// Returns the times the files were last modified, if available.
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok()).collect()
+}
+
+/// Lists the serial ports, or prints the configuration of a serial stream for one of them.
+fn list_ports(json: bool, stream_config_port: Option<&str>) -> Result<(), String> {
+    let ports = serial_ports::list_serial_ports()?;
+
+    if let Some(name) = stream_config_port {
+        let port = ports.iter().find(|port| port.is_named(name)).ok_or(format!("Serial port {name} not found"))?;
+        println!("{}", serde_json::to_string_pretty(&port.stream_config()).map_err(|e| e.to_string())?);
+    } else if json {
+        println!("{}", serde_json::to_string_pretty(&ports).map_err(|e| e.to_string())?);
+    } else if ports.is_empty() {
+        println!("No serial ports found");
+    } else {
+        print!("{}", serial_ports::format_table(&ports));
+    }
+    Ok(())
+}
 
+/// Runs a script against the streams of the engine, returning the exit code of the service.
+fn run_script(engine: &mut StreamsEngine, script_path: &str, junit_path: Option<&str>) -> Result<i32, String> {
+    let script: Script = Script::load(script_path)?;
+    engine.initialise()?;
+    let mut runner: ScriptRunner = ScriptRunner::new(script, engine)?;
+    engine.start()?;
 
+    let report = runner.run();
+
+    if let Err(e) = engine.stop() {
+        println!("Engine failed to stop: {}", e);
+    }
+    if let Some(junit_path) = junit_path {
+        report.write_junit(junit_path)?;
+    }
+
+    if report.passed() {
+        println!("Script '{}' passed", report.name);
+        Ok(0)
+    } else {
+        println!("Script '{}' failed", report.name);
+        Ok(1)
+    }
+}
 
 fn main() {
+    let options = match parse_args(std::env::args()) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("Error: {}", e);
+            println!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Some((input_path, output_path)) = &options.convert_paths {
+        match config_manager::convert_config(input_path.clone(), output_path.clone()) {
+            Ok(warnings) => {
+                print_warnings(&warnings);
+                println!("Converted {} to {}", input_path, output_path);
+                process::exit(0);
+            },
+            Err(e) => {
+                println!("Error: {}", e);
+                process::exit(2);
+            }
+        }
+    }
+
+    if let Some(schema_path) = &options.schema_path {
+        match fs::write(schema_path, config_manager::config_schema()) {
+            Ok(_) => {
+                println!("Schema written to {}", schema_path);
+                process::exit(0);
+            },
+            Err(e) => {
+                println!("Error: {}: {}", schema_path, e);
+                process::exit(2);
+            }
+        }
+    }
+
+    if options.list_ports {
+        match list_ports(options.ports_json, options.stream_config_port.as_deref()) {
+            Ok(_) => process::exit(0),
+            Err(e) => {
+                println!("Error: {}", e);
+                process::exit(2);
+            }
+        }
+    }
+
+    if options.check {
+        process::exit(check_config(&options.config_path));
+    }
+
+    if let Some(script_path) = &options.script_path {
+        let mut engine: StreamsEngine = StreamsEngine::new();
+        let result = create_streams_and_configure_engine(&mut engine, &options.config_path)
+            .and_then(|_| run_script(&mut engine, script_path, options.junit_path.as_deref()));
+        match result {
+            Ok(exit_code) => process::exit(exit_code),
+            Err(e) => {
+                println!("Error: {}", e);
+                process::exit(2);
+            }
+        }
+    }
+
     let running = Arc::new(AtomicBool::new(true));
     let running_clone = Arc::clone(&running);
     let mut engine: StreamsEngine = StreamsEngine::new();
@@ -147,8 +260,15 @@ fn main() {
     })
     .expect("Error setting Ctrl-C handler");
 
-    match create_streams_and_configure_engine(&mut engine){
-        Ok(_) => {
+    let reload_requested = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload_requested))
+        .expect("Error setting SIGHUP handler");
+
+    match create_streams_and_configure_engine(&mut engine, &options.config_path){
+        Ok(files) => {
+            let mut config_files: Vec<PathBuf> = files;
+            let mut config_modified = modified_times(&config_files);
             match engine.initialise(){
                 Ok(_) => {
                     println!("Engine successfully initialised");
@@ -156,9 +276,33 @@ fn main() {
                     match engine.start() {
                         Ok(_) => {
                             println!("Engine successfully started");
+
+                            if let Some(save_config_path) = &options.save_config_path {
+                                match config_manager::save_config(&engine.get_config(), save_config_path.clone()) {
+                                    Ok(_) => println!("Configuration saved to {}", save_config_path),
+                                    Err(e) => println!("Failed to save configuration: {}", e),
+                                }
+                            }
                             
                             while running.load(Ordering::SeqCst) {
                                 thread::sleep(Duration::from_secs(1)); // Simulate work
+
+                                let modified = modified_times(&config_files);
+                                if reload_requested.swap(false, Ordering::SeqCst) || modified != config_modified {
+                                    config_modified = modified;
+                                    // The files included may have changed too.
+                                    if let Some(files) = reload_config(&mut engine, &options.config_path).filter(|files| *files != config_files) {
+                                        config_files = files;
+                                        config_modified = modified_times(&config_files);
+                                    }
+                                }
+
+                                // Follow the devices of the stream templates.
+                                match engine.refresh_templates() {
+                                    Ok(changes) if !changes.is_empty() => println!("Devices changed: {}", changes),
+                                    Ok(_) => {},
+                                    Err(e) => println!("Failed to follow devices: {}", e),
+                                }
                             }
         
                             match engine.stop() {